@@ -0,0 +1,313 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::parse::{
+    build_transport, normalize_v2ray_plugin_opts, parse_mbps, quic_tls_from_params,
+    tls_from_params, udp_over_tcp_value, validate_ss_method,
+};
+use crate::{err, AppError};
+
+fn field(proxy: &Value, key: &str) -> Option<String> {
+    match proxy.get(key)? {
+        Value::String(value) => Some(value.trim().to_string()).filter(|value| !value.is_empty()),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+fn flag(proxy: &Value, key: &str) -> bool {
+    match proxy.get(key) {
+        Some(Value::Bool(value)) => *value,
+        Some(Value::String(value)) => value == "1" || value.eq_ignore_ascii_case("true"),
+        _ => false,
+    }
+}
+
+fn list(proxy: &Value, key: &str) -> Option<String> {
+    match proxy.get(key)? {
+        Value::Array(items) => {
+            let items: Vec<&str> = items.iter().filter_map(Value::as_str).collect();
+            (!items.is_empty()).then(|| items.join(","))
+        }
+        _ => field(proxy, key),
+    }
+}
+
+fn mbps(proxy: &Value, key: &str) -> Option<u32> {
+    parse_mbps(&field(proxy, key)?)
+}
+
+fn transport_params(proxy: &Value, params: &mut HashMap<String, String>) -> String {
+    let network = field(proxy, "network").unwrap_or_else(|| "tcp".to_string());
+    match network.as_str() {
+        "ws" => {
+            let opts = proxy.get("ws-opts").cloned().unwrap_or(Value::Null);
+            if let Some(path) = field(&opts, "path") {
+                params.insert("path".to_string(), path);
+            }
+            if let Some(host) = opts
+                .get("headers")
+                .and_then(|headers| field(headers, "Host"))
+            {
+                params.insert("host".to_string(), host);
+            }
+            if let Some(early_data) = field(&opts, "max-early-data") {
+                params.insert("ed".to_string(), early_data);
+            }
+            if let Some(header) = field(&opts, "early-data-header-name") {
+                params.insert("eh".to_string(), header);
+            }
+        }
+        "h2" => {
+            let opts = proxy.get("h2-opts").cloned().unwrap_or(Value::Null);
+            if let Some(host) = list(&opts, "host") {
+                params.insert("host".to_string(), host);
+            }
+            if let Some(path) = field(&opts, "path") {
+                params.insert("path".to_string(), path);
+            }
+        }
+        "http" => {
+            let opts = proxy.get("http-opts").cloned().unwrap_or(Value::Null);
+            if let Some(path) =
+                list(&opts, "path").and_then(|paths| paths.split(',').next().map(str::to_string))
+            {
+                params.insert("path".to_string(), path);
+            }
+            if let Some(host) = opts
+                .get("headers")
+                .and_then(|headers| list(headers, "Host"))
+            {
+                params.insert("host".to_string(), host);
+            }
+        }
+        "grpc" => {
+            let opts = proxy.get("grpc-opts").cloned().unwrap_or(Value::Null);
+            if let Some(service) = field(&opts, "grpc-service-name") {
+                params.insert("service_name".to_string(), service);
+            }
+        }
+        _ => {}
+    }
+    network
+}
+
+fn tls_params(proxy: &Value, params: &mut HashMap<String, String>, force_tls: bool) {
+    let reality = proxy.get("reality-opts");
+    let security = if reality.is_some() {
+        "reality"
+    } else if force_tls || flag(proxy, "tls") {
+        "tls"
+    } else {
+        return;
+    };
+    params.insert("security".to_string(), security.to_string());
+    if let Some(sni) = field(proxy, "servername").or_else(|| field(proxy, "sni")) {
+        params.insert("sni".to_string(), sni);
+    }
+    if flag(proxy, "skip-cert-verify") {
+        params.insert("insecure".to_string(), "1".to_string());
+    }
+    if let Some(alpn) = list(proxy, "alpn") {
+        params.insert("alpn".to_string(), alpn);
+    }
+    if let Some(fp) = field(proxy, "client-fingerprint") {
+        params.insert("fp".to_string(), fp);
+    }
+    if let Some(reality) = reality {
+        if let Some(pbk) = field(reality, "public-key") {
+            params.insert("pbk".to_string(), pbk);
+        }
+        if let Some(sid) = field(reality, "short-id") {
+            params.insert("sid".to_string(), sid);
+        }
+    }
+}
+
+fn apply_stream(outbound: &mut Value, proxy: &Value, server: &str, force_tls: bool) {
+    let mut params = HashMap::new();
+    let network = transport_params(proxy, &mut params);
+    if let Some(transport) = build_transport(&params, &network) {
+        outbound["transport"] = transport;
+    }
+    tls_params(proxy, &mut params, force_tls);
+    if let Some(tls) = tls_from_params(&params, Some(server.to_string())) {
+        outbound["tls"] = tls;
+    }
+}
+
+fn ss_plugin(outbound: &mut Value, proxy: &Value) -> Result<(), AppError> {
+    let Some(plugin) = field(proxy, "plugin") else {
+        return Ok(());
+    };
+    let opts = proxy.get("plugin-opts").cloned().unwrap_or(Value::Null);
+    let mut parts = Vec::new();
+    let name = match plugin.as_str() {
+        "obfs" => {
+            if let Some(mode) = field(&opts, "mode") {
+                parts.push(format!("obfs={mode}"));
+            }
+            if let Some(host) = field(&opts, "host") {
+                parts.push(format!("obfs-host={host}"));
+            }
+            "obfs-local".to_string()
+        }
+        "v2ray-plugin" => {
+            if let Some(mode) = field(&opts, "mode") {
+                normalize_v2ray_plugin_opts(&format!("mode={mode}"))?;
+                parts.push(format!("mode={mode}"));
+            }
+            if let Some(host) = field(&opts, "host") {
+                parts.push(format!("host={host}"));
+            }
+            if let Some(path) = field(&opts, "path") {
+                parts.push(format!("path={path}"));
+            }
+            if flag(&opts, "tls") {
+                parts.push("tls".to_string());
+            }
+            plugin
+        }
+        _ => plugin,
+    };
+    outbound["plugin"] = json!(name);
+    if !parts.is_empty() {
+        outbound["plugin_opts"] = json!(parts.join(";"));
+    }
+    Ok(())
+}
+
+fn required(proxy: &Value, key: &str) -> Result<String, AppError> {
+    field(proxy, key).ok_or_else(|| err("IMPORT_INVALID", format!("missing {key}")))
+}
+
+fn proxy_to_outbound(proxy: &Value) -> Result<Value, AppError> {
+    let kind = required(proxy, "type")?.to_lowercase();
+    let server = required(proxy, "server")?;
+    let port = required(proxy, "port")?
+        .parse::<u16>()
+        .map_err(|_| err("IMPORT_INVALID", "invalid port"))?;
+    let tag = field(proxy, "name").unwrap_or_else(|| format!("{kind}-{server}:{port}"));
+
+    let outbound = match kind.as_str() {
+        "ss" => {
+            let method = required(proxy, "cipher")?;
+            let password = required(proxy, "password")?;
+            validate_ss_method(&method, &password)?;
+            let mut outbound = json!({
+                "type": "shadowsocks",
+                "tag": tag,
+                "server": server,
+                "server_port": port,
+                "method": method,
+                "password": password
+            });
+            ss_plugin(&mut outbound, proxy)?;
+            if flag(proxy, "udp-over-tcp") {
+                let version = field(proxy, "udp-over-tcp-version").unwrap_or_default();
+                outbound["udp_over_tcp"] = udp_over_tcp_value(&version).unwrap_or(json!(true));
+            }
+            outbound
+        }
+        "vmess" => {
+            let mut outbound = json!({
+                "type": "vmess",
+                "tag": tag,
+                "server": server,
+                "server_port": port,
+                "uuid": required(proxy, "uuid")?,
+                "security": field(proxy, "cipher").unwrap_or_else(|| "auto".to_string())
+            });
+            if let Some(alter_id) =
+                field(proxy, "alterId").and_then(|value| value.parse::<u32>().ok())
+            {
+                outbound["alter_id"] = json!(alter_id);
+            }
+            apply_stream(&mut outbound, proxy, &server, false);
+            outbound
+        }
+        "vless" => {
+            let mut outbound = json!({
+                "type": "vless",
+                "tag": tag,
+                "server": server,
+                "server_port": port,
+                "uuid": required(proxy, "uuid")?
+            });
+            if let Some(flow) = field(proxy, "flow") {
+                outbound["flow"] = json!(flow);
+            }
+            apply_stream(&mut outbound, proxy, &server, false);
+            outbound
+        }
+        "trojan" => {
+            let mut outbound = json!({
+                "type": "trojan",
+                "tag": tag,
+                "server": server,
+                "server_port": port,
+                "password": required(proxy, "password")?
+            });
+            apply_stream(&mut outbound, proxy, &server, true);
+            outbound
+        }
+        "hysteria2" => {
+            let mut outbound = json!({
+                "type": "hysteria2",
+                "tag": tag,
+                "server": server,
+                "server_port": port,
+                "password": required(proxy, "password")?
+            });
+            if let Some(obfs) = field(proxy, "obfs").filter(|obfs| obfs == "salamander") {
+                let mut obfs_obj = json!({ "type": obfs });
+                if let Some(password) = field(proxy, "obfs-password") {
+                    obfs_obj["password"] = json!(password);
+                }
+                outbound["obfs"] = obfs_obj;
+            }
+            if let Some(up) = mbps(proxy, "up") {
+                outbound["up_mbps"] = json!(up);
+            }
+            if let Some(down) = mbps(proxy, "down") {
+                outbound["down_mbps"] = json!(down);
+            }
+            let mut params = HashMap::new();
+            tls_params(proxy, &mut params, true);
+            if let Some(tls) = quic_tls_from_params(&params, Some(server.clone())) {
+                outbound["tls"] = tls;
+            }
+            outbound
+        }
+        other => {
+            return Err(err(
+                "IMPORT_UNSUPPORTED",
+                format!("unsupported proxy type: {other}"),
+            ))
+        }
+    };
+    Ok(outbound)
+}
+
+pub(crate) fn parse_proxies(payload: &str) -> Result<(Vec<Value>, Vec<String>), AppError> {
+    let document: Value =
+        serde_yaml::from_str(payload).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
+    let proxies = document
+        .get("proxies")
+        .and_then(Value::as_array)
+        .ok_or_else(|| err("IMPORT_INVALID", "missing proxies list"))?;
+
+    let mut outbounds = Vec::new();
+    let mut errors = Vec::new();
+    for proxy in proxies {
+        match proxy_to_outbound(proxy) {
+            Ok(outbound) => outbounds.push(outbound),
+            Err(error) => {
+                let name = field(proxy, "name").unwrap_or_else(|| "proxy".to_string());
+                errors.push(format!("{name}: {error}"));
+            }
+        }
+    }
+    Ok((outbounds, errors))
+}
@@ -0,0 +1,72 @@
+use serde_json::json;
+
+use crate::clash::parse_proxies;
+
+const CONFIG: &str = r#"
+proxies:
+  - name: "SS Tokyo"
+    type: ss
+    server: 203.0.113.1
+    port: 8388
+    cipher: aes-256-gcm
+    password: secret
+    udp-over-tcp: true
+  - name: Trojan WS
+    type: trojan
+    server: trojan.example.com
+    port: 443
+    password: hunter2
+    sni: cdn.example.com
+    skip-cert-verify: true
+    network: ws
+    ws-opts:
+      path: /ws
+      headers:
+        Host: cdn.example.com
+  - name: Broken
+    type: ss
+    server: 203.0.113.2
+    port: 8388
+    cipher: rc4-md5
+    password: secret
+"#;
+
+#[test]
+fn maps_shadowsocks_and_trojan_proxies() {
+    let (outbounds, errors) = parse_proxies(CONFIG).unwrap();
+    assert_eq!(outbounds.len(), 2);
+    assert_eq!(
+        outbounds[0],
+        json!({
+            "type": "shadowsocks",
+            "tag": "SS Tokyo",
+            "server": "203.0.113.1",
+            "server_port": 8388,
+            "method": "aes-256-gcm",
+            "password": "secret",
+            "udp_over_tcp": true
+        })
+    );
+
+    let trojan = &outbounds[1];
+    assert_eq!(trojan["type"], "trojan");
+    assert_eq!(trojan["tag"], "Trojan WS");
+    assert_eq!(trojan["server"], "trojan.example.com");
+    assert_eq!(trojan["server_port"], 443);
+    assert_eq!(trojan["password"], "hunter2");
+    assert_eq!(trojan["tls"]["enabled"], true);
+    assert_eq!(trojan["tls"]["server_name"], "cdn.example.com");
+    assert_eq!(trojan["tls"]["insecure"], true);
+    assert_eq!(trojan["transport"]["type"], "ws");
+    assert_eq!(trojan["transport"]["path"], "/ws");
+    assert_eq!(trojan["transport"]["headers"]["Host"], "cdn.example.com");
+
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("Broken: "), "{}", errors[0]);
+}
+
+#[test]
+fn missing_proxies_list_is_invalid() {
+    let error = parse_proxies("rules: []").unwrap_err();
+    assert_eq!(error.code, "IMPORT_INVALID");
+}
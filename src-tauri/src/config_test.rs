@@ -0,0 +1,1362 @@
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::test_util::scratch_dir;
+use crate::{
+    autostart_args, compose_config, default_domain_resolver, dns_server_needs_resolving,
+    geoip_ru_rule_set, merge_template, normalize_app_rules, normalize_rules, parse_singbox_version,
+    supports_default_domain_resolver, tunnel_info, validate_modes, validate_settings, write_config,
+    AppError, AppRule, AppRuleMode, AppState, ClashApi, ComposedConfig, ConfigGeneratedPayload,
+    ConfigOptions, DnsServer, FinalOutbound, ProxyMode, ProxySettings, RulePreset, SelectorChange,
+    CONFIG_FILE, DEFAULT_RULE_PRESET, GEOIP_RU_TAG, LOCAL_PROXY_TAG, MAX_AUTOSTART_DELAY_SECS,
+    MAX_TEST_TIMEOUT_MS, MIN_TEST_TIMEOUT_MS, NODE_DNS_SERVER_FIELD,
+};
+
+fn profile() -> Value {
+    json!({
+        "outbounds": [
+            { "type": "vless", "tag": "nl", "server": "nl.example.com", "server_port": 443 },
+            { "type": "trojan", "tag": "de", "server": "de.example.com", "server_port": 443 }
+        ]
+    })
+}
+
+fn rule(path: &str, mode: AppRuleMode) -> AppRule {
+    AppRule {
+        path: path.to_string(),
+        mode,
+        name: None,
+    }
+}
+
+fn try_compose(
+    profile: Value,
+    mode: ProxyMode,
+    rules: Vec<AppRule>,
+    settings: &ProxySettings,
+    active_tag: Option<&str>,
+    singbox_version: Option<&str>,
+) -> Result<ComposedConfig, AppError> {
+    let log_path = PathBuf::from("singbox.log");
+    compose_config(
+        profile,
+        mode,
+        rules,
+        ConfigOptions {
+            settings,
+            force_ipv4_ru: false,
+            active_tag: active_tag.map(str::to_string),
+            log_path: &log_path,
+            geoip_ru_rule_set: json!({ "tag": GEOIP_RU_TAG }),
+            clash_api_secret: "secret",
+            clash_api_controller: "127.0.0.1:9090",
+            singbox_version,
+        },
+    )
+}
+
+fn compose(
+    profile: Value,
+    mode: ProxyMode,
+    rules: Vec<AppRule>,
+    settings: &ProxySettings,
+    active_tag: Option<&str>,
+    singbox_version: Option<&str>,
+) -> ComposedConfig {
+    try_compose(profile, mode, rules, settings, active_tag, singbox_version)
+        .expect("compose_config")
+}
+
+fn route_rules(config: &Value) -> Vec<Value> {
+    config["route"]["rules"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn outbound<'a>(config: &'a Value, tag: &str) -> &'a Value {
+    config["outbounds"]
+        .as_array()
+        .and_then(|items| items.iter().find(|item| item["tag"] == tag))
+        .unwrap_or_else(|| panic!("no outbound {tag}"))
+}
+
+fn inbound<'a>(config: &'a Value, tag: &str) -> &'a Value {
+    config["inbounds"]
+        .as_array()
+        .and_then(|items| items.iter().find(|item| item["tag"] == tag))
+        .unwrap_or_else(|| panic!("no inbound {tag}"))
+}
+
+#[test]
+fn off_mode_builds_selector_but_no_route() {
+    let settings = ProxySettings::default();
+    let composed = compose(profile(), ProxyMode::Off, Vec::new(), &settings, None, None);
+    assert_eq!(
+        composed.selector_change,
+        SelectorChange::SelectorSynthesized
+    );
+    assert!(composed.config.get("route").is_none());
+    assert_eq!(
+        outbound(&composed.config, "proxy"),
+        &json!({ "type": "selector", "tag": "proxy", "outbounds": ["nl", "de"], "default": "nl" })
+    );
+    assert_eq!(outbound(&composed.config, "direct")["type"], "direct");
+    assert_eq!(
+        composed.config["experimental"]["clash_api"],
+        json!({ "external_controller": "127.0.0.1:9090", "secret": "secret" })
+    );
+}
+
+#[test]
+fn selected_mode_routes_listed_apps() {
+    let settings = ProxySettings::default();
+    let rules = vec![
+        rule("telegram.exe", AppRuleMode::Proxy),
+        rule("C:\\Games\\game.exe", AppRuleMode::Direct),
+    ];
+    let composed = compose(
+        profile(),
+        ProxyMode::Selected,
+        rules,
+        &settings,
+        Some("de"),
+        None,
+    );
+    let config = &composed.config;
+    assert_eq!(outbound(config, "proxy")["default"], "de");
+    assert_eq!(config["route"]["final"], "direct");
+    assert_eq!(
+        route_rules(config),
+        vec![
+            json!({ "action": "hijack-dns", "port": 53 }),
+            json!({ "action": "sniff" }),
+            json!({ "domain_suffix": [".ru"], "outbound": "direct" }),
+            json!({ "rule_set": [GEOIP_RU_TAG], "outbound": "direct" }),
+            json!({ "inbound": [LOCAL_PROXY_TAG], "outbound": "proxy" }),
+            json!({ "process_path": ["C:\\Games\\game.exe"], "outbound": "direct" }),
+            json!({ "process_name": ["telegram.exe"], "outbound": "proxy" }),
+        ]
+    );
+}
+
+#[test]
+fn full_mode_sends_everything_else_to_proxy() {
+    let settings = ProxySettings::default();
+    let rules = vec![rule("steam.exe", AppRuleMode::Direct)];
+    let composed = compose(profile(), ProxyMode::Full, rules, &settings, None, None);
+    let config = &composed.config;
+    assert_eq!(config["route"]["final"], "proxy");
+    let rules = route_rules(config);
+    assert_eq!(rules[0], json!({ "action": "hijack-dns", "port": 53 }));
+    assert_eq!(
+        rules.last(),
+        Some(&json!({ "process_name": ["steam.exe"], "outbound": "direct" }))
+    );
+    let inbounds: Vec<&str> = config["inbounds"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|inbound| inbound["type"].as_str())
+        .collect();
+    assert_eq!(inbounds, ["tun", "mixed"]);
+}
+
+#[test]
+fn selected_preset_feeds_its_rules_into_the_config() {
+    let preset = |name: &str, rule: AppRule| RulePreset {
+        name: name.to_string(),
+        rules: vec![rule],
+    };
+    let mut state = AppState {
+        rule_presets: vec![
+            preset(DEFAULT_RULE_PRESET, rule("steam.exe", AppRuleMode::Direct)),
+            preset("work", rule("slack.exe", AppRuleMode::Proxy)),
+        ],
+        ..AppState::default()
+    };
+    assert_eq!(
+        state.select_preset("missing".to_string()).unwrap_err().code,
+        "PRESET_MISSING"
+    );
+    assert_eq!(state.select_preset("work".to_string()), Ok(true));
+    assert_eq!(state.select_preset("work".to_string()), Ok(false));
+
+    let composed = compose(
+        profile(),
+        ProxyMode::Selected,
+        state.active_rules(),
+        &ProxySettings::default(),
+        None,
+        None,
+    );
+    let rules = route_rules(&composed.config);
+    assert!(rules.contains(&json!({ "process_name": ["slack.exe"], "outbound": "proxy" })));
+    assert!(!rules
+        .iter()
+        .any(|rule| rule["process_name"] == json!(["steam.exe"])));
+}
+
+#[test]
+fn exclude_rule_routes_direct_before_the_hijack() {
+    let settings = ProxySettings::default();
+    let rules = vec![
+        rule("updater.exe", AppRuleMode::Exclude),
+        rule("updater.exe", AppRuleMode::Proxy),
+    ];
+    for mode in [ProxyMode::Selected, ProxyMode::Full] {
+        let composed = compose(profile(), mode, rules.clone(), &settings, None, None);
+        let rules = route_rules(&composed.config);
+        assert_eq!(
+            rules[0],
+            json!({ "process_name": ["updater.exe"], "outbound": "direct" })
+        );
+        assert!(!rules
+            .iter()
+            .any(|rule| rule["outbound"] == "proxy" && rule.get("process_name").is_some()));
+    }
+}
+
+#[test]
+fn exclude_rules_need_tun() {
+    let rules = vec![rule("updater.exe", AppRuleMode::Exclude)];
+    let no_tun = ProxySettings {
+        tun_enabled: false,
+        ..ProxySettings::default()
+    };
+    let composed = compose(profile(), ProxyMode::Full, rules, &no_tun, None, None);
+    assert!(!route_rules(&composed.config)
+        .iter()
+        .any(|rule| rule.get("process_name").is_some()));
+}
+
+#[test]
+fn hijack_dns_proxy_only_limits_the_hijack() {
+    let settings = ProxySettings {
+        hijack_dns_proxy_only: true,
+        ..ProxySettings::default()
+    };
+    let rules = vec![
+        rule("telegram.exe", AppRuleMode::Proxy),
+        rule("steam.exe", AppRuleMode::Direct),
+    ];
+
+    let selected = compose(
+        profile(),
+        ProxyMode::Selected,
+        rules.clone(),
+        &settings,
+        None,
+        None,
+    );
+    assert_eq!(
+        route_rules(&selected.config)[0],
+        json!({ "process_name": ["telegram.exe"], "port": 53, "action": "hijack-dns" })
+    );
+
+    let full = compose(profile(), ProxyMode::Full, rules, &settings, None, None);
+    let rules = route_rules(&full.config);
+    assert_eq!(
+        rules[0],
+        json!({ "process_name": ["steam.exe"], "outbound": "direct" })
+    );
+    assert_eq!(rules[1], json!({ "action": "hijack-dns", "port": 53 }));
+}
+
+#[test]
+fn disabled_hijack_dns_leaves_no_hijack_rule() {
+    let settings = ProxySettings {
+        hijack_dns: false,
+        ..ProxySettings::default()
+    };
+    let rules = vec![rule("telegram.exe", AppRuleMode::Proxy)];
+    for mode in [ProxyMode::Selected, ProxyMode::Full] {
+        let composed = compose(profile(), mode, rules.clone(), &settings, None, None);
+        let rules = route_rules(&composed.config);
+        assert!(!rules.iter().any(|rule| rule["action"] == "hijack-dns"));
+        assert_eq!(rules[0], json!({ "action": "sniff" }));
+    }
+}
+
+#[test]
+fn without_tun_there_is_no_hijack_or_process_routing() {
+    let settings = ProxySettings {
+        tun_enabled: false,
+        ..ProxySettings::default()
+    };
+    let rules = vec![rule("telegram.exe", AppRuleMode::Proxy)];
+    let composed = compose(profile(), ProxyMode::Selected, rules, &settings, None, None);
+    let rules = route_rules(&composed.config);
+    assert!(!rules.iter().any(|rule| rule["action"] == "hijack-dns"));
+    assert!(!rules.iter().any(|rule| rule.get("process_name").is_some()));
+    assert_eq!(composed.config["inbounds"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn custom_route_rules_survive_generation() {
+    let custom = json!({ "domain": ["intranet.example"], "outbound": "direct" });
+    let mut profile = profile();
+    profile["route"] = json!({ "rules": [custom.clone()], "final": "direct" });
+    let composed = compose(
+        profile,
+        ProxyMode::Full,
+        Vec::new(),
+        &ProxySettings::default(),
+        None,
+        None,
+    );
+    let rules = route_rules(&composed.config);
+    assert_eq!(rules[0], custom);
+    assert!(rules.iter().any(|rule| rule["action"] == "sniff"));
+    assert_eq!(composed.config["route"]["final"], "direct");
+    assert_eq!(composed.config["route"]["rule_set"][0]["tag"], GEOIP_RU_TAG);
+}
+
+#[test]
+fn override_route_replaces_the_custom_route() {
+    let settings = ProxySettings {
+        override_route: true,
+        ..ProxySettings::default()
+    };
+    let custom = json!({ "domain": ["intranet.example"], "outbound": "direct" });
+    let mut profile = profile();
+    profile["route"] = json!({ "rules": [custom.clone()], "final": "direct" });
+    let composed = compose(profile, ProxyMode::Full, Vec::new(), &settings, None, None);
+    assert!(!route_rules(&composed.config).contains(&custom));
+    assert_eq!(composed.config["route"]["final"], "proxy");
+}
+
+#[test]
+fn custom_inbounds_survive_generation() {
+    let custom = json!({ "type": "socks", "tag": "lan-socks", "listen_port": 1081 });
+    let mut profile = profile();
+    profile["inbounds"] = json!([custom.clone()]);
+    let composed = compose(
+        profile,
+        ProxyMode::Full,
+        Vec::new(),
+        &ProxySettings::default(),
+        None,
+        None,
+    );
+    let inbounds = composed.config["inbounds"].as_array().unwrap();
+    assert_eq!(inbounds[0], custom);
+    assert_eq!(inbound(&composed.config, "tun-in")["type"], "tun");
+    assert_eq!(inbound(&composed.config, LOCAL_PROXY_TAG)["type"], "mixed");
+}
+
+#[test]
+fn generated_inbound_tags_replace_profile_inbounds() {
+    let mut profile = profile();
+    profile["inbounds"] = json!([
+        { "type": "tun", "tag": "tun-in", "interface_name": "other" },
+        { "type": "http", "tag": LOCAL_PROXY_TAG, "listen_port": 8080 }
+    ]);
+    let composed = compose(
+        profile,
+        ProxyMode::Full,
+        Vec::new(),
+        &ProxySettings::default(),
+        None,
+        None,
+    );
+    let tags: Vec<&str> = composed.config["inbounds"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|inbound| inbound["tag"].as_str())
+        .collect();
+    assert_eq!(tags, ["tun-in", LOCAL_PROXY_TAG]);
+    assert_eq!(
+        inbound(&composed.config, "tun-in")["interface_name"],
+        "YotsubaCore"
+    );
+    assert_eq!(inbound(&composed.config, LOCAL_PROXY_TAG)["type"], "mixed");
+}
+
+#[test]
+fn plain_proxy_outbound_is_renamed_behind_a_selector() {
+    let settings = ProxySettings::default();
+    let profile = json!({
+        "outbounds": [
+            { "type": "vless", "tag": "proxy", "server": "a.example.com", "server_port": 443 },
+            { "type": "vless", "tag": "backup", "server": "b.example.com", "server_port": 443 }
+        ]
+    });
+    let composed = compose(
+        profile,
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        Some("backup"),
+        None,
+    );
+    assert_eq!(composed.selector_change, SelectorChange::ProxyRenamed);
+    assert_eq!(outbound(&composed.config, "proxy-origin")["type"], "vless");
+    assert_eq!(
+        outbound(&composed.config, "proxy"),
+        &json!({
+            "type": "selector",
+            "tag": "proxy",
+            "outbounds": ["proxy-origin", "backup"],
+            "default": "backup"
+        })
+    );
+    assert_eq!(composed.stale_tag, None);
+}
+
+#[test]
+fn existing_selector_or_lone_proxy_is_left_unchanged() {
+    let settings = ProxySettings::default();
+    let selector = json!({
+        "outbounds": [
+            { "type": "selector", "tag": "proxy", "outbounds": ["nl"] },
+            { "type": "vless", "tag": "nl", "server": "nl.example.com", "server_port": 443 }
+        ]
+    });
+    let composed = compose(
+        selector,
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        Some("nl"),
+        None,
+    );
+    assert_eq!(composed.selector_change, SelectorChange::NoChange);
+    assert_eq!(outbound(&composed.config, "proxy")["default"], "nl");
+
+    let lone = json!({
+        "outbounds": [
+            { "type": "vless", "tag": "proxy", "server": "a.example.com", "server_port": 443 }
+        ]
+    });
+    let composed = compose(lone, ProxyMode::Full, Vec::new(), &settings, None, None);
+    assert_eq!(composed.selector_change, SelectorChange::NoChange);
+    assert_eq!(outbound(&composed.config, "proxy")["type"], "vless");
+}
+
+#[test]
+fn auto_select_builds_urltest_group() {
+    let settings = ProxySettings {
+        auto_select: true,
+        ..ProxySettings::default()
+    };
+    let composed = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        None,
+        None,
+    );
+    let proxy = outbound(&composed.config, "proxy");
+    assert_eq!(proxy["type"], "urltest");
+    assert_eq!(proxy["outbounds"], json!(["nl", "de"]));
+}
+
+#[test]
+fn unknown_active_tag_is_reported_as_stale() {
+    let settings = ProxySettings::default();
+    let composed = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        Some("gone"),
+        None,
+    );
+    assert_eq!(composed.stale_tag.as_deref(), Some("gone"));
+    assert_eq!(outbound(&composed.config, "proxy")["default"], "nl");
+}
+
+#[test]
+fn profile_clash_api_is_kept() {
+    let settings = ProxySettings::default();
+    let mut profile = profile();
+    profile["experimental"] = json!({
+        "clash_api": { "external_controller": "127.0.0.1:19090", "secret": "mine" }
+    });
+    let composed = compose(profile, ProxyMode::Full, Vec::new(), &settings, None, None);
+    assert_eq!(
+        composed.config["experimental"]["clash_api"],
+        json!({ "external_controller": "127.0.0.1:19090", "secret": "mine" })
+    );
+}
+
+#[test]
+fn default_domain_resolver_needs_singbox_1_12() {
+    let settings = ProxySettings::default();
+    let composed = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        None,
+        Some("1.12.0"),
+    );
+    assert_eq!(
+        composed.config["route"]["default_domain_resolver"],
+        "dns-local"
+    );
+
+    let composed = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        None,
+        Some("1.11.4"),
+    );
+    assert!(composed.config["route"]
+        .get("default_domain_resolver")
+        .is_none());
+}
+
+#[test]
+fn default_domain_resolver_is_ipv4_only_without_ipv6() {
+    let settings = ProxySettings {
+        ipv6: false,
+        ..ProxySettings::default()
+    };
+    let composed = compose(
+        profile(),
+        ProxyMode::Selected,
+        Vec::new(),
+        &settings,
+        None,
+        Some("1.12.0"),
+    );
+    assert_eq!(
+        composed.config["route"]["default_domain_resolver"],
+        json!({ "server": "dns-local", "strategy": "ipv4_only" })
+    );
+}
+
+#[test]
+fn default_domain_resolver_skips_hostname_only_dns() {
+    let settings = ProxySettings::default();
+    let mut profile = profile();
+    profile["dns"] = json!({
+        "servers": [{ "tag": "doh", "type": "https", "server": "dns.google" }],
+        "final": "doh"
+    });
+    let composed = compose(
+        profile,
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        None,
+        Some("1.12.0"),
+    );
+    assert!(composed.config["route"]
+        .get("default_domain_resolver")
+        .is_none());
+}
+
+#[test]
+fn parse_singbox_version_reads_the_first_line() {
+    let output =
+        "sing-box version 1.12.4\n\nEnvironment: go1.24.5 windows/amd64\nTags: with_gvisor\n";
+    assert_eq!(parse_singbox_version(output).as_deref(), Some("1.12.4"));
+    assert_eq!(
+        parse_singbox_version("sing-box version v1.13.0-beta.2").as_deref(),
+        Some("1.13.0-beta.2")
+    );
+}
+
+#[test]
+fn parse_singbox_version_rejects_garbage() {
+    assert_eq!(parse_singbox_version(""), None);
+    assert_eq!(parse_singbox_version("sing-box version unknown"), None);
+    assert_eq!(parse_singbox_version("sing-box version 1"), None);
+    assert_eq!(parse_singbox_version("sing-box version 1..2"), None);
+}
+
+#[test]
+fn default_domain_resolver_version_gate() {
+    assert!(supports_default_domain_resolver(Some("1.12.0")));
+    assert!(supports_default_domain_resolver(Some("1.13.0-beta.2")));
+    assert!(supports_default_domain_resolver(Some("2.0.0")));
+    assert!(!supports_default_domain_resolver(Some("1.11.15")));
+    assert!(!supports_default_domain_resolver(None));
+}
+
+#[test]
+fn dns_servers_addressed_by_ip_need_no_resolving() {
+    for server in [
+        json!({ "type": "udp", "server": "1.1.1.1" }),
+        json!({ "type": "tls", "server": "2606:4700:4700::1111" }),
+        json!({ "type": "local" }),
+        json!({ "address": "local" }),
+        json!({ "address": "dhcp://auto" }),
+        json!({ "address": "tls://8.8.8.8" }),
+        json!({ "address": "https://1.1.1.1/dns-query" }),
+        json!({ "address": "[2001:db8::53]:53" }),
+        json!({ "address": "9.9.9.9:53" }),
+    ] {
+        assert!(!dns_server_needs_resolving(&server), "{server}");
+    }
+}
+
+#[test]
+fn dns_servers_addressed_by_hostname_need_resolving() {
+    for server in [
+        json!({ "type": "https", "server": "dns.google" }),
+        json!({ "address": "https://cloudflare-dns.com/dns-query" }),
+        json!({ "address": "tls://dns.quad9.net" }),
+        json!({ "type": "udp" }),
+    ] {
+        assert!(dns_server_needs_resolving(&server), "{server}");
+    }
+}
+
+#[test]
+fn default_domain_resolver_prefers_local_then_final() {
+    let dns = json!({
+        "servers": [
+            { "tag": "doh", "type": "https", "server": "dns.google" },
+            { "tag": "cf", "type": "udp", "server": "1.1.1.1" },
+            { "tag": "quad9", "type": "udp", "server": "9.9.9.9" },
+            { "tag": "system", "type": "local" }
+        ],
+        "final": "quad9"
+    });
+    assert_eq!(
+        default_domain_resolver(Some(&dns), true),
+        Some(json!("system"))
+    );
+
+    let mut dns = dns;
+    dns["servers"].as_array_mut().unwrap().pop();
+    assert_eq!(
+        default_domain_resolver(Some(&dns), true),
+        Some(json!("quad9"))
+    );
+
+    dns["final"] = json!("doh");
+    assert_eq!(default_domain_resolver(Some(&dns), true), Some(json!("cf")));
+    assert_eq!(default_domain_resolver(None, true), None);
+}
+
+#[test]
+fn outbound_dns_server_hint_becomes_a_dns_rule() {
+    let settings = ProxySettings::default();
+    let mut profile = profile();
+    profile["outbounds"][0][NODE_DNS_SERVER_FIELD] = json!("dns-local");
+    profile["outbounds"][1][NODE_DNS_SERVER_FIELD] = json!("dns-local");
+    profile["outbounds"][1]["server"] = json!("203.0.113.7");
+    let composed = compose(profile, ProxyMode::Full, Vec::new(), &settings, None, None);
+    let config = &composed.config;
+    assert_eq!(
+        config["dns"]["rules"][0],
+        json!({ "domain": ["nl.example.com"], "action": "route", "server": "dns-local" })
+    );
+    assert!(!config["dns"]["rules"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|rule| rule["domain"] == json!(["203.0.113.7"])));
+    assert!(outbound(config, "nl").get(NODE_DNS_SERVER_FIELD).is_none());
+    assert!(outbound(config, "nl").get("domain_resolver").is_none());
+}
+
+#[test]
+fn outbound_dns_server_hint_must_name_a_dns_server() {
+    let settings = ProxySettings::default();
+    let mut profile = profile();
+    profile["outbounds"][0][NODE_DNS_SERVER_FIELD] = json!("dns-typo");
+    let error = try_compose(profile, ProxyMode::Full, Vec::new(), &settings, None, None)
+        .err()
+        .expect("unknown DNS server");
+    assert_eq!(error.code, "PROFILE_DNS_INVALID");
+    assert!(error.detail.contains("dns-typo"));
+}
+
+#[test]
+fn mixed_credentials_reject_proxy_url_delimiters() {
+    let with_credentials = |username: &str, password: &str| ProxySettings {
+        allow_lan: true,
+        mixed_username: Some(username.to_string()),
+        mixed_password: Some(password.to_string()),
+        ..ProxySettings::default()
+    };
+    assert_eq!(validate_settings(&with_credentials("me", "pw-1!")), Ok(()));
+    for (username, password) in [("me", "p@ss"), ("me", "a:b"), ("me", "pw/"), ("m#e", "pw")] {
+        assert_eq!(
+            validate_settings(&with_credentials(username, password))
+                .unwrap_err()
+                .code,
+            "SETTINGS_INVALID",
+            "{username}:{password}"
+        );
+    }
+}
+
+#[test]
+fn a_profile_without_nodes_only_validates_off() {
+    let settings = ProxySettings::default();
+    let direct_only = json!({ "outbounds": [{ "type": "direct", "tag": "direct" }] });
+    let results = validate_modes(|mode| {
+        try_compose(direct_only.clone(), mode, Vec::new(), &settings, None, None).map(|_| ())
+    });
+    let outcome: Vec<(ProxyMode, bool)> = results.iter().map(|item| (item.mode, item.ok)).collect();
+    assert_eq!(
+        outcome,
+        [
+            (ProxyMode::Off, true),
+            (ProxyMode::Selected, false),
+            (ProxyMode::Full, false)
+        ]
+    );
+    assert_eq!(
+        results[1].error.as_ref().unwrap().detail,
+        "no proxy outbounds"
+    );
+}
+
+#[test]
+fn effective_config_matches_the_written_file() {
+    let dir = scratch_dir("effective-config");
+    let settings = ProxySettings::default();
+    let log_path = dir.join("singbox.log");
+    let compose_with = |controller: &str| {
+        compose_config(
+            profile(),
+            ProxyMode::Full,
+            vec![rule("steam.exe", AppRuleMode::Direct)],
+            ConfigOptions {
+                settings: &settings,
+                force_ipv4_ru: false,
+                active_tag: None,
+                log_path: &log_path,
+                geoip_ru_rule_set: json!({ "tag": GEOIP_RU_TAG }),
+                clash_api_secret: "secret",
+                clash_api_controller: controller,
+                singbox_version: None,
+            },
+        )
+        .expect("compose_config")
+        .config
+    };
+
+    let config_path = dir.join(CONFIG_FILE);
+    let started = compose_with("127.0.0.1:49152");
+    write_config(&config_path, &started).unwrap();
+    let clash_api = ClashApi::default();
+    clash_api.remember(&started);
+
+    let preview = compose_with(&clash_api.controller().expect("remembered controller"));
+    let written: Value =
+        serde_json::from_str(&std::fs::read_to_string(&config_path).unwrap()).unwrap();
+    assert_eq!(preview, written);
+}
+
+#[test]
+fn template_experimental_block_survives_compose() {
+    let mut profile = profile();
+    merge_template(
+        &mut profile,
+        json!({
+            "experimental": {
+                "cache_file": { "enabled": true, "path": "cache.db" },
+                "clash_api": { "external_ui": "ui" }
+            }
+        }),
+    );
+    let composed = compose(
+        profile,
+        ProxyMode::Full,
+        Vec::new(),
+        &ProxySettings::default(),
+        None,
+        None,
+    );
+    let experimental = &composed.config["experimental"];
+    assert_eq!(
+        experimental["cache_file"],
+        json!({ "enabled": true, "path": "cache.db" })
+    );
+    assert_eq!(experimental["clash_api"]["external_ui"], "ui");
+    assert_eq!(
+        experimental["clash_api"]["external_controller"],
+        "127.0.0.1:9090"
+    );
+    assert_eq!(experimental["clash_api"]["secret"], "secret");
+}
+
+#[test]
+fn mixed_inbound_listens_on_all_interfaces_only_with_allow_lan() {
+    let local = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &ProxySettings::default(),
+        None,
+        None,
+    );
+    assert_eq!(
+        inbound(&local.config, LOCAL_PROXY_TAG)["listen"],
+        "127.0.0.1"
+    );
+
+    let settings = ProxySettings {
+        allow_lan: true,
+        ..ProxySettings::default()
+    };
+    let lan = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        None,
+        None,
+    );
+    assert_eq!(inbound(&lan.config, LOCAL_PROXY_TAG)["listen"], "0.0.0.0");
+}
+
+#[test]
+fn custom_dns_servers_replace_the_defaults() {
+    let server = |tag: &str, kind: &str, address: Option<&str>, detour: Option<&str>| DnsServer {
+        tag: tag.to_string(),
+        kind: kind.to_string(),
+        server: address.map(str::to_string),
+        detour: detour.map(str::to_string),
+    };
+    let settings = ProxySettings {
+        dns_servers: vec![
+            server("dns-doh", "https", Some("1.1.1.1"), Some("proxy")),
+            server("dns-direct", "local", None, None),
+        ],
+        dns_final: Some("dns-doh".to_string()),
+        ..ProxySettings::default()
+    };
+    assert_eq!(validate_settings(&settings), Ok(()));
+    let composed = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        None,
+        None,
+    );
+    assert_eq!(
+        composed.config["dns"]["servers"],
+        json!([
+            { "tag": "dns-doh", "type": "https", "server": "1.1.1.1", "detour": "proxy" },
+            { "tag": "dns-direct", "type": "local" }
+        ])
+    );
+    assert_eq!(composed.config["dns"]["final"], "dns-doh");
+
+    let unknown_final = ProxySettings {
+        dns_final: Some("dns-missing".to_string()),
+        ..settings.clone()
+    };
+    assert_eq!(
+        validate_settings(&unknown_final).unwrap_err().code,
+        "SETTINGS_INVALID"
+    );
+}
+
+#[test]
+fn default_fingerprint_fills_only_tls_outbounds_without_one() {
+    let profile = json!({
+        "outbounds": [
+            { "type": "vless", "tag": "nl", "tls": { "enabled": true } },
+            {
+                "type": "trojan",
+                "tag": "de",
+                "tls": { "enabled": true, "utls": { "enabled": true, "fingerprint": "firefox" } }
+            },
+            { "type": "vmess", "tag": "us", "tls": { "enabled": false } },
+            { "type": "hysteria2", "tag": "jp", "tls": { "enabled": true } }
+        ]
+    });
+    let utls = |settings: &ProxySettings| {
+        let composed = compose(
+            profile.clone(),
+            ProxyMode::Full,
+            Vec::new(),
+            settings,
+            None,
+            None,
+        );
+        ["nl", "de", "us", "jp"].map(|tag| outbound(&composed.config, tag)["tls"]["utls"].clone())
+    };
+
+    let settings = ProxySettings {
+        default_fingerprint: "chrome".to_string(),
+        ..ProxySettings::default()
+    };
+    assert_eq!(validate_settings(&settings), Ok(()));
+    assert_eq!(
+        utls(&settings),
+        [
+            json!({ "enabled": true, "fingerprint": "chrome" }),
+            json!({ "enabled": true, "fingerprint": "firefox" }),
+            Value::Null,
+            Value::Null,
+        ]
+    );
+
+    let [nl, ..] = utls(&ProxySettings::default());
+    assert_eq!(nl, Value::Null);
+}
+
+#[test]
+fn fakeip_server_and_rule_appear_only_when_enabled() {
+    let plain = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &ProxySettings::default(),
+        None,
+        None,
+    );
+    let servers = plain.config["dns"]["servers"].as_array().unwrap();
+    assert!(servers.iter().all(|server| server["type"] != "fakeip"));
+    assert!(plain.config["dns"].get("rules").is_none());
+
+    let settings = ProxySettings {
+        fake_ip: true,
+        ..ProxySettings::default()
+    };
+    let composed = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        None,
+        None,
+    );
+    let dns = &composed.config["dns"];
+    let fake = dns["servers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|server| server["type"] == "fakeip")
+        .expect("fakeip server");
+    assert_eq!(fake["inet4_range"], "198.18.0.0/15");
+    assert_eq!(fake["inet6_range"], "fc00::/18");
+    assert_eq!(
+        dns["rules"],
+        json!([{ "query_type": ["A", "AAAA"], "action": "route", "server": fake["tag"] }])
+    );
+    assert_eq!(dns["reverse_mapping"], true);
+}
+
+#[test]
+fn tun_address_is_ipv4_only_without_ipv6() {
+    let settings = ProxySettings {
+        tun_enabled: true,
+        ipv6: false,
+        ..ProxySettings::default()
+    };
+    let composed = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        None,
+        None,
+    );
+    assert_eq!(
+        inbound(&composed.config, "tun-in")["address"],
+        json!(["172.19.0.1/30"])
+    );
+}
+
+#[test]
+fn log_level_follows_the_setting() {
+    let settings = ProxySettings {
+        log_level: "warn".to_string(),
+        ..ProxySettings::default()
+    };
+    let composed = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        None,
+        None,
+    );
+    assert_eq!(
+        composed.config["log"],
+        json!({ "level": "warn", "output": "singbox.log" })
+    );
+
+    let mut profile = profile();
+    profile["log"] = json!({ "level": "trace", "timestamp": true });
+    let composed = compose(profile, ProxyMode::Full, Vec::new(), &settings, None, None);
+    assert_eq!(
+        composed.config["log"],
+        json!({ "level": "warn", "timestamp": true })
+    );
+}
+
+#[test]
+fn selected_mode_final_follows_the_setting() {
+    let settings = ProxySettings {
+        selected_final: FinalOutbound::Proxy,
+        ..ProxySettings::default()
+    };
+    let composed = compose(
+        profile(),
+        ProxyMode::Selected,
+        Vec::new(),
+        &settings,
+        None,
+        None,
+    );
+    assert_eq!(composed.config["route"]["final"], "proxy");
+    assert!(
+        serde_json::from_value::<ProxySettings>(json!({ "selectedFinal": "exclude" })).is_err()
+    );
+}
+
+#[test]
+fn autostart_delay_is_read_from_settings_and_clamped() {
+    let delay = |value: Value| {
+        serde_json::from_value::<ProxySettings>(value)
+            .unwrap()
+            .autostart_delay()
+    };
+    assert_eq!(delay(json!({})), Duration::ZERO);
+    assert_eq!(
+        delay(json!({ "autostartDelaySecs": 15 })),
+        Duration::from_secs(15)
+    );
+    assert_eq!(
+        delay(json!({ "autostartDelaySecs": 86_400 })),
+        Duration::from_secs(MAX_AUTOSTART_DELAY_SECS)
+    );
+}
+
+#[test]
+fn autostart_entry_passes_the_autostart_flag() {
+    assert!(autostart_args().contains(&"--autostart"));
+}
+
+#[test]
+fn geoip_ru_source_must_be_an_http_url() {
+    let with_url = |url: &str| ProxySettings {
+        geoip_ru_url: url.to_string(),
+        ..ProxySettings::default()
+    };
+    assert_eq!(validate_settings(&ProxySettings::default()), Ok(()));
+    assert_eq!(
+        validate_settings(&with_url("https://mirror.example.com/geoip-ru.srs")),
+        Ok(())
+    );
+    for url in [
+        "",
+        "mirror.example.com/geoip-ru.srs",
+        "ftp://mirror.example.com/geoip-ru.srs",
+        "file:///tmp/geoip-ru.srs",
+    ] {
+        assert_eq!(
+            validate_settings(&with_url(url)).unwrap_err().code,
+            "SETTINGS_INVALID",
+            "{url}"
+        );
+    }
+}
+
+#[test]
+fn geoip_ru_rule_set_prefers_the_cached_file() {
+    let dir = scratch_dir("geoip-ru");
+    let path = dir.join("geoip-ru.srs");
+    let settings = ProxySettings {
+        geoip_ru_url: "https://mirror.example.com/geoip-ru.srs".to_string(),
+        geoip_update_interval: "24h".to_string(),
+        ..ProxySettings::default()
+    };
+    assert_eq!(
+        geoip_ru_rule_set(&path, &settings),
+        json!({
+            "tag": GEOIP_RU_TAG,
+            "type": "remote",
+            "format": "binary",
+            "url": "https://mirror.example.com/geoip-ru.srs",
+            "download_detour": "proxy",
+            "update_interval": "24h"
+        })
+    );
+
+    fs::write(&path, b"srs").unwrap();
+    assert_eq!(
+        geoip_ru_rule_set(&path, &settings),
+        json!({
+            "tag": GEOIP_RU_TAG,
+            "type": "local",
+            "format": "binary",
+            "path": path.display().to_string()
+        })
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn direct_is_offered_in_the_selector_only_when_allowed() {
+    let plain = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &ProxySettings::default(),
+        None,
+        None,
+    );
+    assert_eq!(
+        outbound(&plain.config, "proxy")["outbounds"],
+        json!(["nl", "de"])
+    );
+
+    let settings = ProxySettings {
+        allow_direct_selection: true,
+        ..ProxySettings::default()
+    };
+    let composed = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        Some("direct"),
+        None,
+    );
+    let proxy = outbound(&composed.config, "proxy");
+    assert_eq!(proxy["outbounds"], json!(["nl", "de", "direct"]));
+    assert_eq!(proxy["default"], "direct");
+    assert_eq!(composed.stale_tag, None);
+}
+
+#[test]
+fn custom_tun_range_reaches_the_inbound() {
+    let settings = ProxySettings {
+        tun_enabled: true,
+        tun_inet4_address: " 10.88.0.1/30 ".to_string(),
+        tun_inet6_address: "fdfe:dcba:9876::1/126".to_string(),
+        ..ProxySettings::default()
+    };
+    assert_eq!(validate_settings(&settings), Ok(()));
+    let composed = compose(
+        profile(),
+        ProxyMode::Full,
+        Vec::new(),
+        &settings,
+        None,
+        None,
+    );
+    assert_eq!(
+        inbound(&composed.config, "tun-in")["address"],
+        json!(["10.88.0.1/30", "fdfe:dcba:9876::1/126"])
+    );
+}
+
+#[test]
+fn test_url_must_be_http_and_timeout_is_clamped() {
+    let with_url = |url: &str| ProxySettings {
+        test_url: url.to_string(),
+        ..ProxySettings::default()
+    };
+    assert_eq!(
+        validate_settings(&with_url("http://cp.cloudflare.com/generate_204")),
+        Ok(())
+    );
+    for url in [
+        "",
+        "cp.cloudflare.com",
+        "socks5://127.0.0.1:1080",
+        "http://",
+    ] {
+        assert_eq!(
+            validate_settings(&with_url(url)).unwrap_err().code,
+            "SETTINGS_INVALID",
+            "{url}"
+        );
+    }
+
+    let timeout = |ms: u64| {
+        ProxySettings {
+            test_timeout_ms: ms,
+            ..ProxySettings::default()
+        }
+        .test_timeout()
+    };
+    assert_eq!(timeout(3000), Duration::from_millis(3000));
+    assert_eq!(timeout(0), Duration::from_millis(MIN_TEST_TIMEOUT_MS));
+    assert_eq!(
+        timeout(u64::MAX),
+        Duration::from_millis(MAX_TEST_TIMEOUT_MS)
+    );
+}
+
+#[test]
+fn app_rule_paths_are_unquoted_and_use_the_native_separator() {
+    let sep = std::path::MAIN_SEPARATOR;
+    let rules = normalize_app_rules(vec![
+        rule(" \"C:/Games\\game.exe\" ", AppRuleMode::Direct),
+        rule("'/opt/apps/telegram'", AppRuleMode::Proxy),
+        rule(" \"steam.exe\" ", AppRuleMode::Direct),
+    ])
+    .unwrap();
+    let paths: Vec<&str> = rules.iter().map(|rule| rule.path.as_str()).collect();
+    assert_eq!(
+        paths,
+        [
+            format!("C:{sep}Games{sep}game.exe").as_str(),
+            format!("{sep}opt{sep}apps{sep}telegram").as_str(),
+            "steam.exe",
+        ]
+    );
+    assert_eq!(rules[1].mode, AppRuleMode::Proxy);
+
+    let error = normalize_app_rules(vec![
+        rule("steam.exe", AppRuleMode::Direct),
+        rule(" \"\" ", AppRuleMode::Proxy),
+    ])
+    .unwrap_err();
+    assert_eq!(error.code, "RULE_INVALID");
+    assert_eq!(error.detail, "app rule #2 has an empty path");
+}
+
+#[test]
+fn a_path_in_both_modes_routes_direct_and_is_reported() {
+    let rules = vec![
+        rule("Telegram.exe", AppRuleMode::Proxy),
+        rule("telegram.exe", AppRuleMode::Direct),
+        rule("firefox.exe", AppRuleMode::Proxy),
+    ];
+    assert_eq!(normalize_rules(rules.clone()).conflicts, ["Telegram.exe"]);
+
+    let settings = ProxySettings {
+        tun_enabled: true,
+        ..ProxySettings::default()
+    };
+    let composed = compose(profile(), ProxyMode::Selected, rules, &settings, None, None);
+    let process_rules: Vec<Value> = route_rules(&composed.config)
+        .into_iter()
+        .filter(|rule| rule.get("process_name").is_some())
+        .collect();
+    assert_eq!(
+        process_rules,
+        vec![
+            json!({ "process_name": ["telegram.exe"], "outbound": "direct" }),
+            json!({ "process_name": ["firefox.exe"], "outbound": "proxy" }),
+        ]
+    );
+}
+
+#[test]
+fn tunnel_info_serializes_in_camel_case() {
+    let settings = ProxySettings {
+        tun_enabled: true,
+        ..ProxySettings::default()
+    };
+    assert_eq!(
+        serde_json::to_value(tunnel_info(&settings, Some((2048, 512)))).unwrap(),
+        json!({
+            "interfaceName": "YotsubaCore",
+            "addresses": ["172.19.0.1/30", "fdfe:dcba:9876::1/126"],
+            "autoRoute": true,
+            "interfaceUp": true,
+            "receivedBytes": 2048,
+            "transmittedBytes": 512
+        })
+    );
+
+    let down = serde_json::to_value(tunnel_info(&settings, None)).unwrap();
+    assert_eq!(down["interfaceUp"], false);
+    assert_eq!(down["receivedBytes"], 0);
+    assert_eq!(down["transmittedBytes"], 0);
+}
+
+#[test]
+fn direct_dns_server_follows_the_setting() {
+    let local_dns = |direct_dns: Option<&str>| {
+        let settings = ProxySettings {
+            direct_dns: direct_dns.map(str::to_string),
+            ..ProxySettings::default()
+        };
+        let composed = compose(
+            profile(),
+            ProxyMode::Full,
+            Vec::new(),
+            &settings,
+            None,
+            None,
+        );
+        composed.config["dns"]["servers"][0].clone()
+    };
+    assert_eq!(
+        local_dns(None),
+        json!({ "tag": "dns-local", "type": "local" })
+    );
+    assert_eq!(
+        local_dns(Some("192.168.1.1")),
+        json!({ "tag": "dns-local", "type": "udp", "server": "192.168.1.1", "server_port": 53 })
+    );
+    assert_eq!(
+        local_dns(Some("tcp://[2606:4700:4700::1111]:5353")),
+        json!({ "tag": "dns-local", "type": "tcp", "server": "2606:4700:4700::1111", "server_port": 5353 })
+    );
+}
+
+#[test]
+fn config_generated_payload_names_the_written_file() {
+    let dir = scratch_dir("config-generated");
+    let path = dir.join(CONFIG_FILE);
+    let config = json!({ "outbounds": [] });
+    write_config(&path, &config).unwrap();
+
+    let payload = ConfigGeneratedPayload::new(&path, config.clone(), &ProxySettings::default());
+    assert_eq!(PathBuf::from(&payload.path), path);
+    assert!(PathBuf::from(&payload.path).exists());
+    assert_eq!(payload.config, None);
+
+    let settings = ProxySettings {
+        config_event_json: true,
+        ..ProxySettings::default()
+    };
+    assert_eq!(
+        serde_json::to_value(ConfigGeneratedPayload::new(
+            &path,
+            config.clone(),
+            &settings
+        ))
+        .unwrap(),
+        json!({ "path": path.to_string_lossy(), "config": config })
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn mixed_users_need_lan_access_and_both_credentials() {
+    let users = |allow_lan: bool, username: Option<&str>, password: Option<&str>| {
+        let settings = ProxySettings {
+            allow_lan,
+            mixed_username: username.map(str::to_string),
+            mixed_password: password.map(str::to_string),
+            ..ProxySettings::default()
+        };
+        let composed = compose(
+            profile(),
+            ProxyMode::Full,
+            Vec::new(),
+            &settings,
+            None,
+            None,
+        );
+        inbound(&composed.config, LOCAL_PROXY_TAG)
+            .get("users")
+            .cloned()
+    };
+    assert_eq!(users(true, None, None), None);
+    assert_eq!(users(true, Some("me"), Some("")), None);
+    assert_eq!(users(false, Some("me"), Some("pw")), None);
+    assert_eq!(
+        users(true, Some("me"), Some("pw")),
+        Some(json!([{ "username": "me", "password": "pw" }]))
+    );
+}
@@ -0,0 +1,276 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Value};
+
+use crate::parse::parse_share_link;
+use crate::parse_links_in_batches;
+use crate::{
+    merge_outbounds, parse_outbound_json, preview_links, preview_share_link, validate_outbound,
+};
+
+fn links(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|index| format!("trojan://pw@node{index}.example.com:443"))
+        .collect()
+}
+
+fn progress_points(count: usize, batch_size: usize) -> Vec<usize> {
+    let mut points = Vec::new();
+    parse_links_in_batches(links(count), batch_size, |progress| {
+        assert_eq!(progress.total, count);
+        points.push(progress.parsed);
+    });
+    points
+}
+
+#[test]
+fn progress_is_reported_after_every_batch_and_at_the_end() {
+    assert_eq!(progress_points(3, 1), [1, 2, 3]);
+    assert_eq!(progress_points(7, 3), [3, 6, 7]);
+    assert_eq!(progress_points(6, 3), [3, 6]);
+    assert_eq!(progress_points(5, 5), [5]);
+    assert_eq!(progress_points(5, 50), [5]);
+    assert_eq!(progress_points(2, 0), [1, 2]);
+    assert!(progress_points(0, 3).is_empty());
+}
+
+#[test]
+fn progress_counts_failed_links() {
+    let mut input = links(2);
+    input.insert(1, "wireguard://key@example.com:51820".to_string());
+    let mut failed = Vec::new();
+    let (outbounds, errors) = parse_links_in_batches(input, 1, |progress| {
+        failed.push(progress.failed);
+    });
+    assert_eq!(failed, [0, 1, 1]);
+    assert_eq!(outbounds.len(), 2);
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("wireguard://"));
+}
+
+const WS_LINK: &str = "vless://0f1e2d3c-4b5a-6978-8796-a5b4c3d2e1f0@edge.example.com:443\
+     ?security=tls&type=ws&path=%2Fray#Edge";
+const TCP_LINK: &str = "vless://0f1e2d3c-4b5a-6978-8796-a5b4c3d2e1f0@edge.example.com:443\
+     ?security=tls&type=tcp#Edge";
+
+fn reimported_as_tcp(dedupe: bool) -> Vec<Value> {
+    let mut outbounds = vec![parse_share_link(WS_LINK).unwrap()];
+    assert!(outbounds[0].get("transport").is_some());
+    let tcp = parse_share_link(TCP_LINK).unwrap();
+    assert!(tcp.get("transport").is_none());
+    merge_outbounds(&mut outbounds, vec![tcp], dedupe);
+    outbounds
+}
+
+#[test]
+fn reimporting_a_node_as_tcp_drops_its_transport() {
+    let deduped = reimported_as_tcp(true);
+    assert_eq!(deduped.len(), 1);
+    assert!(deduped[0].get("transport").is_none());
+
+    let appended = reimported_as_tcp(false);
+    assert_eq!(appended.len(), 2);
+    assert!(appended.iter().all(|item| item.get("transport").is_none()));
+}
+
+#[test]
+fn manual_outbounds_need_their_type_specific_fields() {
+    let valid = json!({
+        "type": "vless",
+        "tag": "edge",
+        "server": "edge.example.com",
+        "server_port": 443,
+        "uuid": "0f1e2d3c-4b5a-6978-8796-a5b4c3d2e1f0",
+        "flow": "xtls-rprx-vision"
+    });
+    assert_eq!(validate_outbound(&valid), Ok(()));
+
+    let mut missing_server = valid.clone();
+    missing_server.as_object_mut().unwrap().remove("server");
+    assert_eq!(
+        validate_outbound(&missing_server).unwrap_err(),
+        "vless outbound is missing server"
+    );
+}
+
+#[test]
+fn tuic_outbounds_need_what_the_link_parser_emits() {
+    let parsed =
+        parse_share_link("tuic://b831381d-6324-4d53-ad4f-8cda48b30811:pw@tuic.example.com:443")
+            .unwrap();
+    assert_eq!(validate_outbound(&parsed), Ok(()));
+
+    let mut no_password = parsed.clone();
+    no_password.as_object_mut().unwrap().remove("password");
+    assert_eq!(
+        validate_outbound(&no_password).unwrap_err(),
+        "tuic outbound is missing password"
+    );
+}
+
+#[test]
+fn importing_the_same_link_twice_with_dedupe_is_skipped() {
+    let (parsed, errors) = parse_links_in_batches(vec![TCP_LINK.to_string(); 2], 1, |_| {});
+    assert!(errors.is_empty());
+    let mut outbounds = Vec::new();
+    let merged = merge_outbounds(&mut outbounds, parsed, true);
+    assert_eq!(merged.added_tags, ["Edge"]);
+    assert_eq!(merged.duplicates, ["Edge"]);
+
+    let again = merge_outbounds(
+        &mut outbounds,
+        vec![parse_share_link(TCP_LINK).unwrap()],
+        true,
+    );
+    assert!(again.added_tags.is_empty());
+    assert_eq!(again.duplicates, ["Edge"]);
+    assert_eq!(outbounds.len(), 1);
+}
+
+#[test]
+fn added_tags_are_the_final_unique_tags() {
+    let mut outbounds = vec![json!({ "type": "direct", "tag": "Edge" })];
+    let (parsed, _) = parse_links_in_batches(
+        vec![
+            TCP_LINK.to_string(),
+            "trojan://pw@node.example.com:443#Edge".to_string(),
+            "trojan://pw@other.example.com:443".to_string(),
+        ],
+        10,
+        |_| {},
+    );
+    let merged = merge_outbounds(&mut outbounds, parsed, false);
+    let appended: Vec<&str> = outbounds[1..]
+        .iter()
+        .map(|item| item["tag"].as_str().unwrap())
+        .collect();
+    assert_eq!(merged.added_tags, appended);
+    assert_eq!(
+        merged.added_tags,
+        ["Edge-2", "Edge-3", "trojan-other.example.com:443"]
+    );
+}
+
+#[test]
+fn outbound_json_arrays_may_mix_links_and_objects() {
+    let payload = json!([
+        TCP_LINK,
+        { "type": "trojan", "tag": "manual", "server": "t.example.com", "server_port": 443, "password": "pw" },
+        "  ",
+        "wireguard://key@example.com:51820",
+        { "type": "trojan", "tag": "broken", "server_port": 443, "password": "pw" }
+    ]);
+    let Ok(parsed) = parse_outbound_json(&payload.to_string()) else {
+        panic!("links and objects import together");
+    };
+    let tags: Vec<&str> = parsed
+        .outbounds
+        .iter()
+        .map(|item| item["tag"].as_str().unwrap())
+        .collect();
+    assert_eq!(tags, ["Edge", "manual"]);
+    assert_eq!(parsed.outbounds[0], parse_share_link(TCP_LINK).unwrap());
+    assert_eq!(parsed.errors.len(), 2);
+    assert!(parsed.errors[0].starts_with("wireguard://key@example.com:51820: "));
+    assert_eq!(
+        parsed.errors[1],
+        "broken: trojan outbound is missing server"
+    );
+    assert!(parsed.warnings.is_empty());
+
+    let only_bad = json!(["wireguard://key@example.com:51820"]).to_string();
+    let Err(error) = parse_outbound_json(&only_bad) else {
+        panic!("nothing valid to import");
+    };
+    assert_eq!(error.code, "IMPORT_FAILED");
+}
+
+#[test]
+fn previewed_links_report_their_outbound_type() {
+    let vmess = json!({
+        "v": "2",
+        "ps": "VM",
+        "add": "vm.example.com",
+        "port": "443",
+        "id": "b831381d-6324-4d53-ad4f-8cda48b30811",
+        "aid": "0",
+        "net": "tcp"
+    });
+    let cases = [
+        (
+            "ss://aes-256-gcm:secret@203.0.113.1:8388#SS".to_string(),
+            "shadowsocks",
+        ),
+        (
+            format!("vmess://{}", STANDARD.encode(vmess.to_string())),
+            "vmess",
+        ),
+        (TCP_LINK.to_string(), "vless"),
+        ("trojan://pw@tr.example.com:443".to_string(), "trojan"),
+        ("hysteria2://pw@hy.example.com:443".to_string(), "hysteria2"),
+        ("hy2://pw@hy.example.com:443".to_string(), "hysteria2"),
+        (
+            "hysteria://hy.example.com:443?auth=pw".to_string(),
+            "hysteria",
+        ),
+        (
+            "tuic://b831381d-6324-4d53-ad4f-8cda48b30811:pw@tuic.example.com:443".to_string(),
+            "tuic",
+        ),
+        ("anytls://pw@any.example.com:443".to_string(), "anytls"),
+    ];
+    for (link, kind) in cases {
+        let outbound = preview_share_link(link.clone()).unwrap_or_else(|e| panic!("{link}: {e}"));
+        assert_eq!(outbound["type"], kind, "{link}");
+    }
+    assert_eq!(
+        preview_share_link("wireguard://key@example.com:51820".to_string())
+            .unwrap_err()
+            .code,
+        "IMPORT_UNSUPPORTED"
+    );
+}
+
+#[test]
+fn previews_cover_valid_and_malformed_links_in_order() {
+    let subscription =
+        STANDARD.encode("trojan://pw@sub.example.com:443#Sub\ntrojan://pw@sub.example.com\n");
+    let previews = preview_links(vec![
+        TCP_LINK.to_string(),
+        "  ".to_string(),
+        "wireguard://key@example.com:51820".to_string(),
+        subscription,
+    ]);
+    let inputs: Vec<&str> = previews.iter().map(|item| item.input.as_str()).collect();
+    assert_eq!(
+        inputs,
+        [
+            TCP_LINK,
+            "wireguard://key@example.com:51820",
+            "trojan://pw@sub.example.com:443#Sub",
+            "trojan://pw@sub.example.com",
+        ]
+    );
+    let kinds: Vec<Option<&str>> = previews
+        .iter()
+        .map(|item| {
+            item.outbound
+                .as_ref()
+                .and_then(|outbound| outbound["type"].as_str())
+        })
+        .collect();
+    assert_eq!(kinds, [Some("vless"), None, Some("trojan"), None]);
+    let codes: Vec<Option<&str>> = previews
+        .iter()
+        .map(|item| item.error.as_ref().map(|error| error.code.as_str()))
+        .collect();
+    assert_eq!(
+        codes,
+        [
+            None,
+            Some("IMPORT_UNSUPPORTED"),
+            None,
+            Some("IMPORT_INVALID")
+        ]
+    );
+}
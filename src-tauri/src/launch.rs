@@ -0,0 +1,142 @@
+use serde_json::Value;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// osascript reports a cancelled administrator prompt with this error number.
+const OSASCRIPT_CANCELED: &str = "(-128)";
+const STOP_POLL_SECS: &str = "0.5";
+
+/// A program and its arguments, kept apart from `Command` so the elevation
+/// wrappers can be checked without spawning anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Launch {
+    pub(crate) program: PathBuf,
+    pub(crate) args: Vec<OsString>,
+}
+
+impl Launch {
+    pub(crate) fn singbox_run(exe: &Path, config: &Path) -> Self {
+        Self {
+            program: exe.to_path_buf(),
+            args: vec!["run".into(), "-c".into(), config.into()],
+        }
+    }
+
+    pub(crate) fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args);
+        cmd
+    }
+}
+
+/// How sing-box is started when it needs more rights than the app has.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) enum Elevation {
+    /// Run sing-box as the current user.
+    #[default]
+    None,
+    /// Ask for an administrator password through `osascript`. The elevated shell
+    /// creates `started_file` once sing-box is running and stops it once `stop_file`
+    /// appears or the `owner` process is gone, so the app never has to signal a
+    /// root process itself.
+    Osascript {
+        log: PathBuf,
+        stop_file: PathBuf,
+        started_file: PathBuf,
+        owner: u32,
+    },
+}
+
+impl Elevation {
+    pub(crate) fn wrap(&self, launch: Launch) -> Launch {
+        match self {
+            Elevation::None => launch,
+            Elevation::Osascript {
+                log,
+                stop_file,
+                started_file,
+                owner,
+            } => {
+                let stop = shell_quote(stop_file.as_os_str());
+                let started = shell_quote(started_file.as_os_str());
+                let run = std::iter::once(launch.program.as_os_str())
+                    .chain(launch.args.iter().map(OsString::as_os_str))
+                    .map(shell_quote)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let script = format!(
+                    "rm -f {stop} {started}; {run} >>{log} 2>&1 & p=$!; touch {started}; \
+                     while kill -0 $p 2>/dev/null && kill -0 {owner} 2>/dev/null && [ ! -e {stop} ]; \
+                     do sleep {STOP_POLL_SECS}; done; \
+                     kill $p 2>/dev/null; wait $p; s=$?; rm -f {stop} {started}; exit $s",
+                    log = shell_quote(log.as_os_str()),
+                );
+                Launch {
+                    program: PathBuf::from("osascript"),
+                    args: vec![
+                        "-e".into(),
+                        format!(
+                            "do shell script \"{}\" with administrator privileges",
+                            applescript_escape(&script)
+                        )
+                        .into(),
+                    ],
+                }
+            }
+        }
+    }
+
+    /// Whether a startup failure came from the user declining the prompt.
+    pub(crate) fn declined(&self, output: &str) -> bool {
+        match self {
+            Elevation::None => false,
+            Elevation::Osascript { .. } => output.contains(OSASCRIPT_CANCELED),
+        }
+    }
+
+    /// Whether sing-box itself has been started, which for `osascript` only
+    /// happens after the user has answered the password prompt.
+    pub(crate) fn launched(&self) -> bool {
+        match self {
+            Elevation::None => true,
+            Elevation::Osascript { started_file, .. } => started_file.exists(),
+        }
+    }
+
+    /// Asks an elevated sing-box to exit. Returns false when there is nothing to ask.
+    pub(crate) fn request_stop(&self) -> bool {
+        match self {
+            Elevation::None => false,
+            Elevation::Osascript { stop_file, .. } => fs::write(stop_file, b"").is_ok(),
+        }
+    }
+}
+
+/// TUN inbounds need root on macOS.
+pub(crate) fn needs_root(config: &Value) -> bool {
+    config
+        .get("inbounds")
+        .and_then(Value::as_array)
+        .is_some_and(|inbounds| {
+            inbounds
+                .iter()
+                .any(|inbound| inbound.get("type").and_then(Value::as_str) == Some("tun"))
+        })
+}
+
+pub(crate) fn running_as_root() -> bool {
+    Command::new("id")
+        .arg("-u")
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim() == "0")
+}
+
+fn shell_quote(value: &OsStr) -> String {
+    format!("'{}'", value.to_string_lossy().replace('\'', r"'\''"))
+}
+
+fn applescript_escape(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', "\\\"")
+}
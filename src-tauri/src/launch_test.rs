@@ -0,0 +1,96 @@
+use serde_json::json;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::launch::{needs_root, Elevation, Launch};
+use crate::test_util::scratch_dir;
+
+fn osascript() -> Elevation {
+    Elevation::Osascript {
+        log: PathBuf::from("/tmp/yotsuba/singbox.log"),
+        stop_file: PathBuf::from("/tmp/yotsuba/singbox.stop"),
+        started_file: PathBuf::from("/tmp/yotsuba/singbox.started"),
+        owner: 4242,
+    }
+}
+
+#[test]
+fn singbox_run_builds_run_arguments() {
+    let launch = Launch::singbox_run(Path::new("/opt/sing-box"), Path::new("/tmp/config.json"));
+    assert_eq!(launch.program, PathBuf::from("/opt/sing-box"));
+    assert_eq!(
+        launch.args,
+        ["run", "-c", "/tmp/config.json"].map(OsString::from)
+    );
+}
+
+#[test]
+fn no_elevation_keeps_the_launch() {
+    let launch = Launch::singbox_run(Path::new("/opt/sing-box"), Path::new("/tmp/config.json"));
+    assert_eq!(Elevation::None.wrap(launch.clone()), launch);
+}
+
+#[test]
+fn osascript_wraps_the_launch_in_an_admin_prompt() {
+    let launch = Launch::singbox_run(Path::new("/opt/sing-box"), Path::new("/tmp/config.json"));
+    let wrapped = osascript().wrap(launch);
+    assert_eq!(wrapped.program, PathBuf::from("osascript"));
+    assert_eq!(wrapped.args.len(), 2);
+    assert_eq!(wrapped.args[0], "-e");
+    let script = wrapped.args[1].to_string_lossy();
+    assert!(script.starts_with("do shell script \""));
+    assert!(script.ends_with("\" with administrator privileges"));
+    assert!(script.contains(
+        "'/opt/sing-box' 'run' '-c' '/tmp/config.json' >>'/tmp/yotsuba/singbox.log' 2>&1 &"
+    ));
+    assert!(script.contains("& p=$!; touch '/tmp/yotsuba/singbox.started';"));
+    assert!(script.contains("kill -0 4242"));
+    assert!(script.contains("[ ! -e '/tmp/yotsuba/singbox.stop' ]"));
+}
+
+#[test]
+fn osascript_escapes_quotes_in_paths() {
+    let launch = Launch::singbox_run(
+        Path::new("/Users/o'neil/sing-box"),
+        Path::new("/tmp/\"cfg\".json"),
+    );
+    let wrapped = osascript().wrap(launch);
+    let script = wrapped.args[1].to_string_lossy();
+    assert!(script.contains(r"'/Users/o'\\''neil/sing-box'"));
+    assert!(script.contains(r#"'/tmp/\"cfg\".json'"#));
+}
+
+#[test]
+fn only_osascript_recognizes_a_declined_prompt() {
+    let output = "execution error: User canceled. (-128)";
+    assert!(osascript().declined(output));
+    assert!(!osascript().declined("sing-box exited with code 1"));
+    assert!(!Elevation::None.declined(output));
+}
+
+#[test]
+fn tun_inbounds_need_root() {
+    assert!(needs_root(&json!({
+        "inbounds": [{ "type": "mixed" }, { "type": "tun" }]
+    })));
+    assert!(!needs_root(&json!({ "inbounds": [{ "type": "mixed" }] })));
+    assert!(!needs_root(&json!({})));
+}
+
+#[test]
+fn osascript_is_launched_once_the_marker_exists() {
+    let dir = scratch_dir("elevation-started");
+    let started_file = dir.join("singbox.started");
+    let elevation = Elevation::Osascript {
+        log: dir.join("singbox.log"),
+        stop_file: dir.join("singbox.stop"),
+        started_file: started_file.clone(),
+        owner: 4242,
+    };
+    assert!(Elevation::None.launched());
+    assert!(!elevation.launched());
+    fs::write(&started_file, b"").unwrap();
+    assert!(elevation.launched());
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -1,22 +1,29 @@
-use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
-use base64::Engine;
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, DebouncedEvent, Debouncer};
+use launch::{Elevation, Launch};
+use parse::{expand_subscriptions, legacy_vmess_warning, parse_share_link};
+use regex::Regex;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
+use serde_json::{json, Map, Value};
+use std::collections::hash_map::RandomState;
 use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
 use std::fs::{self, OpenOptions};
 use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
-use tauri::menu::{Menu, MenuItemBuilder};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::menu::{CheckMenuItemBuilder, Menu, MenuItemBuilder, SubmenuBuilder};
 use tauri::tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
-use tauri::{AppHandle, Emitter, Manager, State};
-use tauri_plugin_autostart::MacosLauncher;
+use tauri::{AppHandle, Emitter, Manager, PhysicalPosition, PhysicalSize, State, WebviewWindow};
+use tauri_plugin_autostart::{MacosLauncher, ManagerExt};
+use tauri_plugin_opener::OpenerExt;
 use url::Url;
-use percent_encoding::percent_decode_str;
-use sysinfo::{ProcessRefreshKind, RefreshKind, System, UpdateKind};
+use sysinfo::{Networks, Pid, ProcessRefreshKind, RefreshKind, System, UpdateKind};
 
 #[cfg(target_os = "windows")]
 use std::os::windows::process::CommandExt;
@@ -25,48 +32,204 @@ use std::os::windows::io::AsRawHandle;
 #[cfg(target_os = "windows")]
 use windows_sys::Win32::Foundation::CloseHandle;
 #[cfg(target_os = "windows")]
+use windows_sys::Win32::System::Console::{
+    AttachConsole, FreeConsole, GenerateConsoleCtrlEvent, SetConsoleCtrlHandler,
+    CTRL_BREAK_EVENT,
+};
+#[cfg(target_os = "windows")]
 use windows_sys::Win32::System::JobObjects::{
     AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject,
     JobObjectExtendedLimitInformation, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
     JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
 };
 
+#[cfg(feature = "clash")]
+mod clash;
+#[cfg(all(test, feature = "clash"))]
+mod clash_test;
+#[cfg(test)]
+mod config_test;
+#[cfg(test)]
+mod import_test;
+mod launch;
+#[cfg(test)]
+mod launch_test;
+#[cfg(test)]
+mod log_test;
+#[cfg(test)]
+mod node_test;
+pub(crate) mod parse;
+#[cfg(test)]
+mod parse_test;
+#[cfg(test)]
+mod process_test;
+#[cfg(feature = "encryption")]
+mod profile_crypto;
+#[cfg(all(test, feature = "encryption"))]
+mod profile_crypto_test;
+#[cfg(test)]
+mod state_test;
+#[cfg(test)]
+mod test_util;
+#[cfg(test)]
+mod watch_test;
+#[cfg(test)]
+mod window_test;
+
 #[cfg(target_os = "windows")]
 const CREATE_NO_WINDOW: u32 = 0x08000000;
+#[cfg(target_os = "windows")]
+const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+const GRACEFUL_STOP_TIMEOUT: Duration = Duration::from_secs(3);
 
 const PROFILE_FILE: &str = "profile.json";
 const PROFILE_STATE_FILE: &str = "profile.state.json";
+const PROFILE_GROUPS_FILE: &str = "profiles.index.json";
+const PROFILE_GROUPS_DIR: &str = "profiles";
+const BACKUP_DIR: &str = "backups";
+const BACKUP_KEEP: usize = 10;
+const BACKUP_META_FILE: &str = "backup.json";
+const DEFAULT_PROFILE_GROUP: &str = "default";
+const DEFAULT_RULE_PRESET: &str = "default";
 const APP_STATE_FILE: &str = "app.state.json";
 const CONFIG_FILE: &str = "singbox.generated.json";
+const DRY_RUN_CONFIG_PREFIX: &str = "singbox.dry-run";
+const PROBE_CONFIG_PREFIX: &str = "singbox.probe";
 const LOG_FILE: &str = "singbox.log";
+const PID_FILE: &str = "singbox.pid";
+const TEMPLATE_FILE: &str = "template.json";
+const WINDOW_STATE_FILE: &str = "window.state.json";
+const MIN_VISIBLE_WINDOW_PX: i64 = 64;
+const STOP_FILE: &str = "singbox.stop";
+const STARTED_FILE: &str = "singbox.started";
+const PROFILE_WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
 const BIN_DIR: &str = "bin";
 const RULE_SET_DIR: &str = "rule-sets";
+#[cfg(target_os = "windows")]
 const SINGBOX_EXE: &str = "sing-box.exe";
+#[cfg(not(target_os = "windows"))]
+const SINGBOX_EXE: &str = "sing-box";
 const LOG_MAX_BYTES: u64 = 8 * 1024 * 1024;
 const LOG_KEEP_BYTES: u64 = 6 * 1024 * 1024;
+const MIN_LOG_MAX_BYTES: u64 = 1024 * 1024;
+const MIN_LOG_KEEP_BYTES: u64 = 256 * 1024;
+const MAX_LOG_ROTATE_COUNT: u32 = 10;
 const LOCAL_PROXY_HOST: &str = "127.0.0.1";
+const TUN_INET4_ADDRESS: &str = "172.19.0.1/30";
+const TUN_INTERFACE_NAME: &str = "YotsubaCore";
+const TUN_INET6_ADDRESS: &str = "fdfe:dcba:9876::1/126";
+const TUN_INET4_PREFIX_RANGE: std::ops::RangeInclusive<u8> = 8..=30;
+const TUN_INET6_PREFIX_RANGE: std::ops::RangeInclusive<u8> = 64..=126;
+const LAN_PROXY_HOST: &str = "0.0.0.0";
+const PROXY_URL_RESERVED: [char; 4] = ['@', ':', '/', '#'];
 const LOCAL_PROXY_PORT: u16 = 2080;
 const LOCAL_PROXY_TAG: &str = "local-proxy";
+const FAKE_IP_DNS_TAG: &str = "dns-fake";
+const FAKE_IP_INET4_RANGE: &str = "198.18.0.0/15";
+const FAKE_IP_INET6_RANGE: &str = "fc00::/18";
+const SINGBOX_LOG_LEVELS: [&str; 5] = ["trace", "debug", "info", "warn", "error"];
+const IMPORT_PROGRESS_BATCH: usize = 50;
+const UTLS_FINGERPRINTS: [&str; 10] = [
+    "chrome",
+    "firefox",
+    "edge",
+    "safari",
+    "360",
+    "qq",
+    "ios",
+    "android",
+    "random",
+    "randomized",
+];
+const QUIC_OUTBOUND_TYPES: [&str; 3] = ["hysteria", "hysteria2", "tuic"];
+const DNS_STRATEGIES: [&str; 4] = ["prefer_ipv4", "prefer_ipv6", "ipv4_only", "ipv6_only"];
 const RU_IPV4_DOMAIN_SUFFIXES: [&str; 4] = [".ru", ".su", ".xn--p1ai", ".yandex.net"];
+const SYSTEM_PATH_PREFIXES: [&str; 9] = [
+    "/windows/",
+    "/programdata/microsoft/",
+    "/system/",
+    "/usr/lib/",
+    "/usr/libexec/",
+    "/usr/sbin/",
+    "/lib/",
+    "/sbin/",
+    "/snap/core",
+];
 const AUTOSTART_ARG: &str = "--autostart";
+const MAX_AUTOSTART_DELAY_SECS: u64 = 300;
 const TRAY_OPEN_ID: &str = "tray-open";
 const TRAY_EXIT_ID: &str = "tray-exit";
+const TRAY_MODE_OFF_ID: &str = "tray-mode-off";
+const TRAY_MODE_SELECTED_ID: &str = "tray-mode-selected";
+const TRAY_MODE_FULL_ID: &str = "tray-mode-full";
+const TRAY_NODE_PREFIX: &str = "tray-node:";
+const PROFILE_EXPORT_VERSION: u64 = 1;
+const SCHEMA_VERSION: u32 = 2;
+const PROFILE_ENCRYPTED_PREFIX: &str = "YCENC1:";
+const FAST_EXIT_WINDOW: Duration = Duration::from_secs(2);
+const CRASH_LOOP_LIMIT: u32 = 3;
+const CRASH_LOOP_COOLDOWN: Duration = Duration::from_secs(30);
+const CRASH_LOOP_LOG_LINES: usize = 20;
+const RULE_SET_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+const RULE_SET_MAX_BYTES: u64 = 32 * 1024 * 1024;
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(8);
+const ELEVATION_PROMPT_TIMEOUT: Duration = Duration::from_secs(300);
+const STARTUP_LOG_LINES: usize = 20;
+const EXIT_LOG_LINES: usize = 20;
+const CHECK_OUTPUT_LINES: usize = 10;
 const GEOIP_RU_TAG: &str = "geoip-ru";
 const GEOIP_RU_FILE: &str = "geoip-ru.srs";
+const GEOIP_UPDATE_INTERVAL: &str = "72h";
 const GEOIP_RU_URL: &str =
     "https://raw.githubusercontent.com/SagerNet/sing-geoip/rule-set/geoip-ru.srs";
-
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+const URL_TEST_INTERVAL: &str = "3m";
+const CLASH_API_TIMEOUT: Duration = Duration::from_secs(3);
+const LATENCY_TEST_CONCURRENCY: usize = 8;
+const TCP_PING_ATTEMPTS: usize = 4;
+const OTHER_GROUP: &str = "Other";
+const GROUP_SEPARATORS: [char; 6] = ['-', '_', '|', ' ', '·', '/'];
+const GROUP_OUTBOUND_TYPES_SKIPPED: [&str; 5] = ["selector", "urltest", "direct", "block", "dns"];
+const MAX_TCP_PING_ATTEMPTS: usize = 20;
+const DEFAULT_TEST_URL: &str = "http://www.gstatic.com/generate_204";
+const COUNTRY_LOOKUP_URL: &str = "https://ipwho.is/";
+const NODE_COUNTRY_FIELD: &str = "yotsuba_country";
+const NODE_DNS_SERVER_FIELD: &str = "yotsuba_dns_server";
+const DEFAULT_TEST_TIMEOUT_MS: u64 = 5000;
+const MIN_TEST_TIMEOUT_MS: u64 = 500;
+const MAX_TEST_TIMEOUT_MS: u64 = 30000;
+const STATE_READ_ATTEMPTS: u32 = 3;
+const STATE_READ_RETRY: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 enum ProxyMode {
+    #[default]
     Off,
     Selected,
     Full,
 }
 
-impl Default for ProxyMode {
-    fn default() -> Self {
-        Self::Off
+impl ProxyMode {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Off => "Выключен",
+            Self::Selected => "Выборочный",
+            Self::Full => "Полный",
+        }
+    }
+
+    fn tray_id(self) -> &'static str {
+        match self {
+            Self::Off => TRAY_MODE_OFF_ID,
+            Self::Selected => TRAY_MODE_SELECTED_ID,
+            Self::Full => TRAY_MODE_FULL_ID,
+        }
+    }
+
+    fn from_tray_id(id: &str) -> Option<Self> {
+        [Self::Off, Self::Selected, Self::Full]
+            .into_iter()
+            .find(|mode| mode.tray_id() == id)
     }
 }
 
@@ -75,6 +238,33 @@ impl Default for ProxyMode {
 enum AppRuleMode {
     Proxy,
     Direct,
+    /// Routed `direct` ahead of the DNS hijack and sniff rules, so no other rule touches it.
+    Exclude,
+}
+
+/// Where Selected mode sends traffic no rule matched.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum FinalOutbound {
+    Proxy,
+    Direct,
+}
+
+impl FinalOutbound {
+    fn outbound_tag(self) -> &'static str {
+        match self {
+            Self::Proxy => "proxy",
+            Self::Direct => "direct",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+enum SelectorChange {
+    NoChange,
+    ProxyRenamed,
+    SelectorSynthesized,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -92,11 +282,45 @@ struct ProxyState {
     last_error: Option<String>,
     config_path: Option<PathBuf>,
     watch_token: u64,
+    started_at: Option<Instant>,
+    settings: ProxySettings,
+    selector_change: Option<SelectorChange>,
+    fast_exits: u32,
+    rule_conflicts: Vec<String>,
+    cooldown_until: Option<Instant>,
+    elevation: Elevation,
     #[cfg(target_os = "windows")]
     job: Option<JobHandle>,
 }
 
-#[derive(Serialize)]
+impl ProxyState {
+    fn record_exit(&mut self, now: Instant) {
+        let Some(started_at) = self.started_at.take() else {
+            return;
+        };
+        if now.saturating_duration_since(started_at) < FAST_EXIT_WINDOW {
+            self.fast_exits += 1;
+            if self.fast_exits >= CRASH_LOOP_LIMIT {
+                self.cooldown_until = Some(now + CRASH_LOOP_COOLDOWN);
+            }
+        } else {
+            self.fast_exits = 0;
+            self.cooldown_until = None;
+        }
+    }
+
+    fn crash_loop_remaining(&mut self, now: Instant) -> Option<Duration> {
+        let until = self.cooldown_until?;
+        if now < until {
+            return Some(until - now);
+        }
+        self.cooldown_until = None;
+        self.fast_exits = 0;
+        None
+    }
+}
+
+#[derive(Serialize, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 struct ProxyStatus {
     running: bool,
@@ -107,6 +331,20 @@ struct ProxyStatus {
     config_path: Option<String>,
     profile_path: String,
     log_path: Option<String>,
+    listen_address: Option<String>,
+    ipv6: Option<bool>,
+    selected_final: Option<FinalOutbound>,
+    selector_change: Option<SelectorChange>,
+    tun_addresses: Vec<String>,
+    rule_conflicts: Vec<String>,
+    singbox_version: Option<String>,
+    test_url: Option<String>,
+    test_timeout_ms: Option<u64>,
+    log_max_bytes: Option<u64>,
+    log_keep_bytes: Option<u64>,
+    hijack_dns: Option<bool>,
+    hijack_dns_proxy_only: Option<bool>,
+    warnings: Vec<String>,
 }
 
 #[derive(Serialize, Clone)]
@@ -120,6 +358,37 @@ struct LogBatchPayload {
     lines: Vec<String>,
 }
 
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct LogEntry {
+    level: Option<LogLevel>,
+    ts: Option<String>,
+    msg: String,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct LogEntryBatchPayload {
+    entries: Vec<LogEntry>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ConfigGeneratedPayload {
+    path: String,
+    config: Option<Value>,
+}
+
+impl ConfigGeneratedPayload {
+    /// The config itself is only attached when the settings ask for it.
+    fn new(path: &Path, config: Value, settings: &ProxySettings) -> Self {
+        Self {
+            path: path.to_string_lossy().to_string(),
+            config: settings.config_event_json.then_some(config),
+        }
+    }
+}
+
 #[derive(Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 struct ProcessEntry {
@@ -127,1900 +396,5507 @@ struct ProcessEntry {
     path: String,
     count: usize,
     pids: Vec<u32>,
+    cpu: f32,
+    memory: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ProcessSort {
+    #[default]
+    Name,
+    Cpu,
+    Memory,
+}
+
+impl ProcessSort {
+    fn compare(self, a: &ProcessEntry, b: &ProcessEntry) -> std::cmp::Ordering {
+        let by_name = a
+            .name
+            .to_lowercase()
+            .cmp(&b.name.to_lowercase())
+            .then(a.path.cmp(&b.path));
+        match self {
+            ProcessSort::Name => by_name,
+            ProcessSort::Cpu => b.cpu.total_cmp(&a.cpu).then(by_name),
+            ProcessSort::Memory => b.memory.cmp(&a.memory).then(by_name),
+        }
+    }
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct ProfileState {
+    #[serde(default)]
+    schema_version: u32,
     active_tag: Option<String>,
+    #[serde(default)]
+    active_tag_by_mode: HashMap<ProxyMode, String>,
+}
+
+impl Default for ProfileState {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            active_tag: None,
+            active_tag_by_mode: HashMap::new(),
+        }
+    }
+}
+
+impl ProfileState {
+    fn active_tag_for(&self, mode: ProxyMode) -> Option<String> {
+        self.active_tag_by_mode
+            .get(&mode)
+            .cloned()
+            .or_else(|| self.active_tag.clone())
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase", default)]
 struct AppState {
+    #[serde(default)]
+    schema_version: u32,
     last_mode: ProxyMode,
-    app_rules: Vec<AppRule>,
     force_ipv4_ru: bool,
+    settings: ProxySettings,
+    /// The app rules in use are always the active preset's; a flat `appRules` list
+    /// from before presets is only read by `migrate_state`.
+    rule_presets: Vec<RulePreset>,
+    active_preset: String,
 }
 
 impl Default for AppState {
     fn default() -> Self {
         Self {
+            schema_version: SCHEMA_VERSION,
             last_mode: ProxyMode::default(),
-            app_rules: Vec::new(),
             force_ipv4_ru: true,
+            settings: ProxySettings::default(),
+            rule_presets: vec![RulePreset {
+                name: DEFAULT_RULE_PRESET.to_string(),
+                rules: Vec::new(),
+            }],
+            active_preset: DEFAULT_RULE_PRESET.to_string(),
+        }
+    }
+}
+
+impl AppState {
+    fn store_rules(&mut self, rules: Vec<AppRule>) {
+        match self
+            .rule_presets
+            .iter_mut()
+            .find(|preset| preset.name == self.active_preset)
+        {
+            Some(preset) => preset.rules = rules,
+            None => self.rule_presets.push(RulePreset {
+                name: self.active_preset.clone(),
+                rules,
+            }),
+        }
+    }
+
+    /// Records a `set_mode` request, which `start_proxy` later resumes. `stop_proxy`
+    /// never calls this, so the last mode survives a stop.
+    fn remember_request(&mut self, mode: ProxyMode, rules: Vec<AppRule>, force_ipv4_ru: bool) {
+        self.last_mode = mode;
+        self.store_rules(rules);
+        self.force_ipv4_ru = force_ipv4_ru;
+    }
+
+    fn active_rules(&self) -> Vec<AppRule> {
+        self.rule_presets
+            .iter()
+            .find(|preset| preset.name == self.active_preset)
+            .map(|preset| preset.rules.clone())
+            .unwrap_or_default()
+    }
+
+    /// Makes `name` the active preset. Returns false when it already was.
+    fn select_preset(&mut self, name: String) -> Result<bool, AppError> {
+        if !self.rule_presets.iter().any(|preset| preset.name == name) {
+            return Err(err("PRESET_MISSING", name));
+        }
+        if self.active_preset == name {
+            return Ok(false);
+        }
+        self.active_preset = name;
+        Ok(true)
+    }
+
+    fn rule_presets(&self) -> RulePresets {
+        RulePresets {
+            active: self.active_preset.clone(),
+            presets: self.rule_presets.clone(),
         }
     }
 }
 
+/// The saved state as the frontend reads it, with the active preset's rules spelled out.
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
-struct ProfileData {
-    outbounds: Vec<Value>,
-    active_tag: Option<String>,
+struct SavedState {
+    #[serde(flatten)]
+    state: AppState,
+    app_rules: Vec<AppRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RulePreset {
+    name: String,
+    rules: Vec<AppRule>,
 }
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
-struct ImportResult {
-    profile: ProfileData,
-    added: usize,
-    errors: Vec<String>,
+struct RulePresets {
+    active: String,
+    presets: Vec<RulePreset>,
 }
 
-type SharedState = Arc<Mutex<ProxyState>>;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct ProxySettings {
+    allow_lan: bool,
+    mixed_username: Option<String>,
+    mixed_password: Option<String>,
+    mixed_auth_required: bool,
+    dns_servers: Vec<DnsServer>,
+    dns_strategy: Option<String>,
+    dns_final: Option<String>,
+    direct_dns: Option<String>,
+    group_pattern: Option<String>,
+    fake_ip: bool,
+    override_route: bool,
+    ipv6: bool,
+    log_level: String,
+    selected_final: FinalOutbound,
+    default_fingerprint: String,
+    ss_udp_over_tcp: bool,
+    autostart_delay_secs: u64,
+    start_minimized: bool,
+    geoip_ru_url: String,
+    geoip_update_interval: String,
+    encrypt_profile: bool,
+    allow_direct_selection: bool,
+    tun_inet4_address: String,
+    tun_inet6_address: String,
+    test_url: String,
+    test_timeout_ms: u64,
+    auto_select: bool,
+    url_test_interval: String,
+    log_max_bytes: u64,
+    log_keep_bytes: u64,
+    log_rotate_count: u32,
+    structured_logs: bool,
+    config_event_json: bool,
+    tun_enabled: bool,
+    hijack_dns: bool,
+    hijack_dns_proxy_only: bool,
+}
 
-#[derive(Default)]
-struct ExitFlag(AtomicBool);
+impl ProxySettings {
+    fn test_timeout(&self) -> Duration {
+        Duration::from_millis(
+            self.test_timeout_ms
+                .clamp(MIN_TEST_TIMEOUT_MS, MAX_TEST_TIMEOUT_MS),
+        )
+    }
 
-impl ExitFlag {
-    fn allow_exit(&self) {
-        self.0.store(true, Ordering::SeqCst);
+    fn autostart_delay(&self) -> Duration {
+        Duration::from_secs(self.autostart_delay_secs.min(MAX_AUTOSTART_DELAY_SECS))
     }
 
-    fn is_allowed(&self) -> bool {
-        self.0.load(Ordering::SeqCst)
+    /// Address of the running mixed inbound as a `ureq` proxy, with its credentials if any.
+    fn mixed_proxy_url(&self) -> String {
+        let credentials = match (&self.mixed_username, &self.mixed_password) {
+            (Some(username), Some(password)) if self.mixed_users().is_some() => {
+                format!("{username}:{password}@")
+            }
+            _ => String::new(),
+        };
+        format!("http://{credentials}{LOCAL_PROXY_HOST}:{LOCAL_PROXY_PORT}")
+    }
+
+    fn mixed_users(&self) -> Option<Value> {
+        if !self.allow_lan {
+            return None;
+        }
+        let username = self
+            .mixed_username
+            .as_deref()
+            .filter(|value| !value.is_empty())?;
+        let password = self
+            .mixed_password
+            .as_deref()
+            .filter(|value| !value.is_empty())?;
+        Some(json!([{
+            "username": username,
+            "password": password
+        }]))
+    }
+
+    fn tun_addresses(&self) -> Vec<String> {
+        if !self.tun_enabled {
+            return Vec::new();
+        }
+        let mut addresses = vec![self.tun_inet4_address.trim().to_string()];
+        if self.ipv6 {
+            addresses.push(self.tun_inet6_address.trim().to_string());
+        }
+        addresses
     }
 }
 
-struct TrayState {
-    _tray: tauri::tray::TrayIcon,
+impl Default for ProxySettings {
+    fn default() -> Self {
+        Self {
+            allow_lan: false,
+            mixed_username: None,
+            mixed_password: None,
+            mixed_auth_required: false,
+            dns_servers: Vec::new(),
+            dns_strategy: None,
+            dns_final: None,
+            direct_dns: None,
+            group_pattern: None,
+            fake_ip: false,
+            override_route: false,
+            ipv6: true,
+            log_level: "info".to_string(),
+            selected_final: FinalOutbound::Direct,
+            default_fingerprint: String::new(),
+            ss_udp_over_tcp: false,
+            autostart_delay_secs: 0,
+            start_minimized: false,
+            geoip_ru_url: GEOIP_RU_URL.to_string(),
+            geoip_update_interval: GEOIP_UPDATE_INTERVAL.to_string(),
+            encrypt_profile: false,
+            allow_direct_selection: false,
+            tun_inet4_address: TUN_INET4_ADDRESS.to_string(),
+            tun_inet6_address: TUN_INET6_ADDRESS.to_string(),
+            test_url: DEFAULT_TEST_URL.to_string(),
+            test_timeout_ms: DEFAULT_TEST_TIMEOUT_MS,
+            auto_select: false,
+            url_test_interval: URL_TEST_INTERVAL.to_string(),
+            log_max_bytes: LOG_MAX_BYTES,
+            log_keep_bytes: LOG_KEEP_BYTES,
+            log_rotate_count: 0,
+            structured_logs: false,
+            config_event_json: false,
+            tun_enabled: true,
+            hijack_dns: true,
+            hijack_dns_proxy_only: false,
+        }
+    }
 }
 
-#[cfg(target_os = "windows")]
-#[derive(Debug)]
-struct JobHandle(isize);
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DnsServer {
+    tag: String,
+    #[serde(rename = "type")]
+    kind: String,
+    server: Option<String>,
+    detour: Option<String>,
+}
 
-#[cfg(target_os = "windows")]
-impl Drop for JobHandle {
-    fn drop(&mut self) {
-        unsafe {
-            CloseHandle(self.0);
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct ProfileGroups {
+    active: String,
+    groups: Vec<String>,
+}
+
+impl Default for ProfileGroups {
+    fn default() -> Self {
+        Self {
+            active: DEFAULT_PROFILE_GROUP.to_string(),
+            groups: vec![DEFAULT_PROFILE_GROUP.to_string()],
         }
     }
 }
 
-#[cfg(target_os = "windows")]
-fn create_job_object() -> Result<JobHandle, String> {
-    let handle = unsafe { CreateJobObjectW(std::ptr::null_mut(), std::ptr::null()) };
-    if handle == 0 {
-        return Err(err(
-            "JOB_ERROR",
-            std::io::Error::last_os_error().to_string(),
-        ));
+impl ProfileGroups {
+    fn add(&mut self, name: String) -> Result<(), AppError> {
+        if self
+            .groups
+            .iter()
+            .any(|item| item.eq_ignore_ascii_case(&name))
+        {
+            return Err(err("GROUP_EXISTS", name));
+        }
+        self.groups.push(name);
+        Ok(())
     }
-    let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
-    info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
-    let result = unsafe {
-        SetInformationJobObject(
-            handle,
-            JobObjectExtendedLimitInformation,
-            &mut info as *mut _ as *mut _,
-            std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
-        )
-    };
-    if result == 0 {
-        unsafe {
-            CloseHandle(handle);
+
+    /// Makes `name` active and returns the group it replaced, or `None` if it already was.
+    fn switch_to(&mut self, name: String) -> Result<Option<String>, AppError> {
+        if !self.groups.contains(&name) {
+            return Err(err("GROUP_MISSING", name));
         }
-        return Err(err(
-            "JOB_ERROR",
-            std::io::Error::last_os_error().to_string(),
-        ));
+        if self.active == name {
+            return Ok(None);
+        }
+        Ok(Some(std::mem::replace(&mut self.active, name)))
+    }
+
+    fn remove(&mut self, name: &str) -> Result<(), AppError> {
+        if !self.groups.iter().any(|item| item == name) {
+            return Err(err("GROUP_MISSING", name));
+        }
+        if self.active == name {
+            return Err(err("GROUP_ACTIVE", name));
+        }
+        if name == DEFAULT_PROFILE_GROUP {
+            return Err(err("GROUP_INVALID", "default group cannot be deleted"));
+        }
+        self.groups.retain(|item| item != name);
+        Ok(())
     }
-    Ok(JobHandle(handle))
 }
 
-fn err(tag: &str, detail: impl AsRef<str>) -> String {
-    format!("{tag}|{}", detail.as_ref())
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileData {
+    outbounds: Vec<Value>,
+    active_tag: Option<String>,
+    active_tag_by_mode: HashMap<ProxyMode, String>,
 }
 
-fn ensure_app_data_dir(app: &AppHandle) -> Result<PathBuf, String> {
-    let dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| err("PATH_ERROR", e.to_string()))?;
-    fs::create_dir_all(&dir).map_err(|e| err("PATH_ERROR", e.to_string()))?;
-    Ok(dir)
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileExport {
+    version: u64,
+    profile: Value,
+    active_tag: Option<String>,
+    #[serde(default)]
+    active_tag_by_mode: HashMap<ProxyMode, String>,
+    app_rules: Vec<AppRule>,
 }
 
-fn resolve_profile_path(app: &AppHandle) -> Result<PathBuf, String> {
-    Ok(ensure_app_data_dir(app)?.join(PROFILE_FILE))
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportResult {
+    profile: ProfileData,
+    added: usize,
+    added_tags: Vec<String>,
+    errors: Vec<String>,
+    duplicates: Vec<String>,
 }
 
-fn resolve_profile_state_path(app: &AppHandle) -> Result<PathBuf, String> {
-    Ok(ensure_app_data_dir(app)?.join(PROFILE_STATE_FILE))
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BackupMeta {
+    group: String,
 }
 
-fn resolve_app_state_path(app: &AppHandle) -> Result<PathBuf, String> {
-    Ok(ensure_app_data_dir(app)?.join(APP_STATE_FILE))
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResetResult {
+    backup: String,
+    profile: ProfileData,
 }
 
-fn resolve_config_path(app: &AppHandle) -> Result<PathBuf, String> {
-    Ok(ensure_app_data_dir(app)?.join(CONFIG_FILE))
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RuleSetDownload {
+    written: Vec<String>,
+    errors: Vec<String>,
 }
 
-fn resolve_log_path(app: &AppHandle) -> Result<PathBuf, String> {
-    Ok(ensure_app_data_dir(app)?.join(LOG_FILE))
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct LatencyResult {
+    tag: String,
+    delay: Option<u64>,
 }
 
-fn resolve_rule_set_dir(app: &AppHandle) -> Result<PathBuf, String> {
-    let dir = ensure_app_data_dir(app)?.join(RULE_SET_DIR);
-    fs::create_dir_all(&dir).map_err(|e| err("PATH_ERROR", e.to_string()))?;
-    Ok(dir)
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ProbeResult {
+    tag: String,
+    ok: bool,
+    latency_ms: Option<u64>,
+    error: Option<String>,
 }
 
-fn resolve_rule_set_path(app: &AppHandle, name: &str) -> Result<PathBuf, String> {
-    Ok(resolve_rule_set_dir(app)?.join(name))
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ModeValidation {
+    mode: ProxyMode,
+    ok: bool,
+    error: Option<AppError>,
 }
 
-fn default_profile() -> Value {
-    json!({
-        "outbounds": [
-            {
-                "type": "socks",
-                "tag": "proxy",
-                "server": "example.com",
-                "server_port": 1080
-            },
-            {
-                "type": "direct",
-                "tag": "direct"
-            }
-        ]
-    })
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct OutboundGroup {
+    name: String,
+    tags: Vec<String>,
 }
 
-fn ensure_profile(app: &AppHandle) -> Result<(Value, PathBuf), String> {
-    let profile_path = resolve_profile_path(app)?;
-    if !profile_path.exists() {
-        let content = serde_json::to_string_pretty(&default_profile())
-            .map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
-        fs::write(&profile_path, content)
-            .map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
-        return Err(err("PROFILE_MISSING", profile_path.display().to_string()));
-    }
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct PingResult {
+    tag: String,
+    address: String,
+    sent: usize,
+    received: usize,
+    min_ms: Option<u64>,
+    avg_ms: Option<u64>,
+    max_ms: Option<u64>,
+    loss_percent: f64,
+}
 
-    let raw = fs::read_to_string(&profile_path)
-        .map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
-    let value: Value =
-        serde_json::from_str(&raw).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
-    Ok((value, profile_path))
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct TunnelInfo {
+    interface_name: String,
+    addresses: Vec<String>,
+    auto_route: bool,
+    interface_up: bool,
+    received_bytes: u64,
+    transmitted_bytes: u64,
 }
 
-fn load_profile_json(app: &AppHandle) -> Result<Value, String> {
-    match ensure_profile(app) {
-        Ok((value, _)) => Ok(value),
-        Err(message) if message.starts_with("PROFILE_MISSING|") => {
-            let profile_path = resolve_profile_path(app)?;
-            let raw = fs::read_to_string(&profile_path)
-                .map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
-            let value: Value =
-                serde_json::from_str(&raw).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
-            Ok(value)
-        }
-        Err(message) => Err(message),
-    }
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ImportProgress {
+    parsed: usize,
+    total: usize,
+    failed: usize,
 }
 
-fn save_profile_json(app: &AppHandle, profile: &Value) -> Result<(), String> {
-    let profile_path = resolve_profile_path(app)?;
-    let content =
-        serde_json::to_string_pretty(profile).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
-    fs::write(&profile_path, content).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
-    Ok(())
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PreviewResult {
+    input: String,
+    outbound: Option<Value>,
+    error: Option<AppError>,
 }
 
-fn load_profile_state(app: &AppHandle) -> ProfileState {
-    let path = match resolve_profile_state_path(app) {
-        Ok(path) => path,
-        Err(_) => return ProfileState::default(),
-    };
-    if !path.exists() {
-        return ProfileState::default();
+type SharedState = Arc<Mutex<ProxyState>>;
+
+#[derive(Default)]
+struct ExitFlag(AtomicBool);
+
+impl ExitFlag {
+    fn allow_exit(&self) {
+        self.0.store(true, Ordering::SeqCst);
     }
-    let raw = match fs::read_to_string(&path) {
-        Ok(value) => value,
-        Err(_) => return ProfileState::default(),
-    };
-    serde_json::from_str(&raw).unwrap_or_default()
-}
 
-fn save_profile_state(app: &AppHandle, state: &ProfileState) -> Result<(), String> {
-    let path = resolve_profile_state_path(app)?;
-    let content =
-        serde_json::to_string_pretty(state).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
-    fs::write(&path, content).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
-    Ok(())
+    fn is_allowed(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
 }
 
-fn load_app_state(app: &AppHandle) -> AppState {
-    let path = match resolve_app_state_path(app) {
-        Ok(path) => path,
-        Err(_) => return AppState::default(),
-    };
-    if !path.exists() {
-        return AppState::default();
+#[derive(Default)]
+struct LogStream(AtomicBool);
+
+impl LogStream {
+    fn set_paused(&self, paused: bool) {
+        self.0.store(paused, Ordering::SeqCst);
+    }
+
+    fn is_paused(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
     }
-    let raw = match fs::read_to_string(&path) {
-        Ok(value) => value,
-        Err(_) => return AppState::default(),
-    };
-    serde_json::from_str(&raw).unwrap_or_default()
 }
 
-fn save_app_state(app: &AppHandle, state: &AppState) -> Result<(), String> {
-    let path = resolve_app_state_path(app)?;
-    let content =
-        serde_json::to_string_pretty(state).map_err(|e| err("STATE_INVALID", e.to_string()))?;
-    fs::write(&path, content).map_err(|e| err("STATE_INVALID", e.to_string()))?;
-    Ok(())
+struct TrayState {
+    tray: tauri::tray::TrayIcon,
 }
 
-fn profile_data(app: &AppHandle, profile: &Value) -> ProfileData {
-    let outbounds = profile
-        .get("outbounds")
-        .and_then(Value::as_array)
-        .cloned()
-        .unwrap_or_default();
-    let state = load_profile_state(app);
-    ProfileData {
-        outbounds,
-        active_tag: state.active_tag,
+struct ClashApi {
+    secret: String,
+    endpoint: Mutex<Option<ClashEndpoint>>,
+}
+
+#[derive(Clone)]
+struct ClashEndpoint {
+    /// `external_controller` exactly as written into the config.
+    controller: String,
+    base: String,
+    secret: String,
+}
+
+impl Default for ClashApi {
+    fn default() -> Self {
+        let secret = (0..2)
+            .map(|_| format!("{:016x}", RandomState::new().build_hasher().finish()))
+            .collect();
+        Self {
+            secret,
+            endpoint: Mutex::new(None),
+        }
     }
 }
 
-fn ensure_singbox_exe(app: &AppHandle) -> Result<PathBuf, String> {
-    let resource_dir = app
-        .path()
-        .resource_dir()
-        .map_err(|e| err("PATH_ERROR", e.to_string()))?;
+impl ClashApi {
+    // A profile or template may bring its own clash_api, so the address and secret are read
+    // back from the config that sing-box is started with.
+    fn remember(&self, config: &Value) {
+        let clash_api = config.pointer("/experimental/clash_api");
+        let endpoint = clash_api.and_then(|clash_api| {
+            let controller = clash_api
+                .get("external_controller")
+                .and_then(Value::as_str)?;
+            let (host, port) = controller.trim().rsplit_once(':')?;
+            let host = match host.trim_matches(['[', ']']) {
+                "" | "0.0.0.0" | "::" => LOCAL_PROXY_HOST.to_string(),
+                host if host.contains(':') => format!("[{host}]"),
+                host => host.to_string(),
+            };
+            Some(ClashEndpoint {
+                controller: controller.to_string(),
+                base: format!("http://{host}:{port}"),
+                secret: clash_api
+                    .get("secret")
+                    .and_then(Value::as_str)
+                    .unwrap_or("")
+                    .to_string(),
+            })
+        });
+        *self.endpoint.lock().expect("clash api lock") = endpoint;
+    }
 
-    let candidates = [
-        resource_dir.join(SINGBOX_EXE),
-        resource_dir.join("resources").join(SINGBOX_EXE),
-    ];
+    fn controller(&self) -> Option<String> {
+        self.endpoint
+            .lock()
+            .expect("clash api lock")
+            .as_ref()
+            .map(|endpoint| endpoint.controller.clone())
+    }
 
-    let resource_path = candidates
-        .iter()
-        .find(|path| path.exists())
-        .cloned()
-        .ok_or_else(|| err("SINGBOX_MISSING", candidates[0].display().to_string()))?;
+    fn endpoint(&self) -> Result<ClashEndpoint, AppError> {
+        self.endpoint
+            .lock()
+            .expect("clash api lock")
+            .clone()
+            .ok_or_else(|| err("CLASH_API_ERROR", "clash api is not configured"))
+    }
+}
 
-    let bin_dir = ensure_app_data_dir(app)?.join(BIN_DIR);
-    fs::create_dir_all(&bin_dir).map_err(|e| err("PATH_ERROR", e.to_string()))?;
-    let target_path = bin_dir.join(SINGBOX_EXE);
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct StateRecovered {
+    file: String,
+    backup: String,
+}
 
-    let copy_needed = match (fs::metadata(&resource_path), fs::metadata(&target_path)) {
-        (Ok(src_meta), Ok(dst_meta)) => src_meta.len() != dst_meta.len(),
-        _ => true,
-    };
+#[derive(Default)]
+struct StateRecoveries(Mutex<Vec<StateRecovered>>);
 
-    if copy_needed {
-        fs::copy(&resource_path, &target_path)
-            .map_err(|e| err("SINGBOX_MISSING", e.to_string()))?;
+impl StateRecoveries {
+    fn push(&self, recovered: StateRecovered) {
+        self.0.lock().expect("recoveries lock").push(recovered);
     }
 
-    Ok(target_path)
+    fn take(&self) -> Vec<StateRecovered> {
+        std::mem::take(&mut *self.0.lock().expect("recoveries lock"))
+    }
 }
 
-fn is_process_name(value: &str) -> bool {
-    let trimmed = value.trim().trim_matches('"');
-    if trimmed.is_empty() {
-        return false;
-    }
-    if trimmed.contains('\\') || trimmed.contains('/') {
-        return false;
+#[derive(Default)]
+struct SingboxVersion(Mutex<Option<String>>);
+
+impl SingboxVersion {
+    /// Asks the binary when no version is known yet. A failed query is not cached,
+    /// so a binary that shows up later is still picked up.
+    fn get(&self, app: &AppHandle) -> Option<String> {
+        if let Some(version) = self.cached() {
+            return Some(version);
+        }
+        let version = query_singbox_version(app)?;
+        *self.0.lock().expect("singbox version lock") = Some(version.clone());
+        Some(version)
     }
-    if trimmed.contains(':') {
-        return false;
+
+    /// The version read so far, without running sing-box.
+    fn cached(&self) -> Option<String> {
+        self.0.lock().expect("singbox version lock").clone()
     }
-    true
 }
 
-fn sort_dedup(values: &mut Vec<String>) {
-    values.sort();
-    values.dedup();
+#[derive(Default)]
+struct ProfileWatch {
+    last_written: Mutex<Option<String>>,
+    debouncer: Mutex<Option<Debouncer<RecommendedWatcher>>>,
 }
 
-fn normalize_rules(rules: Vec<AppRule>) -> (Vec<String>, Vec<String>, Vec<String>, Vec<String>) {
-    let mut proxy_paths: Vec<String> = Vec::new();
-    let mut direct_paths: Vec<String> = Vec::new();
-    let mut proxy_names: Vec<String> = Vec::new();
-    let mut direct_names: Vec<String> = Vec::new();
-    for rule in rules {
-        let path = rule.path.trim().trim_matches('"').to_string();
-        if path.is_empty() {
-            continue;
-        }
-        let is_name = is_process_name(&path);
-        match rule.mode {
-            AppRuleMode::Proxy => {
-                if is_name {
-                    proxy_names.push(path);
-                } else {
-                    proxy_paths.push(path);
-                }
-            }
-            AppRuleMode::Direct => {
-                if is_name {
-                    direct_names.push(path);
-                } else {
-                    direct_paths.push(path);
-                }
-            }
-        }
+impl ProfileWatch {
+    fn remember_write(&self, content: String) {
+        *self.last_written.lock().expect("profile watch lock") = Some(content);
     }
-    sort_dedup(&mut proxy_paths);
-    sort_dedup(&mut direct_paths);
-    sort_dedup(&mut proxy_names);
-    sort_dedup(&mut direct_names);
-    (proxy_paths, direct_paths, proxy_names, direct_names)
-}
 
-fn push_process_rules(
-    rules: &mut Vec<Value>,
-    paths: &[String],
-    names: &[String],
-    outbound: &str,
-) {
-    if !paths.is_empty() {
-        rules.push(json!({
-            "process_path": paths,
-            "outbound": outbound
-        }));
-    }
-    if !names.is_empty() {
-        rules.push(json!({
-            "process_name": names,
-            "outbound": outbound
-        }));
+    fn is_own_write(&self, content: &str) -> bool {
+        self.last_written
+            .lock()
+            .expect("profile watch lock")
+            .as_deref()
+            == Some(content)
     }
 }
 
-fn build_geoip_ru_rule_set(app: &AppHandle) -> Result<Value, String> {
-    let path = resolve_rule_set_path(app, GEOIP_RU_FILE)?;
-    if path.exists() {
-        Ok(json!({
-            "tag": GEOIP_RU_TAG,
-            "type": "local",
-            "format": "binary",
-            "path": path.display().to_string()
-        }))
-    } else {
-        Ok(json!({
-            "tag": GEOIP_RU_TAG,
-            "type": "remote",
-            "format": "binary",
-            "url": GEOIP_RU_URL,
-            "download_detour": "proxy",
-            "update_interval": "72h"
-        }))
+#[cfg(target_os = "windows")]
+#[derive(Debug)]
+struct JobHandle(isize);
+
+#[cfg(target_os = "windows")]
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0);
+        }
     }
 }
 
-fn push_ru_bypass_rules(rules: &mut Vec<Value>) {
-    rules.push(json!({
-        "domain_suffix": [".ru"],
-        "outbound": "direct"
-    }));
-    rules.push(json!({
-        "rule_set": [GEOIP_RU_TAG],
-        "outbound": "direct"
-    }));
+#[cfg(target_os = "windows")]
+fn send_ctrl_break(pid: u32) -> bool {
+    unsafe {
+        FreeConsole();
+        if AttachConsole(pid) == 0 {
+            return false;
+        }
+        SetConsoleCtrlHandler(None, 1);
+        let sent = GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid) != 0;
+        FreeConsole();
+        SetConsoleCtrlHandler(None, 0);
+        sent
+    }
 }
 
-fn build_config(
-    app: &AppHandle,
-    mode: ProxyMode,
-    rules: Vec<AppRule>,
-    force_ipv4_ru: bool,
-) -> Result<PathBuf, String> {
-    let (mut profile, _profile_path) = ensure_profile(app)?;
-    let log_path = resolve_log_path(app)?;
-
-    let profile_obj = profile
-        .as_object_mut()
-        .ok_or_else(|| err("PROFILE_INVALID", "root must be an object"))?;
-
-    let outbounds_value = profile_obj
-        .get("outbounds")
-        .cloned()
-        .ok_or_else(|| err("PROFILE_OUTBOUNDS_MISSING", "missing outbounds"))?;
+#[cfg(target_os = "windows")]
+fn stop_gracefully(child: &mut Child) -> bool {
+    send_ctrl_break(child.id()) && wait_for_exit(child, GRACEFUL_STOP_TIMEOUT)
+}
 
-    let mut outbounds = outbounds_value
-        .as_array()
-        .cloned()
-        .ok_or_else(|| err("PROFILE_INVALID", "outbounds must be an array"))?;
+#[cfg(not(target_os = "windows"))]
+fn stop_gracefully(_child: &mut Child) -> bool {
+    false
+}
 
-    let mut tags: Vec<String> = outbounds
-        .iter()
-        .filter_map(|item| item.get("tag").and_then(Value::as_str))
-        .map(|tag| tag.to_string())
-        .collect();
+fn wait_for_exit(child: &mut Child, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        match child.try_wait() {
+            Ok(Some(_)) => return true,
+            Ok(None) => std::thread::sleep(Duration::from_millis(100)),
+            Err(_) => return false,
+        }
+    }
+    false
+}
 
-    if tags.is_empty() {
-        return Err(err("PROFILE_OUTBOUNDS_MISSING", "no outbounds"));
+fn stop_child(child: &mut Child, elevation: &Elevation) {
+    let stopped = if elevation.request_stop() {
+        wait_for_exit(child, GRACEFUL_STOP_TIMEOUT)
+    } else {
+        stop_gracefully(child)
+    };
+    if !stopped {
+        let _ = child.kill();
     }
+    let _ = child.wait();
+}
 
-    let mut used_tags: HashSet<String> = tags.iter().cloned().collect();
-    let proxy_index = outbounds.iter().position(|item| {
-        item.get("tag")
-            .and_then(Value::as_str)
-            .map(|tag| tag == "proxy")
-            .unwrap_or(false)
+#[cfg(target_os = "windows")]
+fn create_job_object() -> Result<JobHandle, AppError> {
+    let handle = unsafe { CreateJobObjectW(std::ptr::null_mut(), std::ptr::null()) };
+    if handle == 0 {
+        return Err(err(
+            "JOB_ERROR",
+            std::io::Error::last_os_error().to_string(),
+        ));
+    }
+    let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
+    info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+    let result = unsafe {
+        SetInformationJobObject(
+            handle,
+            JobObjectExtendedLimitInformation,
+            &mut info as *mut _ as *mut _,
+            std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+        )
+    };
+    if result == 0 {
+        unsafe {
+            CloseHandle(handle);
+        }
+        return Err(err(
+            "JOB_ERROR",
+            std::io::Error::last_os_error().to_string(),
+        ));
+    }
+    Ok(JobHandle(handle))
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct AppError {
+    code: String,
+    detail: String,
+}
+
+impl AppError {
+    fn new(code: &str, detail: impl AsRef<str>) -> Self {
+        Self {
+            code: code.to_string(),
+            detail: detail.as_ref().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}|{}", self.code, self.detail)
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<String> for AppError {
+    fn from(message: String) -> Self {
+        match message.split_once('|') {
+            Some((code, detail)) => Self::new(code, detail),
+            None => Self::new("UNKNOWN", message),
+        }
+    }
+}
+
+fn err(tag: &str, detail: impl AsRef<str>) -> AppError {
+    AppError::new(tag, detail)
+}
+
+fn ensure_app_data_dir(app: &AppHandle) -> Result<PathBuf, AppError> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| err("PATH_ERROR", e.to_string()))?;
+    fs::create_dir_all(&dir).map_err(|e| err("PATH_ERROR", e.to_string()))?;
+    Ok(dir)
+}
+
+fn resolve_profile_groups_path(app: &AppHandle) -> Result<PathBuf, AppError> {
+    Ok(ensure_app_data_dir(app)?.join(PROFILE_GROUPS_FILE))
+}
+
+fn resolve_profile_groups_dir(app: &AppHandle) -> Result<PathBuf, AppError> {
+    let dir = ensure_app_data_dir(app)?.join(PROFILE_GROUPS_DIR);
+    fs::create_dir_all(&dir).map_err(|e| err("PATH_ERROR", e.to_string()))?;
+    Ok(dir)
+}
+
+fn resolve_backup_dir(app: &AppHandle) -> Result<PathBuf, AppError> {
+    let dir = ensure_app_data_dir(app)?.join(BACKUP_DIR);
+    fs::create_dir_all(&dir).map_err(|e| err("PATH_ERROR", e.to_string()))?;
+    Ok(dir)
+}
+
+fn resolve_group_profile_path(app: &AppHandle, group: &str) -> Result<PathBuf, AppError> {
+    if group == DEFAULT_PROFILE_GROUP {
+        return Ok(ensure_app_data_dir(app)?.join(PROFILE_FILE));
+    }
+    Ok(resolve_profile_groups_dir(app)?.join(format!("{group}.json")))
+}
+
+fn resolve_group_state_path(app: &AppHandle, group: &str) -> Result<PathBuf, AppError> {
+    if group == DEFAULT_PROFILE_GROUP {
+        return Ok(ensure_app_data_dir(app)?.join(PROFILE_STATE_FILE));
+    }
+    Ok(resolve_profile_groups_dir(app)?.join(format!("{group}.state.json")))
+}
+
+fn resolve_profile_path(app: &AppHandle) -> Result<PathBuf, AppError> {
+    resolve_group_profile_path(app, &load_profile_groups(app).active)
+}
+
+fn resolve_profile_state_path(app: &AppHandle) -> Result<PathBuf, AppError> {
+    resolve_group_state_path(app, &load_profile_groups(app).active)
+}
+
+fn resolve_app_state_path(app: &AppHandle) -> Result<PathBuf, AppError> {
+    Ok(ensure_app_data_dir(app)?.join(APP_STATE_FILE))
+}
+
+fn resolve_config_path(app: &AppHandle) -> Result<PathBuf, AppError> {
+    Ok(ensure_app_data_dir(app)?.join(CONFIG_FILE))
+}
+
+fn resolve_log_path(app: &AppHandle) -> Result<PathBuf, AppError> {
+    Ok(ensure_app_data_dir(app)?.join(LOG_FILE))
+}
+
+fn resolve_pid_path(app: &AppHandle) -> Result<PathBuf, AppError> {
+    Ok(ensure_app_data_dir(app)?.join(PID_FILE))
+}
+
+fn resolve_window_state_path(app: &AppHandle) -> Result<PathBuf, AppError> {
+    Ok(ensure_app_data_dir(app)?.join(WINDOW_STATE_FILE))
+}
+
+fn resolve_template_path(app: &AppHandle) -> Result<PathBuf, AppError> {
+    Ok(ensure_app_data_dir(app)?.join(TEMPLATE_FILE))
+}
+
+fn resolve_rule_set_dir(app: &AppHandle) -> Result<PathBuf, AppError> {
+    let dir = ensure_app_data_dir(app)?.join(RULE_SET_DIR);
+    fs::create_dir_all(&dir).map_err(|e| err("PATH_ERROR", e.to_string()))?;
+    Ok(dir)
+}
+
+fn resolve_rule_set_path(app: &AppHandle, name: &str) -> Result<PathBuf, AppError> {
+    Ok(resolve_rule_set_dir(app)?.join(name))
+}
+
+fn default_profile() -> Value {
+    json!({
+        "outbounds": [
+            {
+                "type": "socks",
+                "tag": "proxy",
+                "server": "example.com",
+                "server_port": 1080
+            },
+            {
+                "type": "direct",
+                "tag": "direct"
+            }
+        ]
+    })
+}
+
+fn ensure_profile(app: &AppHandle) -> Result<(Value, PathBuf), AppError> {
+    let profile_path = resolve_profile_path(app)?;
+    if !profile_path.exists() {
+        let content = serde_json::to_string_pretty(&default_profile())
+            .map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
+        write_file_atomic(&profile_path, content)
+            .map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
+        return Err(err("PROFILE_MISSING", profile_path.display().to_string()));
+    }
+
+    let value = read_profile_file(&profile_path)?;
+    Ok((value, profile_path))
+}
+
+#[cfg(feature = "encryption")]
+fn decode_profile_content(raw: String) -> Result<String, AppError> {
+    if raw.trim_start().starts_with(PROFILE_ENCRYPTED_PREFIX) {
+        profile_crypto::decrypt(&raw)
+    } else {
+        Ok(raw)
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+fn decode_profile_content(raw: String) -> Result<String, AppError> {
+    if raw.trim_start().starts_with(PROFILE_ENCRYPTED_PREFIX) {
+        return Err(err(
+            "PROFILE_ENCRYPTED",
+            "profile is encrypted but encryption support is not enabled",
+        ));
+    }
+    Ok(raw)
+}
+
+#[cfg(feature = "encryption")]
+fn encode_profile_content(content: String, encrypt: bool) -> Result<String, AppError> {
+    if encrypt {
+        profile_crypto::encrypt(&content)
+    } else {
+        Ok(content)
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+fn encode_profile_content(content: String, encrypt: bool) -> Result<String, AppError> {
+    if encrypt {
+        return Err(err(
+            "FEATURE_DISABLED",
+            "profile encryption support is not enabled",
+        ));
+    }
+    Ok(content)
+}
+
+fn json_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn validate_profile_shape(profile: &Value) -> Result<(), AppError> {
+    let Some(root) = profile.as_object() else {
+        return Err(err(
+            "PROFILE_INVALID",
+            format!("root must be an object, found {}", json_type_name(profile)),
+        ));
+    };
+    let Some(outbounds) = root.get("outbounds") else {
+        return Ok(());
+    };
+    let Some(items) = outbounds.as_array() else {
+        return Err(err(
+            "PROFILE_OUTBOUNDS_INVALID",
+            format!("outbounds must be an array, found {}", json_type_name(outbounds)),
+        ));
+    };
+    if let Some((index, item)) = items.iter().enumerate().find(|(_, item)| !item.is_object()) {
+        return Err(err(
+            "PROFILE_OUTBOUNDS_INVALID",
+            format!("outbounds[{index}] must be an object, found {}", json_type_name(item)),
+        ));
+    }
+    Ok(())
+}
+
+fn read_profile_file(path: &PathBuf) -> Result<Value, AppError> {
+    let raw = fs::read_to_string(path).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
+    let raw = decode_profile_content(raw)?;
+    let value: Value =
+        serde_json::from_str(&raw).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
+    validate_profile_shape(&value)?;
+    Ok(migrate_state(value, StateFile::Profile))
+}
+
+fn write_profile_file(path: &PathBuf, profile: &Value, encrypt: bool) -> Result<String, AppError> {
+    let mut profile = profile.clone();
+    if let Some(obj) = profile.as_object_mut() {
+        obj.insert("schema_version".to_string(), json!(SCHEMA_VERSION));
+    }
+    let content =
+        serde_json::to_string_pretty(&profile).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
+    let content = encode_profile_content(content, encrypt)?;
+    write_file_atomic(path, &content).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
+    Ok(content)
+}
+
+fn load_profile_json(app: &AppHandle) -> Result<Value, AppError> {
+    match ensure_profile(app) {
+        Ok((value, _)) => Ok(value),
+        Err(error) if error.code == "PROFILE_MISSING" => {
+            let profile_path = resolve_profile_path(app)?;
+            read_profile_file(&profile_path)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+fn save_profile_json(app: &AppHandle, profile: &Value) -> Result<(), AppError> {
+    let profile_path = resolve_profile_path(app)?;
+    let encrypt = load_app_state(app).settings.encrypt_profile;
+    let content = write_profile_file(&profile_path, profile, encrypt)?;
+    app.state::<ProfileWatch>().remember_write(content);
+    Ok(())
+}
+
+fn validate_template(template: &Value) -> Result<(), AppError> {
+    let obj = template
+        .as_object()
+        .ok_or_else(|| err("TEMPLATE_INVALID", "root must be an object"))?;
+    if obj.contains_key("outbounds") {
+        return Err(err("TEMPLATE_INVALID", "outbounds come from the profile"));
+    }
+    Ok(())
+}
+
+fn load_template(app: &AppHandle) -> Result<Option<Value>, AppError> {
+    let path = resolve_template_path(app)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let raw = fs::read_to_string(&path).map_err(|e| err("TEMPLATE_INVALID", e.to_string()))?;
+    let template: Value =
+        serde_json::from_str(&raw).map_err(|e| err("TEMPLATE_INVALID", e.to_string()))?;
+    validate_template(&template)?;
+    Ok(Some(template))
+}
+
+fn merge_template(target: &mut Value, template: Value) {
+    match (target, template) {
+        (Value::Object(target), Value::Object(template)) => {
+            for (key, value) in template {
+                match target.get_mut(&key) {
+                    Some(existing) => merge_template(existing, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, template) => *target = template,
+    }
+}
+
+fn load_profile_groups(app: &AppHandle) -> ProfileGroups {
+    let path = match resolve_profile_groups_path(app) {
+        Ok(path) => path,
+        Err(_) => return ProfileGroups::default(),
+    };
+    if !path.exists() {
+        return ProfileGroups::default();
+    }
+    let raw = match fs::read_to_string(&path) {
+        Ok(value) => value,
+        Err(_) => return ProfileGroups::default(),
+    };
+    let mut groups: ProfileGroups = serde_json::from_str(&raw).unwrap_or_default();
+    if !groups.groups.iter().any(|name| name == DEFAULT_PROFILE_GROUP) {
+        groups.groups.insert(0, DEFAULT_PROFILE_GROUP.to_string());
+    }
+    if !groups.groups.contains(&groups.active) {
+        groups.active = DEFAULT_PROFILE_GROUP.to_string();
+    }
+    groups
+}
+
+fn save_profile_groups(app: &AppHandle, groups: &ProfileGroups) -> Result<(), AppError> {
+    let path = resolve_profile_groups_path(app)?;
+    let content =
+        serde_json::to_string_pretty(groups).map_err(|e| err("STATE_INVALID", e.to_string()))?;
+    write_file_atomic(&path, content).map_err(|e| err("STATE_INVALID", e.to_string()))?;
+    Ok(())
+}
+
+fn validate_group_name(name: &str) -> Result<String, AppError> {
+    let trimmed = name.trim();
+    if trimmed.is_empty() {
+        return Err(err("GROUP_INVALID", "name is empty"));
+    }
+    if trimmed.chars().count() > 64 {
+        return Err(err("GROUP_INVALID", "name is too long"));
+    }
+    let valid = trimmed
+        .chars()
+        .all(|ch| ch.is_alphanumeric() || ch == '-' || ch == '_' || ch == ' ');
+    if !valid {
+        return Err(err("GROUP_INVALID", trimmed));
+    }
+    Ok(trimmed.to_string())
+}
+
+/// The versioned files on disk. Each has its own migration history.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StateFile {
+    App,
+    ProfileState,
+    Profile,
+}
+
+impl StateFile {
+    fn version_key(self) -> &'static str {
+        match self {
+            StateFile::Profile => "schema_version",
+            StateFile::App | StateFile::ProfileState => "schemaVersion",
+        }
+    }
+}
+
+fn migrate_state(mut value: Value, file: StateFile) -> Value {
+    let version_key = file.version_key();
+    let version = value
+        .get(version_key)
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    let Some(obj) = value.as_object_mut() else {
+        return value;
+    };
+    if version < 1 {
+        obj.insert(version_key.to_string(), json!(1));
+    }
+    if version < 2 {
+        if file == StateFile::App {
+            if let Some(rules) = obj.get("appRules").cloned() {
+                obj.entry("rulePresets")
+                    .or_insert_with(|| json!([{ "name": DEFAULT_RULE_PRESET, "rules": rules }]));
+                obj.entry("activePreset")
+                    .or_insert_with(|| json!(DEFAULT_RULE_PRESET));
+            }
+        }
+        obj.insert(version_key.to_string(), json!(2));
+    }
+    value
+}
+
+fn load_versioned<T: DeserializeOwned>(raw: &str, file: StateFile) -> Option<T> {
+    let value: Value = serde_json::from_str(raw).ok()?;
+    serde_json::from_value(migrate_state(value, file)).ok()
+}
+
+static STATE_WRITE_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Writes through a sibling temp file and a rename, so readers never see a truncated file.
+fn write_file_atomic(path: &Path, content: impl AsRef<[u8]>) -> std::io::Result<()> {
+    let sequence = STATE_WRITE_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(".{}.{sequence}.tmp", std::process::id()));
+    let tmp_path = PathBuf::from(tmp_path);
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+fn quarantine_state_file(path: &PathBuf) -> Option<StateRecovered> {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or_default();
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".corrupt.{millis}"));
+    let backup = PathBuf::from(backup);
+    fs::rename(path, &backup).ok()?;
+    Some(StateRecovered {
+        file: path.display().to_string(),
+        backup: backup.display().to_string(),
+    })
+}
+
+/// Reads a state file, moving an unreadable one aside so defaults can be saved over it.
+/// A failed parse is retried first, and an empty file is never treated as corrupt.
+fn read_state_file<T: DeserializeOwned + Default>(
+    path: &PathBuf,
+    file: StateFile,
+) -> (T, Option<StateRecovered>) {
+    let mut attempt = 1;
+    loop {
+        let Ok(raw) = fs::read_to_string(path) else {
+            return (T::default(), None);
+        };
+        if let Some(state) = load_versioned(&raw, file) {
+            return (state, None);
+        }
+        if attempt >= STATE_READ_ATTEMPTS {
+            let recovered = if raw.trim().is_empty() {
+                None
+            } else {
+                quarantine_state_file(path)
+            };
+            return (T::default(), recovered);
+        }
+        attempt += 1;
+        std::thread::sleep(STATE_READ_RETRY);
+    }
+}
+
+fn load_state_file<T: DeserializeOwned + Default>(
+    app: &AppHandle,
+    path: &PathBuf,
+    file: StateFile,
+) -> T {
+    let (state, recovered) = read_state_file(path, file);
+    if let Some(recovered) = recovered {
+        app.state::<StateRecoveries>().push(recovered.clone());
+        let _ = app.emit("state-recovered", recovered);
+    }
+    state
+}
+
+fn load_profile_state(app: &AppHandle) -> ProfileState {
+    match resolve_profile_state_path(app) {
+        Ok(path) => load_state_file(app, &path, StateFile::ProfileState),
+        Err(_) => ProfileState::default(),
+    }
+}
+
+fn save_profile_state(app: &AppHandle, state: &ProfileState) -> Result<(), AppError> {
+    let path = resolve_profile_state_path(app)?;
+    let content =
+        serde_json::to_string_pretty(state).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
+    write_file_atomic(&path, content).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
+    Ok(())
+}
+
+fn load_app_state(app: &AppHandle) -> AppState {
+    match resolve_app_state_path(app) {
+        Ok(path) => load_state_file(app, &path, StateFile::App),
+        Err(_) => AppState::default(),
+    }
+}
+
+fn save_app_state(app: &AppHandle, state: &AppState) -> Result<(), AppError> {
+    let path = resolve_app_state_path(app)?;
+    let content =
+        serde_json::to_string_pretty(state).map_err(|e| err("STATE_INVALID", e.to_string()))?;
+    write_file_atomic(&path, content).map_err(|e| err("STATE_INVALID", e.to_string()))?;
+    Ok(())
+}
+
+fn is_same_path(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn is_profile_event(path: &Path, profile_path: &Path) -> bool {
+    if path.file_name() != profile_path.file_name() {
+        return false;
+    }
+    match (path.parent(), profile_path.parent()) {
+        (Some(a), Some(b)) => is_same_path(a, b),
+        _ => false,
+    }
+}
+
+fn changed_profile_content(
+    watch: &ProfileWatch,
+    events: &[DebouncedEvent],
+    profile_path: &Path,
+) -> Option<String> {
+    if !events
+        .iter()
+        .any(|event| is_profile_event(&event.path, profile_path))
+    {
+        return None;
+    }
+    let content = fs::read_to_string(profile_path).ok()?;
+    if watch.is_own_write(&content) {
+        return None;
+    }
+    Some(content)
+}
+
+fn handle_profile_events(app: &AppHandle, events: Vec<DebouncedEvent>) {
+    let Ok(profile_path) = resolve_profile_path(app) else {
+        return;
+    };
+    let watch = app.state::<ProfileWatch>();
+    let Some(content) = changed_profile_content(&watch, &events, &profile_path) else {
+        return;
+    };
+    let profile = match read_profile_file(&profile_path) {
+        Ok(profile) => profile,
+        Err(error) => {
+            let _ = app.emit("profile-invalid", error);
+            return;
+        }
+    };
+    watch.remember_write(content);
+    let _ = app.emit("profile-changed", profile_data(app, &profile));
+}
+
+fn start_profile_watcher(app: &AppHandle) -> Result<(), AppError> {
+    let data_dir = ensure_app_data_dir(app)?;
+    let groups_dir = resolve_profile_groups_dir(app)?;
+    let handle = app.clone();
+    let mut debouncer = new_debouncer(
+        PROFILE_WATCH_DEBOUNCE,
+        move |result: DebounceEventResult| {
+            if let Ok(events) = result {
+                handle_profile_events(&handle, events);
+            }
+        },
+    )
+    .map_err(|e| err("WATCH_FAILED", e.to_string()))?;
+    // Editors often save by replacing the file, which drops a watch on the file
+    // itself, so the directories are watched and events are filtered by name.
+    let watcher = debouncer.watcher();
+    for dir in [&data_dir, &groups_dir] {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| err("WATCH_FAILED", e.to_string()))?;
+    }
+    *app.state::<ProfileWatch>()
+        .debouncer
+        .lock()
+        .expect("profile watch lock") = Some(debouncer);
+    Ok(())
+}
+
+fn profile_data(app: &AppHandle, profile: &Value) -> ProfileData {
+    let outbounds = profile
+        .get("outbounds")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let state = load_profile_state(app);
+    ProfileData {
+        outbounds,
+        active_tag: state.active_tag,
+        active_tag_by_mode: state.active_tag_by_mode,
+    }
+}
+
+fn ensure_singbox_exe(app: &AppHandle) -> Result<PathBuf, AppError> {
+    let resource_dir = app
+        .path()
+        .resource_dir()
+        .map_err(|e| err("PATH_ERROR", e.to_string()))?;
+
+    let candidates = [
+        resource_dir.join(SINGBOX_EXE),
+        resource_dir.join("resources").join(SINGBOX_EXE),
+    ];
+
+    let resource_path = candidates
+        .iter()
+        .find(|path| path.exists())
+        .cloned()
+        .ok_or_else(|| err("SINGBOX_MISSING", candidates[0].display().to_string()))?;
+
+    let bin_dir = ensure_app_data_dir(app)?.join(BIN_DIR);
+    fs::create_dir_all(&bin_dir).map_err(|e| err("PATH_ERROR", e.to_string()))?;
+    let target_path = bin_dir.join(SINGBOX_EXE);
+
+    let copy_needed = match (fs::metadata(&resource_path), fs::metadata(&target_path)) {
+        (Ok(src_meta), Ok(dst_meta)) => src_meta.len() != dst_meta.len(),
+        _ => true,
+    };
+
+    if copy_needed {
+        fs::copy(&resource_path, &target_path)
+            .map_err(|e| err("SINGBOX_MISSING", e.to_string()))?;
+    }
+
+    Ok(target_path)
+}
+
+fn parse_singbox_version(output: &str) -> Option<String> {
+    let token = output.lines().next()?.split_whitespace().last()?;
+    let version = token.trim_start_matches('v');
+    let numeric = version.split(['-', '+']).next()?;
+    let valid = numeric.split('.').count() >= 2
+        && numeric
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+    valid.then(|| version.to_string())
+}
+
+// route.default_domain_resolver is only understood by sing-box 1.12 and newer; an unknown
+// version keeps the legacy layout.
+fn supports_default_domain_resolver(version: Option<&str>) -> bool {
+    let Some(version) = version else {
+        return false;
+    };
+    let mut parts = version
+        .split(['-', '+'])
+        .next()
+        .unwrap_or("")
+        .split('.')
+        .map(|part| part.parse::<u32>().unwrap_or(0));
+    let major = parts.next().unwrap_or(0);
+    let minor = parts.next().unwrap_or(0);
+    (major, minor) >= (1, 12)
+}
+
+fn dns_server_tag(server: &Value) -> Option<&str> {
+    server.get("tag").and_then(Value::as_str)
+}
+
+fn dns_server_host(server: &Value) -> Option<&str> {
+    if let Some(host) = server.get("server").and_then(Value::as_str) {
+        return Some(host);
+    }
+    // Legacy servers put everything into one address URL.
+    let address = server.get("address").and_then(Value::as_str)?;
+    let rest = address.split_once("://").map_or(address, |(_, rest)| rest);
+    rest.split('/').next()
+}
+
+fn is_local_dns_server(server: &Value) -> bool {
+    let kind = server.get("type").and_then(Value::as_str);
+    let address = server.get("address").and_then(Value::as_str);
+    matches!(kind, Some("local" | "dhcp"))
+        || address == Some("local")
+        || address.is_some_and(|value| value.starts_with("dhcp://"))
+}
+
+/// Whether a DNS server is addressed by hostname and so needs another resolver first.
+fn dns_server_needs_resolving(server: &Value) -> bool {
+    if is_local_dns_server(server) {
+        return false;
+    }
+    let Some(host) = dns_server_host(server) else {
+        return true;
+    };
+    let is_ip = |value: &str| {
+        value
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok()
+    };
+    !(is_ip(host)
+        || host.parse::<SocketAddr>().is_ok()
+        || host.rsplit_once(':').is_some_and(|(ip, _)| is_ip(ip)))
+}
+
+/// Picks the server that resolves outbound hostnames. A DoH/DoT server addressed by
+/// hostname cannot resolve its own name, so only servers reachable by IP qualify.
+fn default_domain_resolver(dns: Option<&Value>, ipv6: bool) -> Option<Value> {
+    let dns = dns?;
+    let final_tag = dns.get("final").and_then(Value::as_str);
+    let candidates: Vec<&Value> = dns
+        .get("servers")
+        .and_then(Value::as_array)?
+        .iter()
+        .filter(|server| dns_server_tag(server).is_some() && !dns_server_needs_resolving(server))
+        .collect();
+    let server = candidates
+        .iter()
+        .find(|server| dns_server_tag(server) == Some("dns-local"))
+        .or_else(|| candidates.iter().find(|server| is_local_dns_server(server)))
+        .or_else(|| {
+            candidates
+                .iter()
+                .find(|server| final_tag.is_some() && dns_server_tag(server) == final_tag)
+        })
+        .or_else(|| candidates.first())
+        .and_then(|server| dns_server_tag(server))?;
+    Some(if ipv6 {
+        json!(server)
+    } else {
+        json!({
+            "server": server,
+            "strategy": "ipv4_only"
+        })
+    })
+}
+
+fn query_singbox_version(app: &AppHandle) -> Option<String> {
+    let exe = ensure_singbox_exe(app).ok()?;
+    let mut cmd = Command::new(exe);
+    cmd.arg("version").stdin(Stdio::null()).stderr(Stdio::null());
+
+    #[cfg(target_os = "windows")]
+    cmd.creation_flags(CREATE_NO_WINDOW);
+
+    let output = cmd.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_singbox_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn is_process_name(value: &str) -> bool {
+    let trimmed = value.trim().trim_matches('"');
+    if trimmed.is_empty() {
+        return false;
+    }
+    if trimmed.contains('\\') || trimmed.contains('/') {
+        return false;
+    }
+    if trimmed.contains(':') {
+        return false;
+    }
+    true
+}
+
+fn normalize_rule_path(value: &str) -> String {
+    let trimmed = value
+        .trim()
+        .trim_matches(|c| c == '"' || c == '\'')
+        .trim();
+    if is_process_name(trimmed) {
+        return trimmed.to_string();
+    }
+    trimmed
+        .chars()
+        .map(|c| {
+            if c == '/' || c == '\\' {
+                std::path::MAIN_SEPARATOR
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+fn normalize_app_rules(rules: Vec<AppRule>) -> Result<Vec<AppRule>, AppError> {
+    rules
+        .into_iter()
+        .enumerate()
+        .map(|(index, rule)| {
+            let path = normalize_rule_path(&rule.path);
+            if path.is_empty() {
+                return Err(err(
+                    "RULE_INVALID",
+                    format!("app rule #{} has an empty path", index + 1),
+                ));
+            }
+            Ok(AppRule { path, ..rule })
+        })
+        .collect()
+}
+
+fn sort_dedup(values: &mut Vec<String>) {
+    values.sort();
+    values.dedup();
+}
+
+struct NormalizedRules {
+    proxy_paths: Vec<String>,
+    direct_paths: Vec<String>,
+    proxy_names: Vec<String>,
+    direct_names: Vec<String>,
+    exclude_paths: Vec<String>,
+    exclude_names: Vec<String>,
+    conflicts: Vec<String>,
+}
+
+// Excluded targets always go direct first, so they are dropped from both other lists.
+fn drop_excluded(targets: &mut Vec<String>, excluded: &[String]) {
+    let excluded: HashSet<String> = excluded.iter().map(|value| value.to_lowercase()).collect();
+    targets.retain(|value| !excluded.contains(&value.to_lowercase()));
+}
+
+// A target listed as both proxy and direct is routed direct.
+fn drop_conflicts(proxy: &mut Vec<String>, direct: &[String]) -> Vec<String> {
+    let direct: HashSet<String> = direct.iter().map(|value| value.to_lowercase()).collect();
+    let mut conflicts = Vec::new();
+    proxy.retain(|value| {
+        let conflict = direct.contains(&value.to_lowercase());
+        if conflict {
+            conflicts.push(value.clone());
+        }
+        !conflict
+    });
+    conflicts
+}
+
+fn normalize_rules(rules: Vec<AppRule>) -> NormalizedRules {
+    let mut proxy_paths: Vec<String> = Vec::new();
+    let mut direct_paths: Vec<String> = Vec::new();
+    let mut proxy_names: Vec<String> = Vec::new();
+    let mut direct_names: Vec<String> = Vec::new();
+    let mut exclude_paths: Vec<String> = Vec::new();
+    let mut exclude_names: Vec<String> = Vec::new();
+    for rule in rules {
+        let path = rule.path.trim().trim_matches('"').to_string();
+        if path.is_empty() {
+            continue;
+        }
+        let is_name = is_process_name(&path);
+        match rule.mode {
+            AppRuleMode::Proxy => {
+                if is_name {
+                    proxy_names.push(path);
+                } else {
+                    proxy_paths.push(path);
+                }
+            }
+            AppRuleMode::Direct => {
+                if is_name {
+                    direct_names.push(path);
+                } else {
+                    direct_paths.push(path);
+                }
+            }
+            AppRuleMode::Exclude => {
+                if is_name {
+                    exclude_names.push(path);
+                } else {
+                    exclude_paths.push(path);
+                }
+            }
+        }
+    }
+    sort_dedup(&mut proxy_paths);
+    sort_dedup(&mut direct_paths);
+    sort_dedup(&mut proxy_names);
+    sort_dedup(&mut direct_names);
+    sort_dedup(&mut exclude_paths);
+    sort_dedup(&mut exclude_names);
+    for (paths, names) in [
+        (&mut proxy_paths, &mut proxy_names),
+        (&mut direct_paths, &mut direct_names),
+    ] {
+        drop_excluded(paths, &exclude_paths);
+        drop_excluded(names, &exclude_names);
+    }
+    let mut conflicts = drop_conflicts(&mut proxy_paths, &direct_paths);
+    conflicts.extend(drop_conflicts(&mut proxy_names, &direct_names));
+    NormalizedRules {
+        proxy_paths,
+        direct_paths,
+        proxy_names,
+        direct_names,
+        exclude_paths,
+        exclude_names,
+        conflicts,
+    }
+}
+
+fn push_process_rules(
+    rules: &mut Vec<Value>,
+    paths: &[String],
+    names: &[String],
+    outbound: &str,
+) {
+    if !paths.is_empty() {
+        rules.push(json!({
+            "process_path": paths,
+            "outbound": outbound
+        }));
+    }
+    if !names.is_empty() {
+        rules.push(json!({
+            "process_name": names,
+            "outbound": outbound
+        }));
+    }
+}
+
+fn push_hijack_dns_rules(rules: &mut Vec<Value>, paths: &[String], names: &[String]) {
+    if !paths.is_empty() {
+        rules.push(json!({
+            "process_path": paths,
+            "port": 53,
+            "action": "hijack-dns"
+        }));
+    }
+    if !names.is_empty() {
+        rules.push(json!({
+            "process_name": names,
+            "port": 53,
+            "action": "hijack-dns"
+        }));
+    }
+}
+
+fn build_geoip_ru_rule_set(app: &AppHandle, settings: &ProxySettings) -> Result<Value, AppError> {
+    let path = resolve_rule_set_path(app, GEOIP_RU_FILE)?;
+    Ok(geoip_ru_rule_set(&path, settings))
+}
+
+/// A cached `.srs` at `path` is used as is; otherwise sing-box downloads it from the settings URL.
+fn geoip_ru_rule_set(path: &Path, settings: &ProxySettings) -> Value {
+    if path.exists() {
+        json!({
+            "tag": GEOIP_RU_TAG,
+            "type": "local",
+            "format": "binary",
+            "path": path.display().to_string()
+        })
+    } else {
+        json!({
+            "tag": GEOIP_RU_TAG,
+            "type": "remote",
+            "format": "binary",
+            "url": settings.geoip_ru_url,
+            "download_detour": "proxy",
+            "update_interval": settings.geoip_update_interval
+        })
+    }
+}
+
+fn push_ru_bypass_rules(rules: &mut Vec<Value>) {
+    rules.push(json!({
+        "domain_suffix": [".ru"],
+        "outbound": "direct"
+    }));
+    rules.push(json!({
+        "rule_set": [GEOIP_RU_TAG],
+        "outbound": "direct"
+    }));
+}
+
+fn is_valid_duration(value: &str) -> bool {
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return false;
+    }
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        if digits == 0 || rest[..digits].parse::<f64>().is_err() {
+            return false;
+        }
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        if !matches!(&rest[..unit_len], "ns" | "us" | "µs" | "ms" | "s" | "m" | "h" | "d") {
+            return false;
+        }
+        rest = &rest[unit_len..];
+    }
+    true
+}
+
+fn validate_tun_cidr(value: &str, ipv6: bool) -> Result<(), AppError> {
+    let invalid = || err("SETTINGS_INVALID", format!("invalid tun address: {value}"));
+    let (address, prefix) = value.trim().split_once('/').ok_or_else(invalid)?;
+    let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+    let prefix_range = if ipv6 {
+        address.parse::<Ipv6Addr>().map_err(|_| invalid())?;
+        TUN_INET6_PREFIX_RANGE
+    } else {
+        address.parse::<Ipv4Addr>().map_err(|_| invalid())?;
+        TUN_INET4_PREFIX_RANGE
+    };
+    if !prefix_range.contains(&prefix) {
+        return Err(err(
+            "SETTINGS_INVALID",
+            format!(
+                "tun address {value} prefix must be between /{} and /{}",
+                prefix_range.start(),
+                prefix_range.end()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_settings(settings: &ProxySettings) -> Result<(), AppError> {
+    validate_tun_cidr(&settings.tun_inet4_address, false)?;
+    validate_tun_cidr(&settings.tun_inet6_address, true)?;
+    match Url::parse(settings.test_url.trim()) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.host_str().is_some() => {}
+        _ => {
+            return Err(err(
+                "SETTINGS_INVALID",
+                format!("invalid test url: {}", settings.test_url),
+            ));
+        }
+    }
+    match Url::parse(settings.geoip_ru_url.trim()) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.host_str().is_some() => {}
+        _ => {
+            return Err(err(
+                "SETTINGS_INVALID",
+                format!("invalid geoip url: {}", settings.geoip_ru_url),
+            ));
+        }
+    }
+    if settings.log_max_bytes < MIN_LOG_MAX_BYTES {
+        return Err(err(
+            "SETTINGS_INVALID",
+            format!("log max size must be at least {MIN_LOG_MAX_BYTES} bytes"),
+        ));
+    }
+    if settings.log_keep_bytes < MIN_LOG_KEEP_BYTES {
+        return Err(err(
+            "SETTINGS_INVALID",
+            format!("log keep size must be at least {MIN_LOG_KEEP_BYTES} bytes"),
+        ));
+    }
+    if settings.log_keep_bytes >= settings.log_max_bytes {
+        return Err(err(
+            "SETTINGS_INVALID",
+            "log keep size must be smaller than log max size",
+        ));
+    }
+    if settings.log_rotate_count > MAX_LOG_ROTATE_COUNT {
+        return Err(err(
+            "SETTINGS_INVALID",
+            format!("log rotate count must be at most {MAX_LOG_ROTATE_COUNT}"),
+        ));
+    }
+    let has_username = settings
+        .mixed_username
+        .as_deref()
+        .is_some_and(|value| !value.is_empty());
+    let has_password = settings
+        .mixed_password
+        .as_deref()
+        .is_some_and(|value| !value.is_empty());
+    if has_username != has_password {
+        return Err(err(
+            "SETTINGS_INVALID",
+            "mixed inbound needs both a username and a password",
+        ));
+    }
+    // The credentials are also embedded in a proxy URL for requests through the inbound.
+    let url_unsafe = |value: &Option<String>| {
+        value
+            .as_deref()
+            .is_some_and(|value| value.contains(PROXY_URL_RESERVED))
+    };
+    if url_unsafe(&settings.mixed_username) || url_unsafe(&settings.mixed_password) {
+        return Err(err(
+            "SETTINGS_INVALID",
+            "mixed inbound credentials cannot contain @, :, / or #",
+        ));
+    }
+    if settings.allow_lan && settings.mixed_auth_required && !has_username {
+        return Err(err(
+            "SETTINGS_INVALID",
+            "LAN access requires mixed inbound credentials",
+        ));
+    }
+    if !is_valid_duration(&settings.url_test_interval) {
+        return Err(err(
+            "SETTINGS_INVALID",
+            format!("invalid url test interval: {}", settings.url_test_interval),
+        ));
+    }
+    if !is_valid_duration(&settings.geoip_update_interval) {
+        return Err(err(
+            "SETTINGS_INVALID",
+            format!("invalid update interval: {}", settings.geoip_update_interval),
+        ));
+    }
+    if !SINGBOX_LOG_LEVELS.contains(&settings.log_level.as_str()) {
+        return Err(err(
+            "CONFIG_INVALID",
+            format!("unknown log level: {}", settings.log_level),
+        ));
+    }
+    let fingerprint = settings.default_fingerprint.trim();
+    if !fingerprint.is_empty() && !UTLS_FINGERPRINTS.contains(&fingerprint) {
+        return Err(err(
+            "SETTINGS_INVALID",
+            format!("unknown utls fingerprint: {fingerprint}"),
+        ));
+    }
+    if let Some(direct_dns) = settings
+        .direct_dns
+        .as_deref()
+        .filter(|value| !value.trim().is_empty())
+    {
+        if parse_direct_dns(direct_dns).is_none() {
+            return Err(err(
+                "SETTINGS_INVALID",
+                format!("invalid direct dns server: {direct_dns}"),
+            ));
+        }
+    }
+    if let Some(pattern) = settings.group_pattern.as_deref() {
+        Regex::new(pattern)
+            .map_err(|e| err("SETTINGS_INVALID", format!("invalid group pattern: {e}")))?;
+    }
+    if let Some(strategy) = settings.dns_strategy.as_deref() {
+        if !DNS_STRATEGIES.contains(&strategy) {
+            return Err(err(
+                "SETTINGS_INVALID",
+                format!("unknown dns strategy: {strategy}"),
+            ));
+        }
+    }
+    if settings.dns_servers.is_empty() {
+        if settings.dns_final.is_some() {
+            return Err(err(
+                "SETTINGS_INVALID",
+                "dns final requires at least one dns server",
+            ));
+        }
+        return Ok(());
+    }
+    let mut seen = HashSet::new();
+    for server in &settings.dns_servers {
+        let tag = server.tag.trim();
+        if tag.is_empty() {
+            return Err(err("SETTINGS_INVALID", "dns server tag is empty"));
+        }
+        if server.kind.trim().is_empty() {
+            return Err(err(
+                "SETTINGS_INVALID",
+                format!("dns server {tag} has no type"),
+            ));
+        }
+        if settings.fake_ip && tag == FAKE_IP_DNS_TAG {
+            return Err(err(
+                "SETTINGS_INVALID",
+                format!("dns server tag {tag} is reserved for fake-ip"),
+            ));
+        }
+        if !seen.insert(tag) {
+            return Err(err(
+                "SETTINGS_INVALID",
+                format!("duplicate dns server tag: {tag}"),
+            ));
+        }
+    }
+    if let Some(final_tag) = settings.dns_final.as_deref() {
+        if !seen.contains(final_tag) {
+            return Err(err(
+                "SETTINGS_INVALID",
+                format!("dns final references unknown server: {final_tag}"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn parse_direct_dns(value: &str) -> Option<(&'static str, SocketAddr)> {
+    let value = value.trim();
+    let (kind, address) = match value.split_once("://") {
+        Some(("udp", address)) => ("udp", address),
+        Some(("tcp", address)) => ("tcp", address),
+        Some(_) => return None,
+        None => ("udp", value),
+    };
+    let address = address.parse::<SocketAddr>().ok().or_else(|| {
+        address
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .ok()
+            .map(|ip| SocketAddr::new(ip, 53))
+    })?;
+    Some((kind, address))
+}
+
+fn direct_dns_server(settings: &ProxySettings) -> Value {
+    match settings.direct_dns.as_deref().and_then(parse_direct_dns) {
+        Some((kind, address)) => json!({
+            "tag": "dns-local",
+            "type": kind,
+            "server": address.ip().to_string(),
+            "server_port": address.port()
+        }),
+        None => json!({
+            "tag": "dns-local",
+            "type": "local"
+        }),
+    }
+}
+
+fn build_dns(force_ipv4_ru: bool, settings: &ProxySettings) -> Value {
+    let (mut servers, final_tag) = if settings.dns_servers.is_empty() {
+        (
+            vec![
+                direct_dns_server(settings),
+                json!({
+                    "tag": "dns-remote",
+                    "type": "https",
+                    "server": "dns.google",
+                    "path": "/dns-query",
+                    "domain_resolver": if settings.ipv6 {
+                        json!("dns-local")
+                    } else {
+                        json!({
+                            "server": "dns-local",
+                            "strategy": "ipv4_only"
+                        })
+                    }
+                }),
+            ],
+            "dns-remote".to_string(),
+        )
+    } else {
+        let servers: Vec<Value> = settings
+            .dns_servers
+            .iter()
+            .map(|server| {
+                let mut entry = json!({
+                    "tag": server.tag,
+                    "type": server.kind
+                });
+                if let Some(address) = server.server.as_deref() {
+                    entry["server"] = json!(address);
+                }
+                if let Some(detour) = server.detour.as_deref() {
+                    entry["detour"] = json!(detour);
+                }
+                entry
+            })
+            .collect();
+        let final_tag = settings
+            .dns_final
+            .clone()
+            .unwrap_or_else(|| settings.dns_servers[0].tag.clone());
+        (servers, final_tag)
+    };
+
+    let mut rules = Vec::new();
+    if force_ipv4_ru {
+        rules.push(json!({
+            "domain_suffix": RU_IPV4_DOMAIN_SUFFIXES,
+            "action": "route",
+            "server": final_tag,
+            "strategy": "ipv4_only"
+        }));
+    }
+    if settings.fake_ip {
+        let mut fake_server = json!({
+            "tag": FAKE_IP_DNS_TAG,
+            "type": "fakeip",
+            "inet4_range": FAKE_IP_INET4_RANGE
+        });
+        if settings.ipv6 {
+            fake_server["inet6_range"] = json!(FAKE_IP_INET6_RANGE);
+        }
+        servers.push(fake_server);
+        rules.push(json!({
+            "query_type": if settings.ipv6 { json!(["A", "AAAA"]) } else { json!(["A"]) },
+            "action": "route",
+            "server": FAKE_IP_DNS_TAG
+        }));
+    }
+
+    let mut dns = json!({
+        "servers": servers,
+        "final": final_tag
+    });
+    if !settings.ipv6 {
+        dns["strategy"] = json!("ipv4_only");
+    } else if let Some(strategy) = settings.dns_strategy.as_deref() {
+        dns["strategy"] = json!(strategy);
+    } else if force_ipv4_ru {
+        dns["strategy"] = json!("prefer_ipv4");
+    }
+    if force_ipv4_ru || settings.fake_ip {
+        dns["reverse_mapping"] = json!(true);
+    }
+    if !rules.is_empty() {
+        dns["rules"] = Value::Array(rules);
+    }
+    dns
+}
+
+fn outbound_resolver_rules(outbounds: &[Value]) -> Vec<Value> {
+    outbounds
+        .iter()
+        .filter_map(|outbound| {
+            let resolver = outbound
+                .get(NODE_DNS_SERVER_FIELD)
+                .and_then(Value::as_str)?;
+            let server = outbound.get("server").and_then(Value::as_str)?;
+            if resolver.trim().is_empty() || server.parse::<IpAddr>().is_ok() {
+                return None;
+            }
+            Some(json!({
+                "domain": [server],
+                "action": "route",
+                "server": resolver
+            }))
+        })
+        .collect()
+}
+
+/// A resolver rule naming a server the DNS section lacks would fail `sing-box check`.
+fn check_resolver_servers(dns: &Map<String, Value>, rules: &[Value]) -> Result<(), AppError> {
+    let servers: HashSet<&str> = dns
+        .get("servers")
+        .and_then(Value::as_array)
+        .map(|servers| servers.iter().filter_map(dns_server_tag).collect())
+        .unwrap_or_default();
+    for rule in rules {
+        let server = rule["server"].as_str().unwrap_or_default();
+        if !servers.contains(server) {
+            let domain = rule["domain"][0].as_str().unwrap_or_default();
+            return Err(err(
+                "PROFILE_DNS_INVALID",
+                format!("unknown DNS server {server} for {domain}"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn merge_route(mut existing: Map<String, Value>, generated: Value) -> Value {
+    let Value::Object(generated) = generated else {
+        return Value::Object(existing);
+    };
+    for (key, value) in generated {
+        match (key.as_str(), value) {
+            ("rules", Value::Array(rules)) => {
+                let target = existing
+                    .entry("rules")
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                match target.as_array_mut() {
+                    Some(items) => items.extend(rules),
+                    None => *target = Value::Array(rules),
+                }
+            }
+            ("rule_set", Value::Array(rule_sets)) => {
+                let target = existing
+                    .entry("rule_set")
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                match target.as_array_mut() {
+                    Some(items) => {
+                        for rule_set in rule_sets {
+                            let tag = rule_set.get("tag").cloned();
+                            let present = tag.is_some()
+                                && items.iter().any(|item| item.get("tag") == tag.as_ref());
+                            if !present {
+                                items.push(rule_set);
+                            }
+                        }
+                    }
+                    None => *target = Value::Array(rule_sets),
+                }
+            }
+            (_, value) => {
+                existing.entry(key).or_insert(value);
+            }
+        }
+    }
+    Value::Object(existing)
+}
+
+fn apply_default_fingerprint(outbounds: &mut [Value], fingerprint: &str) {
+    if fingerprint.is_empty() {
+        return;
+    }
+    for outbound in outbounds.iter_mut() {
+        let outbound_type = outbound.get("type").and_then(Value::as_str).unwrap_or("");
+        if QUIC_OUTBOUND_TYPES.contains(&outbound_type) {
+            continue;
+        }
+        let Some(tls) = outbound.get_mut("tls").and_then(Value::as_object_mut) else {
+            continue;
+        };
+        let enabled = tls.get("enabled").and_then(Value::as_bool).unwrap_or(false);
+        if enabled && !tls.contains_key("utls") {
+            tls.insert(
+                "utls".to_string(),
+                json!({
+                    "enabled": true,
+                    "fingerprint": fingerprint
+                }),
+            );
+        }
+    }
+}
+
+// sing-box rejects unknown keys, so fields the app caches on outbounds never reach the config.
+fn strip_app_fields(outbounds: &mut [Value]) {
+    for outbound in outbounds.iter_mut() {
+        if let Some(obj) = outbound.as_object_mut() {
+            obj.remove(NODE_COUNTRY_FIELD);
+            obj.remove(NODE_DNS_SERVER_FIELD);
+        }
+    }
+}
+
+fn apply_default_udp_over_tcp(outbounds: &mut [Value], enabled: bool) {
+    if !enabled {
+        return;
+    }
+    for outbound in outbounds.iter_mut() {
+        if outbound.get("type").and_then(Value::as_str) != Some("shadowsocks") {
+            continue;
+        }
+        if let Some(obj) = outbound.as_object_mut() {
+            obj.entry("udp_over_tcp").or_insert(json!(true));
+        }
+    }
+}
+
+fn selector_candidates(tags: &[String], allow_direct: bool) -> Vec<String> {
+    let mut candidates: Vec<String> = tags
+        .iter()
+        .filter(|tag| *tag != "proxy" && *tag != "direct")
+        .cloned()
+        .collect();
+    if allow_direct {
+        candidates.push("direct".to_string());
+    }
+    candidates
+}
+
+fn proxy_group(tags: Vec<String>, default: Option<String>, settings: &ProxySettings) -> Value {
+    if settings.auto_select {
+        return json!({
+            "type": "urltest",
+            "tag": "proxy",
+            "outbounds": tags,
+            "url": settings.test_url.trim(),
+            "interval": settings.url_test_interval.trim()
+        });
+    }
+    let mut group = json!({
+        "type": "selector",
+        "tag": "proxy",
+        "outbounds": tags
+    });
+    if let Some(default) = default {
+        group["default"] = json!(default);
+    }
+    group
+}
+
+fn selector_default(active_tag: Option<String>, selector_tags: &[String]) -> Option<String> {
+    active_tag
+        .filter(|tag| selector_tags.contains(tag))
+        .or_else(|| selector_tags.first().cloned())
+}
+
+fn mixed_listen_host(allow_lan: bool) -> &'static str {
+    if allow_lan {
+        LAN_PROXY_HOST
+    } else {
+        LOCAL_PROXY_HOST
+    }
+}
+
+struct ConfigOptions<'a> {
+    settings: &'a ProxySettings,
+    force_ipv4_ru: bool,
+    active_tag: Option<String>,
+    log_path: &'a PathBuf,
+    geoip_ru_rule_set: Value,
+    clash_api_secret: &'a str,
+    clash_api_controller: &'a str,
+    singbox_version: Option<&'a str>,
+}
+
+struct ComposedConfig {
+    config: Value,
+    selector_change: SelectorChange,
+    /// The remembered tag that no longer matches any outbound, if any.
+    stale_tag: Option<String>,
+}
+
+fn compose_config(
+    mut profile: Value,
+    mode: ProxyMode,
+    rules: Vec<AppRule>,
+    options: ConfigOptions,
+) -> Result<ComposedConfig, AppError> {
+    let settings = options.settings;
+    let force_ipv4_ru = options.force_ipv4_ru;
+    let log_path = options.log_path;
+
+    let profile_obj = profile
+        .as_object_mut()
+        .ok_or_else(|| err("PROFILE_INVALID", "root must be an object"))?;
+    profile_obj.remove("schema_version");
+
+    let outbounds_value = profile_obj
+        .get("outbounds")
+        .cloned()
+        .ok_or_else(|| err("PROFILE_OUTBOUNDS_MISSING", "missing outbounds"))?;
+
+    let mut outbounds = outbounds_value
+        .as_array()
+        .cloned()
+        .ok_or_else(|| err("PROFILE_INVALID", "outbounds must be an array"))?;
+
+    let mut tags: Vec<String> = outbounds
+        .iter()
+        .filter_map(|item| item.get("tag").and_then(Value::as_str))
+        .map(|tag| tag.to_string())
+        .collect();
+
+    if tags.is_empty() {
+        return Err(err("PROFILE_OUTBOUNDS_MISSING", "no outbounds"));
+    }
+
+    let mut used_tags: HashSet<String> = tags.iter().cloned().collect();
+    let proxy_index = outbounds.iter().position(|item| {
+        item.get("tag")
+            .and_then(Value::as_str)
+            .map(|tag| tag == "proxy")
+            .unwrap_or(false)
+    });
+    let requested_tag = options.active_tag;
+    let mut active_tag = requested_tag.clone();
+    if let Some(tag) = active_tag.clone() {
+        let direct_allowed = settings.allow_direct_selection && tag == "direct";
+        if !tags.contains(&tag) && !direct_allowed {
+            active_tag = None;
+        }
+    }
+    let mut stale_active = requested_tag.is_some() && active_tag.is_none();
+
+    let allow_direct = settings.allow_direct_selection && !settings.auto_select;
+    let mut selector_change = SelectorChange::NoChange;
+    if let Some(index) = proxy_index {
+        let proxy_type = outbounds[index]
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or("");
+        let needs_selector = active_tag.is_some() && tags.len() > 1;
+        if proxy_type == "selector" {
+            let selector_tags = selector_candidates(&tags, allow_direct);
+            let selector_tags = if selector_tags.is_empty() {
+                outbounds[index]
+                    .get("outbounds")
+                    .and_then(Value::as_array)
+                    .map(|items| {
+                        items
+                            .iter()
+                            .filter_map(Value::as_str)
+                            .map(|tag| tag.to_string())
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            } else {
+                outbounds[index]["outbounds"] = json!(selector_tags);
+                selector_tags
+            };
+            if active_tag
+                .as_ref()
+                .is_some_and(|tag| !selector_tags.contains(tag))
+            {
+                stale_active = true;
+            }
+            if settings.auto_select {
+                outbounds[index] = proxy_group(selector_tags, None, settings);
+            } else if let Some(tag) = selector_default(active_tag, &selector_tags) {
+                outbounds[index]["default"] = json!(tag);
+            }
+        } else if needs_selector {
+            let renamed = unique_tag("proxy-origin", &mut used_tags);
+            outbounds[index]["tag"] = json!(renamed.clone());
+            selector_change = SelectorChange::ProxyRenamed;
+            tags = outbounds
+                .iter()
+                .filter_map(|item| item.get("tag").and_then(Value::as_str))
+                .map(|tag| tag.to_string())
+                .collect();
+            let selector_tags = selector_candidates(&tags, allow_direct);
+            if selector_tags.is_empty() {
+                return Err(err("PROFILE_OUTBOUNDS_MISSING", "no proxy outbounds"));
+            }
+            let selected_tag = match active_tag.filter(|tag| selector_tags.contains(tag)) {
+                Some(tag) => tag,
+                None => {
+                    stale_active = true;
+                    renamed.clone()
+                }
+            };
+            outbounds.push(proxy_group(selector_tags, Some(selected_tag), settings));
+            tags = outbounds
+                .iter()
+                .filter_map(|item| item.get("tag").and_then(Value::as_str))
+                .map(|tag| tag.to_string())
+                .collect();
+        }
+    } else if mode == ProxyMode::Off && selector_candidates(&tags, allow_direct).is_empty() {
+        // Off routes nothing through the proxy group, so a profile without nodes
+        // still composes for validation.
+    } else {
+        selector_change = SelectorChange::SelectorSynthesized;
+        let selector_tags = selector_candidates(&tags, allow_direct);
+        if selector_tags.is_empty() {
+            return Err(err("PROFILE_OUTBOUNDS_MISSING", "no proxy outbounds"));
+        }
+        if active_tag
+            .as_ref()
+            .is_some_and(|tag| !selector_tags.contains(tag))
+        {
+            stale_active = true;
+        }
+        let selected_tag = selector_default(active_tag, &selector_tags)
+            .unwrap_or_else(|| selector_tags[0].clone());
+        outbounds.push(proxy_group(selector_tags, Some(selected_tag), settings));
+        tags = outbounds
+            .iter()
+            .filter_map(|item| item.get("tag").and_then(Value::as_str))
+            .map(|tag| tag.to_string())
+            .collect();
+    }
+
+    let has_direct = tags.iter().any(|tag| tag == "direct");
+    if !has_direct {
+        outbounds.push(json!({
+            "type": "direct",
+            "tag": "direct"
+        }));
+    }
+
+    apply_default_fingerprint(&mut outbounds, settings.default_fingerprint.trim());
+    apply_default_udp_over_tcp(&mut outbounds, settings.ss_udp_over_tcp);
+    let resolver_rules = outbound_resolver_rules(&outbounds);
+    strip_app_fields(&mut outbounds);
+    profile_obj.insert("outbounds".to_string(), Value::Array(outbounds));
+
+    match profile_obj.get_mut("log").and_then(Value::as_object_mut) {
+        Some(log) => {
+            log.insert("level".to_string(), json!(settings.log_level));
+        }
+        None => {
+            profile_obj.insert(
+                "log".to_string(),
+                json!( {
+                    "level": settings.log_level,
+                    "output": log_path
+                }),
+            );
+        }
+    }
+
+    if !profile_obj.contains_key("dns") {
+        profile_obj.insert("dns".to_string(), build_dns(force_ipv4_ru, settings));
+    }
+    if let Some(dns) = profile_obj.get_mut("dns").and_then(Value::as_object_mut) {
+        check_resolver_servers(dns, &resolver_rules)?;
+        if !resolver_rules.is_empty() {
+            let rules = dns
+                .entry("rules")
+                .or_insert_with(|| Value::Array(Vec::new()));
+            match rules.as_array_mut() {
+                Some(items) => {
+                    items.splice(0..0, resolver_rules);
+                }
+                None => *rules = Value::Array(resolver_rules),
+            }
+        }
+    }
+
+    let mut inbounds: Vec<Value> = profile_obj
+        .get("inbounds")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let mut generated = Vec::new();
+    if settings.tun_enabled {
+        generated.push(json!({
+            "type": "tun",
+            "tag": "tun-in",
+            "interface_name": TUN_INTERFACE_NAME,
+            "address": settings.tun_addresses(),
+            "auto_route": true,
+            "strict_route": true,
+            "stack": "system"
+        }));
+    }
+    let mut mixed = json!({
+        "type": "mixed",
+        "tag": LOCAL_PROXY_TAG,
+        "listen": mixed_listen_host(settings.allow_lan),
+        "listen_port": LOCAL_PROXY_PORT
+    });
+    if let Some(users) = settings.mixed_users() {
+        mixed["users"] = users;
+    }
+    generated.push(mixed);
+    let generated_tags: HashSet<&str> = ["tun-in", LOCAL_PROXY_TAG].into_iter().collect();
+    inbounds.retain(|existing| {
+        existing
+            .get("tag")
+            .and_then(Value::as_str)
+            .map(|tag| !generated_tags.contains(tag))
+            .unwrap_or(true)
+    });
+    inbounds.extend(generated);
+    profile_obj.insert("inbounds".to_string(), Value::Array(inbounds));
+
+    let geoip_ru_rule_set = options.geoip_ru_rule_set;
+    let NormalizedRules {
+        proxy_paths,
+        direct_paths,
+        proxy_names,
+        direct_names,
+        exclude_paths,
+        exclude_names,
+        ..
+    } = normalize_rules(rules);
+    let tun_enabled = settings.tun_enabled;
+    let hijack_dns = tun_enabled && settings.hijack_dns;
+    let proxy_only_dns = hijack_dns && settings.hijack_dns_proxy_only;
+    let route = match mode {
+        ProxyMode::Full => {
+            let mut rules = Vec::new();
+            if tun_enabled {
+                push_process_rules(&mut rules, &exclude_paths, &exclude_names, "direct");
+            }
+            // Direct apps are routed before the hijack so their DNS stays on the system resolver.
+            if proxy_only_dns {
+                push_process_rules(&mut rules, &direct_paths, &direct_names, "direct");
+            }
+            if hijack_dns {
+                rules.push(json!({
+                    "action": "hijack-dns",
+                    "port": 53
+                }));
+            }
+            rules.push(json!({
+                "action": "sniff"
+            }));
+            push_ru_bypass_rules(&mut rules);
+            rules.push(json!({
+                "inbound": [LOCAL_PROXY_TAG],
+                "outbound": "proxy"
+            }));
+            if tun_enabled && !proxy_only_dns {
+                push_process_rules(&mut rules, &direct_paths, &direct_names, "direct");
+            }
+            json!({
+                "rules": rules,
+                "final": "proxy",
+                "auto_detect_interface": true,
+                "rule_set": [geoip_ru_rule_set]
+            })
+        }
+        ProxyMode::Selected => {
+            let mut rules = Vec::new();
+            if tun_enabled {
+                push_process_rules(&mut rules, &exclude_paths, &exclude_names, "direct");
+            }
+            if proxy_only_dns {
+                push_hijack_dns_rules(&mut rules, &proxy_paths, &proxy_names);
+            } else if hijack_dns {
+                rules.push(json!({
+                    "action": "hijack-dns",
+                    "port": 53
+                }));
+            }
+            rules.push(json!({
+                "action": "sniff"
+            }));
+            push_ru_bypass_rules(&mut rules);
+            rules.push(json!({
+                "inbound": [LOCAL_PROXY_TAG],
+                "outbound": "proxy"
+            }));
+            if tun_enabled {
+                push_process_rules(&mut rules, &direct_paths, &direct_names, "direct");
+                push_process_rules(&mut rules, &proxy_paths, &proxy_names, "proxy");
+            }
+            json!({
+                "rules": rules,
+                "final": settings.selected_final.outbound_tag(),
+                "auto_detect_interface": true,
+                "rule_set": [geoip_ru_rule_set]
+            })
+        }
+        ProxyMode::Off => json!({}),
+    };
+
+    if mode != ProxyMode::Off {
+        let route = match profile_obj.remove("route") {
+            Some(Value::Object(existing)) if !settings.override_route => {
+                merge_route(existing, route)
+            }
+            _ => route,
+        };
+        profile_obj.insert("route".to_string(), route);
+        if supports_default_domain_resolver(options.singbox_version) {
+            let resolver = default_domain_resolver(profile_obj.get("dns"), settings.ipv6);
+            let route = profile_obj.get_mut("route").and_then(Value::as_object_mut);
+            if let (Some(resolver), Some(route)) = (resolver, route) {
+                route.entry("default_domain_resolver").or_insert(resolver);
+            }
+        }
+    }
+
+    let experimental = profile_obj
+        .entry("experimental")
+        .or_insert_with(|| json!({}));
+    if let Some(experimental) = experimental.as_object_mut() {
+        let clash_api = experimental.entry("clash_api").or_insert_with(|| json!({}));
+        if let Some(clash_api) = clash_api.as_object_mut() {
+            clash_api
+                .entry("external_controller")
+                .or_insert_with(|| json!(options.clash_api_controller));
+            clash_api
+                .entry("secret")
+                .or_insert_with(|| json!(options.clash_api_secret));
+        }
+    }
+
+    Ok(ComposedConfig {
+        config: profile,
+        selector_change,
+        stale_tag: requested_tag.filter(|_| stale_active),
+    })
+}
+
+/// `controller` pins the clash api address; `None` picks a free port for a new start.
+fn generate_config(
+    app: &AppHandle,
+    mode: ProxyMode,
+    rules: Vec<AppRule>,
+    force_ipv4_ru: bool,
+    settings: &ProxySettings,
+    controller: Option<String>,
+) -> Result<ComposedConfig, AppError> {
+    validate_settings(settings)?;
+    let controller = match controller {
+        Some(controller) => controller,
+        None => free_local_address()?,
+    };
+    let (mut profile, _profile_path) = ensure_profile(app)?;
+    if let Some(template) = load_template(app)? {
+        merge_template(&mut profile, template);
+    }
+    let log_path = resolve_log_path(app)?;
+    let singbox_version = app.state::<SingboxVersion>().get(app);
+
+    compose_config(
+        profile,
+        mode,
+        rules,
+        ConfigOptions {
+            settings,
+            force_ipv4_ru,
+            active_tag: load_profile_state(app).active_tag_for(mode),
+            log_path: &log_path,
+            geoip_ru_rule_set: build_geoip_ru_rule_set(app, settings)?,
+            clash_api_secret: &app.state::<ClashApi>().secret,
+            clash_api_controller: &controller,
+            singbox_version: singbox_version.as_deref(),
+        },
+    )
+}
+
+fn forget_stale_tag(app: &AppHandle, mode: ProxyMode, stale: &str) {
+    let mut state = load_profile_state(app);
+    if state.active_tag_by_mode.get(&mode).map(String::as_str) == Some(stale) {
+        state.active_tag_by_mode.remove(&mode);
+    }
+    if state.active_tag.as_deref() == Some(stale) {
+        state.active_tag = None;
+    }
+    let _ = save_profile_state(app, &state);
+}
+
+fn build_config(
+    app: &AppHandle,
+    mode: ProxyMode,
+    rules: Vec<AppRule>,
+    force_ipv4_ru: bool,
+    settings: &ProxySettings,
+) -> Result<(PathBuf, SelectorChange, Elevation), AppError> {
+    let composed = generate_config(app, mode, rules, force_ipv4_ru, settings, None)?;
+    let config = composed.config;
+    let config_path = resolve_config_path(app)?;
+    write_config(&config_path, &config)?;
+    app.state::<ClashApi>().remember(&config);
+    if let Some(stale) = composed.stale_tag {
+        forget_stale_tag(app, mode, &stale);
+    }
+    let elevation = select_elevation(app, &config)?;
+    let _ = app.emit(
+        "config-generated",
+        ConfigGeneratedPayload::new(&config_path, config, settings),
+    );
+
+    Ok((config_path, composed.selector_change, elevation))
+}
+
+fn write_config(path: &Path, config: &Value) -> Result<(), AppError> {
+    let content =
+        serde_json::to_string_pretty(config).map_err(|e| err("CONFIG_INVALID", e.to_string()))?;
+    fs::write(path, content).map_err(|e| err("CONFIG_INVALID", e.to_string()))
+}
+
+fn select_elevation(app: &AppHandle, config: &Value) -> Result<Elevation, AppError> {
+    if !cfg!(target_os = "macos") || !launch::needs_root(config) || launch::running_as_root() {
+        return Ok(Elevation::None);
+    }
+    let data_dir = ensure_app_data_dir(app)?;
+    let started_file = data_dir.join(STARTED_FILE);
+    // A marker left by an earlier elevated run would end the prompt wait too early.
+    let _ = fs::remove_file(&started_file);
+    Ok(Elevation::Osascript {
+        log: resolve_log_path(app)?,
+        stop_file: data_dir.join(STOP_FILE),
+        started_file,
+        owner: std::process::id(),
+    })
+}
+
+fn refresh_state(state: &mut ProxyState) {
+    if let Some(child) = state.child.as_mut() {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                state.last_exit = status.code();
+                state.child = None;
+                state.mode = ProxyMode::Off;
+                state.record_exit(Instant::now());
+            }
+            Ok(None) => {}
+            Err(err) => {
+                state.last_exit = Some(-1);
+                state.last_error = Some(err.to_string());
+                state.child = None;
+                state.mode = ProxyMode::Off;
+                state.record_exit(Instant::now());
+            }
+        }
+    }
+}
+
+fn emit_proxy_state(app: &AppHandle) {
+    let Some(state) = app.try_state::<SharedState>() else {
+        return;
+    };
+    let status = {
+        let Ok(mut guard) = state.lock() else {
+            return;
+        };
+        current_status(app, &mut guard)
+    };
+    let _ = app.emit("proxy-state", status);
+}
+
+/// What went wrong when sing-box exits on its own: the exit code and the last lines it
+/// wrote to the log, which is where its stderr goes.
+fn exit_detail(code: Option<i32>, log_path: &PathBuf) -> String {
+    let mut detail = match code {
+        Some(code) => format!("sing-box exited with code {code}"),
+        None => "sing-box exited".to_string(),
+    };
+    append_lines(
+        &mut detail,
+        tail_log_lines(log_path, EXIT_LOG_LINES, None).unwrap_or_default(),
+    );
+    detail
+}
+
+fn spawn_monitor(app: AppHandle, state: SharedState, token: u64, log_path: PathBuf) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_millis(750));
+        let exited = {
+            let mut guard = state.lock().expect("state lock");
+            if guard.watch_token != token {
+                return;
+            }
+            if let Some(child) = guard.child.as_mut() {
+                match child.try_wait() {
+                    Ok(Some(status)) => {
+                        guard.last_exit = status.code();
+                        guard.child = None;
+                        guard.mode = ProxyMode::Off;
+                        guard.record_exit(Instant::now());
+                        Some((status.code(), None))
+                    }
+                    Ok(None) => None,
+                    Err(err) => {
+                        guard.last_exit = Some(-1);
+                        guard.child = None;
+                        guard.mode = ProxyMode::Off;
+                        guard.record_exit(Instant::now());
+                        Some((Some(-1), Some(err.to_string())))
+                    }
+                }
+            } else {
+                return;
+            }
+        };
+
+        let Some((exit_code, wait_error)) = exited else {
+            continue;
+        };
+        let detail = wait_error.unwrap_or_else(|| exit_detail(exit_code, &log_path));
+        {
+            let mut guard = state.lock().expect("state lock");
+            if guard.watch_token == token {
+                guard.last_error = Some(detail);
+            }
+        }
+        remove_pid_lock(&app);
+        let _ = app.emit(
+            "proxy-exited",
+            ProxyExitPayload { code: exit_code },
+        );
+        emit_proxy_state(&app);
+        refresh_tray(&app);
+        return;
+    });
+}
+
+fn spawn_log_tailer(app: AppHandle, state: SharedState, token: u64, log_path: PathBuf) {
+    std::thread::spawn(move || {
+        let Some(mut tail) = LogTail::open(&log_path) else {
+            return;
+        };
+
+        let mut pending: Vec<String> = Vec::new();
+        let mut last_emit = Instant::now();
+        let mut last_trim = Instant::now();
+
+        loop {
+            std::thread::sleep(Duration::from_millis(200));
+            let (stop, keep_bytes, max_bytes, rotate_count, structured) = {
+                let guard = match state.lock() {
+                    Ok(guard) => guard,
+                    Err(_) => return,
+                };
+                (
+                    guard.watch_token != token,
+                    guard.settings.log_keep_bytes,
+                    guard.settings.log_max_bytes,
+                    guard.settings.log_rotate_count,
+                    guard.settings.structured_logs,
+                )
+            };
+            if stop {
+                return;
+            }
+
+            if last_trim.elapsed() >= Duration::from_secs(2) {
+                let limited = if rotate_count == 0 {
+                    trim_log_file(&log_path, keep_bytes, max_bytes)
+                } else {
+                    rotate_log_file(&log_path, max_bytes, rotate_count)
+                };
+                if limited.unwrap_or(false) {
+                    if let Some(reopened) = LogTail::open(&log_path) {
+                        tail = reopened;
+                    }
+                }
+                last_trim = Instant::now();
+            }
+
+            let paused = app.state::<LogStream>().is_paused();
+            if tail.read_lines(paused, &mut pending).is_err() {
+                return;
+            }
+
+            if !pending.is_empty()
+                && (pending.len() >= 50 || last_emit.elapsed() >= Duration::from_millis(250))
+            {
+                let lines: Vec<String> = std::mem::take(&mut pending);
+                if structured {
+                    let entries = lines.iter().map(|line| parse_log_line(line)).collect();
+                    let _ = app.emit("proxy-log-structured", LogEntryBatchPayload { entries });
+                }
+                let _ = app.emit("proxy-log-batch", LogBatchPayload { lines });
+                last_emit = Instant::now();
+            }
+        }
     });
-    let state = load_profile_state(app);
-    let mut active_tag = state.active_tag;
-    if let Some(tag) = active_tag.clone() {
-        if !tags.contains(&tag) {
-            active_tag = None;
+}
+
+/// Follows the log from where it ended when opened.
+struct LogTail {
+    reader: BufReader<std::fs::File>,
+    path: PathBuf,
+    was_paused: bool,
+}
+
+impl LogTail {
+    fn open(path: &PathBuf) -> Option<Self> {
+        let file = OpenOptions::new().read(true).open(path).ok()?;
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::End(0)).ok()?;
+        Some(Self {
+            reader,
+            path: path.clone(),
+            was_paused: false,
+        })
+    }
+
+    /// Appends the lines written since the last call to `pending`. Nothing is read while
+    /// paused, and lines logged during a pause are skipped rather than replayed.
+    fn read_lines(&mut self, paused: bool, pending: &mut Vec<String>) -> std::io::Result<()> {
+        if paused {
+            self.was_paused = true;
+            return Ok(());
+        }
+        if self.was_paused {
+            self.was_paused = false;
+            pending.clear();
+            self.reader.seek(SeekFrom::End(0))?;
+        }
+        if log_was_truncated(&mut self.reader, &self.path) {
+            self.reader.seek(SeekFrom::Start(0))?;
+            pending.clear();
+        }
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let trimmed = line.trim_end_matches(['\r', '\n']);
+            if !trimmed.is_empty() {
+                pending.push(trimmed.to_string());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl LogLevel {
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "trace" => Some(Self::Trace),
+            "debug" => Some(Self::Debug),
+            "info" => Some(Self::Info),
+            "warn" | "warning" => Some(Self::Warn),
+            "error" => Some(Self::Error),
+            "fatal" | "panic" => Some(Self::Fatal),
+            _ => None,
+        }
+    }
+}
+
+fn strip_ansi(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\u{1b}' {
+            for next in chars.by_ref() {
+                if next.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+        out.push(ch);
+    }
+    out
+}
+
+fn parse_log_line(line: &str) -> LogEntry {
+    let clean = strip_ansi(line);
+    let mut rest = clean.trim_start();
+    for _ in 0..5 {
+        if rest.is_empty() {
+            break;
+        }
+        let token_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let word: String = rest[..token_end]
+            .trim_start_matches('[')
+            .chars()
+            .take_while(char::is_ascii_alphabetic)
+            .collect();
+        if let Some(level) = LogLevel::parse(&word) {
+            let ts = clean[..clean.len() - rest.len()].trim();
+            return LogEntry {
+                level: Some(level),
+                ts: (!ts.is_empty()).then(|| ts.to_string()),
+                msg: rest[token_end..].trim().to_string(),
+            };
+        }
+        rest = rest[token_end..].trim_start();
+    }
+    LogEntry {
+        level: None,
+        ts: None,
+        msg: clean.trim().to_string(),
+    }
+}
+
+fn log_line_level(line: &str) -> Option<LogLevel> {
+    parse_log_line(line).level
+}
+
+fn log_line_passes(line: &str, min_level: Option<LogLevel>) -> bool {
+    match (min_level, log_line_level(line)) {
+        (Some(min), Some(level)) => level >= min,
+        _ => true,
+    }
+}
+
+fn log_was_truncated(reader: &mut BufReader<std::fs::File>, path: &PathBuf) -> bool {
+    let Ok(position) = reader.stream_position() else {
+        return false;
+    };
+    fs::metadata(path)
+        .map(|meta| meta.len() < position)
+        .unwrap_or(false)
+}
+
+fn trim_log_file(path: &PathBuf, keep_bytes: u64, max_bytes: u64) -> Result<bool, AppError> {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return Ok(false),
+    };
+    let len = meta.len();
+    if len <= max_bytes {
+        return Ok(false);
+    }
+    let keep = keep_bytes.min(len);
+    let start = len.saturating_sub(keep);
+    let mut file = fs::File::open(path).map_err(|e| err("LOG_ERROR", e.to_string()))?;
+    file.seek(SeekFrom::Start(start))
+        .map_err(|e| err("LOG_ERROR", e.to_string()))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)
+        .map_err(|e| err("LOG_ERROR", e.to_string()))?;
+
+    let mut out = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(path)
+        .map_err(|e| err("LOG_ERROR", e.to_string()))?;
+    out.write_all(&buf)
+        .map_err(|e| err("LOG_ERROR", e.to_string()))?;
+    Ok(true)
+}
+
+fn rotated_log_path(path: &Path, index: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{index}"));
+    PathBuf::from(name)
+}
+
+fn rotate_log_file(path: &PathBuf, max_bytes: u64, count: u32) -> Result<bool, AppError> {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return Ok(false),
+    };
+    if meta.len() <= max_bytes {
+        return Ok(false);
+    }
+    for index in (1..count).rev() {
+        let from = rotated_log_path(path, index);
+        if from.exists() {
+            fs::rename(&from, rotated_log_path(path, index + 1))
+                .map_err(|e| err("LOG_ERROR", e.to_string()))?;
+        }
+    }
+    // sing-box keeps the log open in append mode, so it cannot be renamed away. Only the
+    // bytes present when rotation started are moved out; whatever sing-box appended while
+    // they were copied is written back after the truncate. A line landing between that
+    // read and the truncate is still lost.
+    let rotated_len = meta.len();
+    let log_error = |e: std::io::Error| err("LOG_ERROR", e.to_string());
+    let mut log = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(log_error)?;
+    let mut rotated = fs::File::create(rotated_log_path(path, 1)).map_err(log_error)?;
+    move_log_prefix(&mut log, &mut rotated, rotated_len).map_err(log_error)?;
+    Ok(true)
+}
+
+/// Moves the first `len` bytes of `log` into `rotated` and keeps only the rest in `log`.
+fn move_log_prefix(log: &mut fs::File, rotated: &mut fs::File, len: u64) -> std::io::Result<()> {
+    log.seek(SeekFrom::Start(0))?;
+    std::io::copy(&mut Read::by_ref(log).take(len), rotated)?;
+    let mut appended = Vec::new();
+    log.seek(SeekFrom::Start(len))?;
+    log.read_to_end(&mut appended)?;
+    log.set_len(0)?;
+    log.seek(SeekFrom::Start(0))?;
+    log.write_all(&appended)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WindowGeometry {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+struct MonitorArea {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+impl MonitorArea {
+    fn overlaps(&self, geometry: &WindowGeometry) -> bool {
+        let overlap = |start: i32, len: u32, area_start: i32, area_len: u32| {
+            let end = (start as i64 + len as i64).min(area_start as i64 + area_len as i64);
+            end - (start as i64).max(area_start as i64)
+        };
+        overlap(geometry.x, geometry.width, self.x, self.width) >= MIN_VISIBLE_WINDOW_PX
+            && overlap(geometry.y, geometry.height, self.y, self.height) >= MIN_VISIBLE_WINDOW_PX
+    }
+}
+
+/// Keeps a saved window reachable: if it no longer overlaps any monitor (one was unplugged
+/// or the layout changed), it is shrunk to fit and centered on the first monitor.
+fn clamp_window_geometry(geometry: WindowGeometry, monitors: &[MonitorArea]) -> WindowGeometry {
+    if monitors.iter().any(|monitor| monitor.overlaps(&geometry)) {
+        return geometry;
+    }
+    let Some(monitor) = monitors.first() else {
+        return geometry;
+    };
+    let width = geometry.width.min(monitor.width);
+    let height = geometry.height.min(monitor.height);
+    WindowGeometry {
+        x: monitor.x + ((monitor.width - width) / 2) as i32,
+        y: monitor.y + ((monitor.height - height) / 2) as i32,
+        width,
+        height,
+    }
+}
+
+fn save_window_geometry(app: &AppHandle, window: &WebviewWindow) {
+    let minimized = window.is_minimized().unwrap_or(true);
+    let maximized = window.is_maximized().unwrap_or(true);
+    if minimized || maximized || !window.is_visible().unwrap_or(false) {
+        return;
+    }
+    let (Ok(position), Ok(size)) = (window.outer_position(), window.inner_size()) else {
+        return;
+    };
+    let geometry = WindowGeometry {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+    };
+    let Ok(path) = resolve_window_state_path(app) else {
+        return;
+    };
+    if let Ok(content) = serde_json::to_string_pretty(&geometry) {
+        let _ = fs::write(path, content);
+    }
+}
+
+fn restore_window_geometry(app: &AppHandle) {
+    let Some(window) = app.get_webview_window("main") else {
+        return;
+    };
+    let Some(geometry) = resolve_window_state_path(app)
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|raw| serde_json::from_str::<WindowGeometry>(&raw).ok())
+    else {
+        return;
+    };
+    let primary = window.primary_monitor().ok().flatten();
+    let monitors: Vec<MonitorArea> = primary
+        .iter()
+        .chain(window.available_monitors().unwrap_or_default().iter())
+        .map(|monitor| MonitorArea {
+            x: monitor.position().x,
+            y: monitor.position().y,
+            width: monitor.size().width,
+            height: monitor.size().height,
+        })
+        .collect();
+    if monitors.is_empty() {
+        return;
+    }
+    let geometry = clamp_window_geometry(geometry, &monitors);
+    let _ = window.set_size(PhysicalSize::new(geometry.width, geometry.height));
+    let _ = window.set_position(PhysicalPosition::new(geometry.x, geometry.y));
+}
+
+fn show_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
+
+fn hide_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window("main") {
+        save_window_geometry(app, &window);
+        let _ = window.hide();
+    }
+}
+
+fn window_should_hide(visible: bool, minimized: bool, focused: bool) -> bool {
+    visible && !minimized && focused
+}
+
+#[tauri::command]
+fn toggle_main_window(app: AppHandle) {
+    let Some(window) = app.get_webview_window("main") else {
+        return;
+    };
+    let hide = window_should_hide(
+        window.is_visible().unwrap_or(false),
+        window.is_minimized().unwrap_or(false),
+        window.is_focused().unwrap_or(false),
+    );
+    if hide {
+        hide_main_window(&app);
+    } else {
+        show_main_window(&app);
+    }
+}
+
+fn tray_tooltip(mode: ProxyMode, running: bool, active_tag: Option<&str>) -> String {
+    if !running || mode == ProxyMode::Off {
+        return format!("YotsubaCore — {}", ProxyMode::Off.label());
+    }
+    match active_tag {
+        Some(tag) => format!("YotsubaCore — {} · {tag}", mode.label()),
+        None => format!("YotsubaCore — {}", mode.label()),
+    }
+}
+
+fn update_tray_tooltip(app: &AppHandle) {
+    let Some(tray_state) = app.try_state::<TrayState>() else {
+        return;
+    };
+    let Some(state) = app.try_state::<SharedState>() else {
+        return;
+    };
+    let (mode, running) = match state.lock() {
+        Ok(mut guard) => {
+            refresh_state(&mut guard);
+            (guard.mode, guard.child.is_some())
+        }
+        Err(_) => return,
+    };
+    let active_tag = load_profile_state(app).active_tag_for(mode);
+    let tooltip = tray_tooltip(mode, running, active_tag.as_deref());
+    let _ = tray_state.tray.set_tooltip(Some(tooltip));
+}
+
+fn selectable_tags(profile: &Value, allow_direct: bool) -> Vec<String> {
+    let tags: Vec<String> = profile
+        .get("outbounds")
+        .and_then(Value::as_array)
+        .map(|outbounds| {
+            outbounds
+                .iter()
+                .filter_map(|item| item.get("tag").and_then(Value::as_str))
+                .map(|tag| tag.to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    selector_candidates(&tags, allow_direct)
+}
+
+fn build_tray_menu(app: &AppHandle) -> tauri::Result<Menu<tauri::Wry>> {
+    let current_mode = app
+        .try_state::<SharedState>()
+        .and_then(|state| {
+            state.lock().ok().map(|mut guard| {
+                refresh_state(&mut guard);
+                guard.mode
+            })
+        })
+        .unwrap_or_default();
+    let allow_direct = load_app_state(app).settings.allow_direct_selection;
+    let tags = load_profile_json(app)
+        .map(|profile| selectable_tags(&profile, allow_direct))
+        .unwrap_or_default();
+    let active_tag = load_profile_state(app).active_tag_for(current_mode);
+
+    let mut modes = SubmenuBuilder::new(app, "Режим");
+    for mode in [ProxyMode::Off, ProxyMode::Selected, ProxyMode::Full] {
+        let item = CheckMenuItemBuilder::with_id(mode.tray_id(), mode.label())
+            .checked(mode == current_mode)
+            .build(app)?;
+        modes = modes.item(&item);
+    }
+    let modes = modes.build()?;
+
+    let mut nodes = SubmenuBuilder::new(app, "Узлы").enabled(!tags.is_empty());
+    for tag in &tags {
+        let item = CheckMenuItemBuilder::with_id(format!("{TRAY_NODE_PREFIX}{tag}"), tag)
+            .checked(active_tag.as_deref() == Some(tag.as_str()))
+            .build(app)?;
+        nodes = nodes.item(&item);
+    }
+    let nodes = nodes.build()?;
+
+    let menu = Menu::new(app)?;
+    let open_item = MenuItemBuilder::with_id(TRAY_OPEN_ID, "Открыть").build(app)?;
+    let exit_item = MenuItemBuilder::with_id(TRAY_EXIT_ID, "Закрыть").build(app)?;
+    menu.append_items(&[&open_item, &modes, &nodes, &exit_item])?;
+    Ok(menu)
+}
+
+fn update_tray_menu(app: &AppHandle) {
+    let Some(tray_state) = app.try_state::<TrayState>() else {
+        return;
+    };
+    if let Ok(menu) = build_tray_menu(app) {
+        let _ = tray_state.tray.set_menu(Some(menu));
+    }
+}
+
+fn refresh_tray(app: &AppHandle) {
+    update_tray_tooltip(app);
+    update_tray_menu(app);
+}
+
+fn handle_tray_menu_event(app: &AppHandle, id: &str) {
+    if let Some(mode) = ProxyMode::from_tray_id(id) {
+        let saved = load_app_state(app);
+        let state = app.state::<SharedState>();
+        let _ = apply_mode(
+            app,
+            state.inner(),
+            mode,
+            saved.active_rules(),
+            saved.force_ipv4_ru,
+        );
+        return;
+    }
+    if let Some(tag) = id.strip_prefix(TRAY_NODE_PREFIX) {
+        if select_node(app, tag, None).is_ok() {
+            let _ = restart_if_running(app);
+        }
+        refresh_tray(app);
+    }
+}
+
+fn current_status(app: &AppHandle, state: &mut ProxyState) -> ProxyStatus {
+    let mut status = status_of(state);
+    status.profile_path = resolve_profile_path(app)
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    status.config_path = resolve_config_path(app)
+        .ok()
+        .and_then(|path| path.exists().then(|| path.display().to_string()));
+    status.log_path = resolve_log_path(app)
+        .ok()
+        .and_then(|path| path.exists().then(|| path.display().to_string()));
+    status.singbox_version = app.state::<SingboxVersion>().cached();
+    status
+}
+
+/// The status as far as `state` knows it; paths and the sing-box version are left empty.
+fn status_of(state: &mut ProxyState) -> ProxyStatus {
+    refresh_state(state);
+    let pid = state.child.as_ref().map(|child| child.id());
+    let running = state.child.is_some();
+    let listen_address = running.then(|| {
+        format!("{}:{LOCAL_PROXY_PORT}", mixed_listen_host(state.settings.allow_lan))
+    });
+    let mut warnings = Vec::new();
+    if running && state.settings.allow_lan {
+        warnings.push(format!(
+            "LAN access is enabled: the proxy on port {LOCAL_PROXY_PORT} is reachable from other devices"
+        ));
+    }
+    if running && !state.rule_conflicts.is_empty() {
+        warnings.push(format!(
+            "Listed as both proxy and direct, routed direct: {}",
+            state.rule_conflicts.join(", ")
+        ));
+    }
+
+    ProxyStatus {
+        running: state.child.is_some(),
+        mode: state.mode,
+        pid,
+        last_exit: state.last_exit,
+        last_error: state.last_error.clone(),
+        listen_address,
+        ipv6: running.then_some(state.settings.ipv6),
+        selected_final: running.then_some(state.settings.selected_final),
+        selector_change: state.selector_change.filter(|_| running),
+        tun_addresses: if running {
+            state.settings.tun_addresses()
+        } else {
+            Vec::new()
+        },
+        rule_conflicts: if running {
+            state.rule_conflicts.clone()
+        } else {
+            Vec::new()
+        },
+        test_url: running.then(|| state.settings.test_url.trim().to_string()),
+        test_timeout_ms: running.then(|| state.settings.test_timeout().as_millis() as u64),
+        log_max_bytes: running.then_some(state.settings.log_max_bytes),
+        log_keep_bytes: running.then_some(state.settings.log_keep_bytes),
+        hijack_dns: running.then_some(state.settings.hijack_dns),
+        hijack_dns_proxy_only: running.then_some(state.settings.hijack_dns_proxy_only),
+        warnings,
+        ..ProxyStatus::default()
+    }
+}
+
+fn is_system_path(path: &str) -> bool {
+    let normalized = path.replace('\\', "/").to_lowercase();
+    let without_drive = match normalized.split_once(":/") {
+        Some((drive, rest)) if drive.len() == 1 => format!("/{rest}"),
+        _ => normalized,
+    };
+    SYSTEM_PATH_PREFIXES
+        .iter()
+        .any(|prefix| without_drive.starts_with(prefix))
+}
+
+fn list_running_processes(sort: ProcessSort) -> Vec<ProcessEntry> {
+    let mut refresh = ProcessRefreshKind::new().with_exe(UpdateKind::OnlyIfNotSet);
+    match sort {
+        ProcessSort::Name => {}
+        ProcessSort::Cpu => refresh = refresh.with_cpu(),
+        ProcessSort::Memory => refresh = refresh.with_memory(),
+    }
+    let mut system = System::new_with_specifics(RefreshKind::new().with_processes(refresh));
+    if sort == ProcessSort::Cpu {
+        std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+        system.refresh_processes_specifics(refresh);
+    }
+
+    let mut entries: HashMap<String, ProcessEntry> = HashMap::new();
+    for (pid, process) in system.processes() {
+        let Some(path) = process.exe().and_then(|value| value.to_str()) else {
+            continue;
+        };
+        let lower = path.to_lowercase();
+        if !lower.ends_with(".exe") {
+            continue;
+        }
+        let name = process.name().to_string();
+        let entry = entries.entry(path.to_string()).or_insert_with(|| {
+            let fallback = PathBuf::from(path)
+                .file_name()
+                .and_then(|value| value.to_str())
+                .unwrap_or(path)
+                .to_string();
+            ProcessEntry {
+                name: if name.is_empty() { fallback } else { name.clone() },
+                path: path.to_string(),
+                count: 0,
+                pids: Vec::new(),
+                cpu: 0.0,
+                memory: 0,
+            }
+        });
+        if entry.name.is_empty() && !name.is_empty() {
+            entry.name = name.clone();
         }
+        entry.count += 1;
+        entry.pids.push(pid.as_u32());
+        entry.cpu += process.cpu_usage();
+        entry.memory += process.memory();
     }
 
-    if let Some(index) = proxy_index {
-        let proxy_type = outbounds[index]
+    let mut list: Vec<ProcessEntry> = entries.into_values().collect();
+    list.sort_by(|a, b| sort.compare(a, b));
+    list
+}
+
+fn unique_tag(base: &str, used: &mut HashSet<String>) -> String {
+    let mut candidate = base.to_string();
+    let mut index = 2;
+    while used.contains(&candidate) {
+        candidate = format!("{base}-{index}");
+        index += 1;
+    }
+    used.insert(candidate.clone());
+    candidate
+}
+
+fn guess_tag(raw: &Value, fallback: &str) -> String {
+    raw.get("tag")
+        .and_then(Value::as_str)
+        .filter(|tag| !tag.trim().is_empty())
+        .map(|tag| tag.to_string())
+        .or_else(|| raw.get("ps").and_then(Value::as_str).map(|tag| tag.to_string()))
+        .unwrap_or_else(|| fallback.to_string())
+}
+
+fn outbound_identity(outbound: &Value) -> Option<String> {
+    let server = outbound.get("server").and_then(Value::as_str)?;
+    let identity = json!([
+        outbound.get("type"),
+        server,
+        outbound.get("server_port"),
+        outbound.get("uuid"),
+        outbound.get("username"),
+        outbound.get("password"),
+        outbound.get("method"),
+    ]);
+    Some(identity.to_string())
+}
+
+const OUTBOUND_REQUIRED_FIELDS: [(&str, &[&str]); 11] = [
+    (
+        "shadowsocks",
+        &["server", "server_port", "method", "password"],
+    ),
+    ("vmess", &["server", "server_port", "uuid"]),
+    ("vless", &["server", "server_port", "uuid"]),
+    ("trojan", &["server", "server_port", "password"]),
+    ("hysteria", &["server", "server_port"]),
+    ("hysteria2", &["server", "server_port", "password"]),
+    ("tuic", &["server", "server_port", "uuid", "password"]),
+    ("anytls", &["server", "server_port", "password"]),
+    ("socks", &["server", "server_port"]),
+    ("http", &["server", "server_port"]),
+    ("selector", &["outbounds"]),
+];
+
+fn validate_outbound(outbound: &Value) -> Result<(), String> {
+    let obj = outbound
+        .as_object()
+        .ok_or_else(|| "not an object".to_string())?;
+    let kind = obj
+        .get("type")
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|kind| !kind.is_empty())
+        .ok_or_else(|| "missing type".to_string())?;
+    let required = OUTBOUND_REQUIRED_FIELDS
+        .iter()
+        .find(|(name, _)| *name == kind)
+        .map(|(_, fields)| *fields)
+        .unwrap_or(&[]);
+    for field in required {
+        let present = match obj.get(*field) {
+            Some(Value::String(value)) => !value.trim().is_empty(),
+            Some(Value::Number(_)) => true,
+            Some(Value::Array(items)) => !items.is_empty(),
+            _ => false,
+        };
+        if !present {
+            return Err(format!("{kind} outbound is missing {field}"));
+        }
+    }
+    if let Some(port) = obj.get("server_port") {
+        let valid = port
+            .as_u64()
+            .is_some_and(|port| (1..=65535).contains(&port));
+        if !valid {
+            return Err(format!("{kind} outbound has an invalid server_port"));
+        }
+    }
+    Ok(())
+}
+
+fn sync_transport(existing: &mut Value, incoming: &Value) {
+    let Some(existing) = existing.as_object_mut() else {
+        return;
+    };
+    match incoming.get("transport") {
+        Some(transport) => {
+            existing.insert("transport".to_string(), transport.clone());
+        }
+        None => {
+            existing.remove("transport");
+        }
+    }
+}
+
+struct MergedOutbounds {
+    added_tags: Vec<String>,
+    errors: Vec<String>,
+    duplicates: Vec<String>,
+}
+
+fn merge_outbounds(
+    outbounds: &mut Vec<Value>,
+    new_outbounds: Vec<Value>,
+    dedupe: bool,
+) -> MergedOutbounds {
+    let mut used_tags: HashSet<String> = outbounds
+        .iter()
+        .filter_map(|item| item.get("tag").and_then(Value::as_str))
+        .map(|tag| tag.to_string())
+        .collect();
+    let mut identities: HashMap<String, usize> = outbounds
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| outbound_identity(item).map(|identity| (identity, index)))
+        .collect();
+
+    let mut merged = MergedOutbounds {
+        added_tags: Vec::new(),
+        errors: Vec::new(),
+        duplicates: Vec::new(),
+    };
+    for outbound in new_outbounds {
+        let Some(obj) = outbound.as_object() else {
+            merged.errors.push("Invalid outbound object".to_string());
+            continue;
+        };
+        // A re-imported node carries its current transport, so the stored copy
+        // follows it whether or not the import keeps a second entry.
+        let identity = outbound_identity(&outbound);
+        if let Some(&index) = identity.as_ref().and_then(|id| identities.get(id)) {
+            sync_transport(&mut outbounds[index], &outbound);
+            if dedupe {
+                merged.duplicates.push(guess_tag(&outbound, "outbound"));
+                continue;
+            }
+        } else if let Some(identity) = identity {
+            identities.insert(identity, outbounds.len());
+        }
+
+        let fallback = obj
             .get("type")
             .and_then(Value::as_str)
-            .unwrap_or("");
-        let needs_selector = active_tag.is_some() && tags.len() > 1;
-        if proxy_type == "selector" {
-            let selector_tags: Vec<String> = tags
-                .iter()
-                .filter(|tag| *tag != "proxy" && *tag != "direct")
-                .cloned()
-                .collect();
-            if !selector_tags.is_empty() {
-                outbounds[index]["outbounds"] = json!(selector_tags);
-            }
-            if let Some(tag) = active_tag {
-                outbounds[index]["default"] = json!(tag);
-            }
-        } else if needs_selector {
-            let renamed = unique_tag("proxy-origin", &mut used_tags);
-            outbounds[index]["tag"] = json!(renamed.clone());
-            tags = outbounds
-                .iter()
-                .filter_map(|item| item.get("tag").and_then(Value::as_str))
-                .map(|tag| tag.to_string())
-                .collect();
-        let selected_tag = active_tag.unwrap_or_else(|| renamed.clone());
-        let selector_tags: Vec<String> = tags
-            .iter()
-            .filter(|tag| *tag != "proxy" && *tag != "direct")
-            .cloned()
-            .collect();
-        if selector_tags.is_empty() {
-            return Err(err("PROFILE_OUTBOUNDS_MISSING", "no proxy outbounds"));
+            .unwrap_or("profile");
+        let tag = guess_tag(&outbound, fallback);
+        let unique = unique_tag(&tag, &mut used_tags);
+        let mut outbound = outbound;
+        outbound["tag"] = json!(unique.clone());
+        outbounds.push(outbound);
+        merged.added_tags.push(unique);
+    }
+    merged
+}
+
+fn append_outbounds(
+    app: &AppHandle,
+    new_outbounds: Vec<Value>,
+    dedupe: bool,
+) -> Result<ImportResult, AppError> {
+    let mut profile = load_profile_json(app)?;
+    let profile_obj = profile
+        .as_object_mut()
+        .ok_or_else(|| err("PROFILE_INVALID", "root must be an object"))?;
+    let mut outbounds = profile_obj
+        .get("outbounds")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let MergedOutbounds {
+        added_tags,
+        errors,
+        duplicates,
+    } = merge_outbounds(&mut outbounds, new_outbounds, dedupe);
+
+    profile_obj.insert("outbounds".to_string(), Value::Array(outbounds));
+    save_profile_json(app, &profile)?;
+
+    let mut state = load_profile_state(app);
+    if state.active_tag.is_none() {
+        if let Some(tag) = added_tags.first() {
+            state.active_tag = Some(tag.clone());
+            let _ = save_profile_state(app, &state);
         }
-        outbounds.push(json!({
-            "type": "selector",
-            "tag": "proxy",
-            "outbounds": selector_tags,
-            "default": selected_tag
-        }));
-            tags = outbounds
-                .iter()
-                .filter_map(|item| item.get("tag").and_then(Value::as_str))
-                .map(|tag| tag.to_string())
-                .collect();
+    }
+    refresh_tray(app);
+
+    Ok(ImportResult {
+        profile: profile_data(app, &profile),
+        added: added_tags.len(),
+        added_tags,
+        errors,
+        duplicates,
+    })
+}
+
+fn select_node(app: &AppHandle, tag: &str, mode: Option<ProxyMode>) -> Result<(), AppError> {
+    if load_app_state(app).settings.auto_select {
+        return Err(err(
+            "AUTO_SELECT_ENABLED",
+            "nodes are picked automatically while auto select is on",
+        ));
+    }
+    let mut state = load_profile_state(app);
+    match mode {
+        Some(mode) => {
+            state.active_tag_by_mode.insert(mode, tag.to_string());
         }
-    } else {
-        let selected_tag = active_tag.unwrap_or_else(|| tags[0].clone());
-        let selector_tags: Vec<String> = tags
-            .iter()
-            .filter(|tag| *tag != "proxy" && *tag != "direct")
-            .cloned()
-            .collect();
-        if selector_tags.is_empty() {
-            return Err(err("PROFILE_OUTBOUNDS_MISSING", "no proxy outbounds"));
+        None => state.active_tag = Some(tag.to_string()),
+    }
+    save_profile_state(app, &state)
+}
+
+fn running_mode(app: &AppHandle) -> Option<ProxyMode> {
+    let state = app.state::<SharedState>();
+    let mut guard = state.lock().expect("state lock");
+    refresh_state(&mut guard);
+    guard.child.is_some().then_some(guard.mode)
+}
+
+fn reapply_mode(app: &AppHandle, mode: ProxyMode) -> Result<(), AppError> {
+    let saved = load_app_state(app);
+    apply_mode(
+        app,
+        app.state::<SharedState>().inner(),
+        mode,
+        saved.active_rules(),
+        saved.force_ipv4_ru,
+    )?;
+    Ok(())
+}
+
+fn restart_if_running(app: &AppHandle) -> Result<(), AppError> {
+    if let Some(mode) = running_mode(app) {
+        reapply_mode(app, mode)?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn get_status(app: AppHandle, state: State<SharedState>) -> ProxyStatus {
+    let mut guard = state.lock().expect("state lock");
+    current_status(&app, &mut guard)
+}
+
+/// Reaps an exit first so the status read right after does not bring the error back.
+fn clear_errors(state: &mut ProxyState) {
+    refresh_state(state);
+    state.last_error = None;
+    state.last_exit = None;
+}
+
+#[tauri::command]
+fn clear_last_error(app: AppHandle, state: State<SharedState>) -> ProxyStatus {
+    let status = {
+        let mut guard = state.lock().expect("state lock");
+        clear_errors(&mut guard);
+        current_status(&app, &mut guard)
+    };
+    emit_proxy_state(&app);
+    status
+}
+
+#[tauri::command(async)]
+fn set_settings(
+    app: AppHandle,
+    state: State<SharedState>,
+    settings: ProxySettings,
+) -> Result<ProxyStatus, AppError> {
+    validate_settings(&settings)?;
+    let mut saved = load_app_state(&app);
+    saved.settings = settings;
+    save_app_state(&app, &saved)?;
+    restart_if_running(&app)?;
+    let mut guard = state.lock().expect("state lock");
+    Ok(current_status(&app, &mut guard))
+}
+
+fn download_file(url: &str, path: &PathBuf) -> Result<(), AppError> {
+    let response = ureq::get(url)
+        .timeout(RULE_SET_DOWNLOAD_TIMEOUT)
+        .call()
+        .map_err(|e| err("RULE_SET_ERROR", e.to_string()))?;
+    let mut bytes = Vec::new();
+    response
+        .into_reader()
+        .take(RULE_SET_MAX_BYTES)
+        .read_to_end(&mut bytes)
+        .map_err(|e| err("RULE_SET_ERROR", e.to_string()))?;
+    if bytes.is_empty() {
+        return Err(err("RULE_SET_ERROR", "empty response"));
+    }
+    let tmp_path = path.with_extension("download");
+    fs::write(&tmp_path, &bytes).map_err(|e| err("RULE_SET_ERROR", e.to_string()))?;
+    fs::rename(&tmp_path, path).map_err(|e| {
+        let _ = fs::remove_file(&tmp_path);
+        err("RULE_SET_ERROR", e.to_string())
+    })
+}
+
+/// Caches every remote rule set the generated config references (only geoip-ru today).
+/// A failed URL is reported in `errors` next to the files that did download.
+#[tauri::command(async)]
+fn download_rule_sets(app: AppHandle) -> Result<RuleSetDownload, AppError> {
+    let settings = load_app_state(&app).settings;
+    let sources = [(GEOIP_RU_FILE, settings.geoip_ru_url.as_str())];
+    let mut written = Vec::new();
+    let mut errors = Vec::new();
+    for (name, url) in sources {
+        let path = resolve_rule_set_path(&app, name)?;
+        match download_file(url, &path) {
+            Ok(()) => written.push(path.display().to_string()),
+            Err(error) => errors.push(format!("{url}: {error}")),
+        }
+    }
+    Ok(RuleSetDownload { written, errors })
+}
+
+#[tauri::command(async)]
+fn refresh_rule_sets(app: AppHandle) -> Result<(), AppError> {
+    let path = resolve_rule_set_path(&app, GEOIP_RU_FILE)?;
+    if path.exists() {
+        fs::remove_file(&path).map_err(|e| err("RULE_SET_ERROR", e.to_string()))?;
+    }
+    restart_if_running(&app)
+}
+
+#[tauri::command]
+fn encrypt_profiles(app: AppHandle) -> Result<(), AppError> {
+    let groups = load_profile_groups(&app);
+    for group in &groups.groups {
+        let path = resolve_group_profile_path(&app, group)?;
+        if !path.exists() {
+            continue;
+        }
+        backup_group(&app, group, true)?;
+        let profile = read_profile_file(&path)?;
+        let content = write_profile_file(&path, &profile, true)?;
+        if *group == groups.active {
+            app.state::<ProfileWatch>().remember_write(content);
+        }
+    }
+    encrypt_backups(&app)?;
+    let mut saved = load_app_state(&app);
+    saved.settings.encrypt_profile = true;
+    save_app_state(&app, &saved)
+}
+
+/// Rewrites every plaintext profile left in older backups so no copy keeps the credentials.
+fn encrypt_backups(app: &AppHandle) -> Result<(), AppError> {
+    let dir = resolve_backup_dir(app)?;
+    for name in list_backup_names(app)? {
+        let path = dir.join(name).join(PROFILE_FILE);
+        if path.exists() {
+            copy_profile(&path, &path, true)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies a profile byte for byte, or encrypts it on the way when `encrypt` is set.
+fn copy_profile(source: &Path, target: &Path, encrypt: bool) -> Result<(), AppError> {
+    let raw = fs::read_to_string(source).map_err(|e| err("BACKUP_ERROR", e.to_string()))?;
+    if !encrypt || raw.trim_start().starts_with(PROFILE_ENCRYPTED_PREFIX) {
+        if source != target {
+            fs::copy(source, target).map_err(|e| err("BACKUP_ERROR", e.to_string()))?;
+        }
+        return Ok(());
+    }
+    let content = encode_profile_content(raw, true)?;
+    write_file_atomic(target, content).map_err(|e| err("BACKUP_ERROR", e.to_string()))
+}
+
+fn backup_files(app: &AppHandle, group: &str) -> Result<Vec<(&'static str, PathBuf)>, AppError> {
+    Ok(vec![
+        (PROFILE_FILE, resolve_group_profile_path(app, group)?),
+        (PROFILE_STATE_FILE, resolve_group_state_path(app, group)?),
+        (APP_STATE_FILE, resolve_app_state_path(app)?),
+    ])
+}
+
+fn read_backup_group(dir: &Path) -> Option<String> {
+    let raw = fs::read_to_string(dir.join(BACKUP_META_FILE)).ok()?;
+    let meta: BackupMeta = serde_json::from_str(&raw).ok()?;
+    validate_group_name(&meta.group).ok()
+}
+
+fn list_backup_names(app: &AppHandle) -> Result<Vec<String>, AppError> {
+    let dir = resolve_backup_dir(app)?;
+    let entries = fs::read_dir(&dir).map_err(|e| err("BACKUP_ERROR", e.to_string()))?;
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| is_backup_name(name))
+        .collect();
+    names.sort_by(|a, b| b.cmp(a));
+    Ok(names)
+}
+
+fn is_backup_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_digit())
+}
+
+fn prune_backups(names: &[String], keep: usize) -> &[String] {
+    names.get(keep..).unwrap_or(&[])
+}
+
+fn backup_state(app: &AppHandle) -> Result<String, AppError> {
+    let group = load_profile_groups(app).active;
+    let encrypt = load_app_state(app).settings.encrypt_profile;
+    backup_group(app, &group, encrypt)
+}
+
+fn backup_group(app: &AppHandle, group: &str, encrypt: bool) -> Result<String, AppError> {
+    let mut millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| err("BACKUP_ERROR", e.to_string()))?
+        .as_millis();
+    let dir = resolve_backup_dir(app)?;
+    // Backing up several groups in a row can land in the same millisecond.
+    while dir.join(format!("{millis:016}")).exists() {
+        millis += 1;
+    }
+    let name = format!("{millis:016}");
+    let target = dir.join(&name);
+    fs::create_dir_all(&target).map_err(|e| err("BACKUP_ERROR", e.to_string()))?;
+    let meta = serde_json::to_string_pretty(&BackupMeta {
+        group: group.to_string(),
+    })
+    .map_err(|e| err("BACKUP_ERROR", e.to_string()))?;
+    fs::write(target.join(BACKUP_META_FILE), meta)
+        .map_err(|e| err("BACKUP_ERROR", e.to_string()))?;
+    for (file_name, source) in backup_files(app, group)? {
+        if !source.exists() {
+            continue;
+        }
+        if file_name == PROFILE_FILE {
+            copy_profile(&source, &target.join(file_name), encrypt)?;
+        } else {
+            fs::copy(&source, target.join(file_name))
+                .map_err(|e| err("BACKUP_ERROR", e.to_string()))?;
         }
-        outbounds.push(json!({
-            "type": "selector",
-            "tag": "proxy",
-            "outbounds": selector_tags,
-            "default": selected_tag
-        }));
-        tags = outbounds
-            .iter()
-            .filter_map(|item| item.get("tag").and_then(Value::as_str))
-            .map(|tag| tag.to_string())
-            .collect();
     }
 
-    let has_direct = tags.iter().any(|tag| tag == "direct");
-    if !has_direct {
-        outbounds.push(json!({
-            "type": "direct",
-            "tag": "direct"
-        }));
+    let names = list_backup_names(app)?;
+    for stale in prune_backups(&names, BACKUP_KEEP) {
+        let _ = fs::remove_dir_all(dir.join(stale));
     }
+    Ok(name)
+}
 
-    profile_obj.insert("outbounds".to_string(), Value::Array(outbounds));
+#[tauri::command]
+fn create_backup(app: AppHandle) -> Result<String, AppError> {
+    backup_state(&app)
+}
 
-    if !profile_obj.contains_key("log") {
-        profile_obj.insert(
-            "log".to_string(),
-            json!( {
-                "level": "info",
-                "output": log_path
-            }),
-        );
-    }
+#[tauri::command]
+fn list_backups(app: AppHandle) -> Result<Vec<String>, AppError> {
+    list_backup_names(&app)
+}
 
-    if !profile_obj.contains_key("dns") {
-        let mut dns = json!({
-            "servers": [
-                {
-                    "tag": "dns-local",
-                    "type": "local"
-                },
-                {
-                    "tag": "dns-remote",
-                    "type": "https",
-                    "server": "dns.google",
-                    "path": "/dns-query",
-                    "domain_resolver": "dns-local"
-                }
-            ],
-            "final": "dns-remote"
-        });
-        if force_ipv4_ru {
-            if let Some(dns_obj) = dns.as_object_mut() {
-                dns_obj.insert("strategy".to_string(), json!("prefer_ipv4"));
-                dns_obj.insert("reverse_mapping".to_string(), json!(true));
-                dns_obj.insert(
-                    "rules".to_string(),
-                    json!([{
-                        "domain_suffix": RU_IPV4_DOMAIN_SUFFIXES,
-                        "action": "route",
-                        "server": "dns-remote",
-                        "strategy": "ipv4_only"
-                    }]),
-                );
-            }
+#[tauri::command(async)]
+fn restore_backup(app: AppHandle, name: String) -> Result<ProfileData, AppError> {
+    if !is_backup_name(&name) {
+        return Err(err("BACKUP_MISSING", name));
+    }
+    let source_dir = resolve_backup_dir(&app)?.join(&name);
+    if !source_dir.is_dir() {
+        return Err(err("BACKUP_MISSING", name));
+    }
+    backup_state(&app)?;
+    // Backups taken before the group was recorded belong to whichever group is active.
+    let mut groups = load_profile_groups(&app);
+    let group = read_backup_group(&source_dir).unwrap_or_else(|| groups.active.clone());
+    for (file_name, target) in backup_files(&app, &group)? {
+        let source = source_dir.join(file_name);
+        if source.exists() {
+            let content = fs::read(&source).map_err(|e| err("BACKUP_ERROR", e.to_string()))?;
+            write_file_atomic(&target, content).map_err(|e| err("BACKUP_ERROR", e.to_string()))?;
         }
-        profile_obj.insert("dns".to_string(), dns);
     }
+    if !groups.groups.contains(&group) {
+        groups.groups.push(group.clone());
+    }
+    groups.active = group;
+    save_profile_groups(&app, &groups)?;
+    let restarted = restart_if_running(&app);
+    refresh_tray(&app);
+    restarted?;
+    let profile = load_profile_json(&app)?;
+    Ok(profile_data(&app, &profile))
+}
 
-    let mut inbounds = vec![json!({
-        "type": "tun",
-        "tag": "tun-in",
-        "address": ["172.19.0.1/30", "fdfe:dcba:9876::1/126"],
-        "auto_route": true,
-        "strict_route": true,
-        "stack": "system"
-    })];
-    inbounds.push(json!({
-        "type": "mixed",
-        "tag": LOCAL_PROXY_TAG,
-        "listen": LOCAL_PROXY_HOST,
-        "listen_port": LOCAL_PROXY_PORT
-    }));
-    profile_obj.insert("inbounds".to_string(), Value::Array(inbounds));
-
-    let geoip_ru_rule_set = build_geoip_ru_rule_set(app)?;
-    let (proxy_paths, direct_paths, proxy_names, direct_names) = normalize_rules(rules);
-    let route = match mode {
-        ProxyMode::Full => {
-            let mut rules = Vec::new();
-            rules.push(json!({
-                "action": "hijack-dns",
-                "port": 53
-            }));
-            rules.push(json!({
-                "action": "sniff"
-            }));
-            push_ru_bypass_rules(&mut rules);
-            rules.push(json!({
-                "inbound": [LOCAL_PROXY_TAG],
-                "outbound": "proxy"
-            }));
-            push_process_rules(&mut rules, &direct_paths, &direct_names, "direct");
-            json!({
-                "rules": rules,
-                "final": "proxy",
-                "auto_detect_interface": true,
-                "rule_set": [geoip_ru_rule_set]
-            })
+/// Moves everything the app keeps in `data_dir` aside into `backup_dir`, drops cached
+/// rule sets and writes the default profile. Returns the written profile file content.
+fn reset_data_dir(data_dir: &Path, backup_dir: &Path) -> Result<String, AppError> {
+    // Files outside the regular backup set move into it, so nothing is lost for good.
+    for name in [
+        PROFILE_GROUPS_FILE,
+        PROFILE_GROUPS_DIR,
+        TEMPLATE_FILE,
+        WINDOW_STATE_FILE,
+    ] {
+        let source = data_dir.join(name);
+        if source.exists() {
+            fs::rename(&source, backup_dir.join(name))
+                .map_err(|e| err("RESET_ERROR", e.to_string()))?;
         }
-        ProxyMode::Selected => {
-            let mut rules = Vec::new();
-            rules.push(json!({
-                "action": "hijack-dns",
-                "port": 53
-            }));
-            rules.push(json!({
-                "action": "sniff"
-            }));
-            push_ru_bypass_rules(&mut rules);
-            rules.push(json!({
-                "inbound": [LOCAL_PROXY_TAG],
-                "outbound": "proxy"
-            }));
-            push_process_rules(&mut rules, &direct_paths, &direct_names, "direct");
-            push_process_rules(&mut rules, &proxy_paths, &proxy_names, "proxy");
-            json!({
-                "rules": rules,
-                "final": "direct",
-                "auto_detect_interface": true,
-                "rule_set": [geoip_ru_rule_set]
-            })
+    }
+    for name in [APP_STATE_FILE, PROFILE_STATE_FILE] {
+        let path = data_dir.join(name);
+        if path.exists() {
+            fs::remove_file(&path).map_err(|e| err("RESET_ERROR", e.to_string()))?;
         }
-        ProxyMode::Off => json!({}),
-    };
-
-    if mode != ProxyMode::Off {
-        profile_obj.insert("route".to_string(), route);
     }
+    let rule_sets = data_dir.join(RULE_SET_DIR);
+    if rule_sets.exists() {
+        fs::remove_dir_all(&rule_sets).map_err(|e| err("RESET_ERROR", e.to_string()))?;
+    }
+    write_profile_file(&data_dir.join(PROFILE_FILE), &default_profile(), false)
+}
 
-    let config_path = resolve_config_path(app)?;
-    let content =
-        serde_json::to_string_pretty(&profile).map_err(|e| err("CONFIG_INVALID", e.to_string()))?;
-    fs::write(&config_path, content).map_err(|e| err("CONFIG_INVALID", e.to_string()))?;
-
-    Ok(config_path)
+#[tauri::command(async)]
+fn reset_to_defaults(app: AppHandle, state: State<SharedState>) -> Result<ResetResult, AppError> {
+    let backup = backup_state(&app)?;
+    {
+        let mut guard = state.lock().expect("state lock");
+        kill_child(&app, &mut guard);
+        guard.mode = ProxyMode::Off;
+        guard.last_error = None;
+        guard.watch_token = guard.watch_token.wrapping_add(1);
+    }
+    let data_dir = ensure_app_data_dir(&app)?;
+    let backup_dir = resolve_backup_dir(&app)?.join(&backup);
+    let content = reset_data_dir(&data_dir, &backup_dir)?;
+    app.state::<ProfileWatch>().remember_write(content);
+    let profile = default_profile();
+    emit_proxy_state(&app);
+    refresh_tray(&app);
+    Ok(ResetResult {
+        backup,
+        profile: profile_data(&app, &profile),
+    })
 }
 
-fn refresh_state(state: &mut ProxyState) {
-    if let Some(child) = state.child.as_mut() {
-        match child.try_wait() {
-            Ok(Some(status)) => {
-                state.last_exit = status.code();
-                state.child = None;
-                state.mode = ProxyMode::Off;
-            }
-            Ok(None) => {}
-            Err(err) => {
-                state.last_exit = Some(-1);
-                state.last_error = Some(err.to_string());
-                state.child = None;
-                state.mode = ProxyMode::Off;
-            }
-        }
+#[tauri::command]
+fn get_saved_state(app: AppHandle) -> SavedState {
+    let state = load_app_state(&app);
+    SavedState {
+        app_rules: state.active_rules(),
+        state,
     }
 }
 
-fn spawn_monitor(app: AppHandle, state: SharedState, token: u64) {
-    std::thread::spawn(move || loop {
-        std::thread::sleep(Duration::from_millis(750));
-        let exit_code = {
-            let mut guard = state.lock().expect("state lock");
-            if guard.watch_token != token {
-                return;
-            }
-            if let Some(child) = guard.child.as_mut() {
-                match child.try_wait() {
-                    Ok(Some(status)) => {
-                        guard.last_exit = status.code();
-                        guard.child = None;
-                        guard.mode = ProxyMode::Off;
-                        guard.last_error = None;
-                        status.code()
-                    }
-                    Ok(None) => None,
-                    Err(err) => {
-                        guard.last_exit = Some(-1);
-                        guard.last_error = Some(err.to_string());
-                        guard.child = None;
-                        guard.mode = ProxyMode::Off;
-                        Some(-1)
-                    }
-                }
-            } else {
-                return;
-            }
-        };
+#[tauri::command]
+fn take_state_recoveries(recoveries: State<StateRecoveries>) -> Vec<StateRecovered> {
+    recoveries.take()
+}
 
-        if exit_code.is_some() {
-            let _ = app.emit(
-                "proxy-exited",
-                ProxyExitPayload { code: exit_code },
-            );
-            return;
-        }
-    });
+#[tauri::command(async)]
+fn list_processes(
+    sort: Option<ProcessSort>,
+    query: Option<String>,
+    include_system: Option<bool>,
+) -> Vec<ProcessEntry> {
+    let include_system = include_system.unwrap_or(false);
+    let query = query
+        .map(|value| value.trim().to_lowercase())
+        .filter(|value| !value.is_empty());
+    list_running_processes(sort.unwrap_or_default())
+        .into_iter()
+        .filter(|entry| include_system || !is_system_path(&entry.path))
+        .filter(|entry| {
+            query.as_ref().is_none_or(|query| {
+                entry.name.to_lowercase().contains(query)
+                    || entry.path.to_lowercase().contains(query)
+            })
+        })
+        .collect()
 }
 
-fn spawn_log_tailer(app: AppHandle, state: SharedState, token: u64, log_path: PathBuf) {
-    std::thread::spawn(move || {
-        let mut reader = match open_log_reader(&log_path) {
-            Some(reader) => reader,
-            None => return,
-        };
+#[tauri::command]
+fn read_log_tail(
+    app: AppHandle,
+    limit: Option<usize>,
+    min_level: Option<String>,
+) -> Result<Vec<String>, AppError> {
+    let limit = limit.unwrap_or(200).max(1);
+    let min_level = match min_level.as_deref().map(str::trim) {
+        Some(value) if !value.is_empty() => Some(
+            LogLevel::parse(value)
+                .ok_or_else(|| err("LOG_ERROR", format!("unknown log level: {value}")))?,
+        ),
+        _ => None,
+    };
+    let path = resolve_log_path(&app)?;
+    tail_log_lines(&path, limit, min_level)
+}
 
-        let mut pending: Vec<String> = Vec::new();
-        let mut last_emit = Instant::now();
-        let mut last_trim = Instant::now();
+fn tail_log_lines(
+    path: &PathBuf,
+    limit: usize,
+    min_level: Option<LogLevel>,
+) -> Result<Vec<String>, AppError> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut file = fs::File::open(path).map_err(|e| err("LOG_ERROR", e.to_string()))?;
+    let file_len = file
+        .metadata()
+        .map_err(|e| err("LOG_ERROR", e.to_string()))?
+        .len();
+    if file_len == 0 {
+        return Ok(Vec::new());
+    }
 
-        loop {
-            std::thread::sleep(Duration::from_millis(200));
-            let stop = {
-                let guard = match state.lock() {
-                    Ok(guard) => guard,
-                    Err(_) => return,
-                };
-                guard.watch_token != token
-            };
-            if stop {
-                return;
-            }
+    let mut read_size: u64 = 64 * 1024;
+    let mut lines: Vec<String> = Vec::new();
+    loop {
+        let start = file_len.saturating_sub(read_size);
+        file.seek(SeekFrom::Start(start))
+            .map_err(|e| err("LOG_ERROR", e.to_string()))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)
+            .map_err(|e| err("LOG_ERROR", e.to_string()))?;
+        let text = String::from_utf8_lossy(&buf);
+        lines.clear();
+        lines.extend(
+            text.lines()
+                .filter(|line| log_line_passes(line, min_level))
+                .map(|line| line.to_string()),
+        );
+        if lines.len() >= limit || start == 0 {
+            break;
+        }
+        read_size = (read_size * 2).min(file_len);
+    }
 
-            if last_trim.elapsed() >= Duration::from_secs(2) {
-                if trim_log_file(&log_path, LOG_KEEP_BYTES, LOG_MAX_BYTES).unwrap_or(false) {
-                    if let Some(new_reader) = open_log_reader(&log_path) {
-                        reader = new_reader;
-                    }
-                }
-                last_trim = Instant::now();
-            }
+    if lines.len() > limit {
+        lines = lines.split_off(lines.len() - limit);
+    }
+    Ok(lines)
+}
 
-            loop {
-                let mut line = String::new();
-                match reader.read_line(&mut line) {
-                    Ok(0) => break,
-                    Ok(_) => {
-                        let trimmed = line.trim_end_matches(['\r', '\n']);
-                        if !trimmed.is_empty() {
-                            pending.push(trimmed.to_string());
-                        }
-                    }
-                    Err(_) => return,
-                }
-            }
+#[tauri::command]
+fn pause_log_stream(stream: State<LogStream>) {
+    stream.set_paused(true);
+}
 
-            if !pending.is_empty()
-                && (pending.len() >= 50 || last_emit.elapsed() >= Duration::from_millis(250))
-            {
-                let payload = LogBatchPayload {
-                    lines: pending.drain(..).collect(),
-                };
-                let _ = app.emit("proxy-log-batch", payload);
-                last_emit = Instant::now();
-            }
-        }
-    });
+#[tauri::command]
+fn resume_log_stream(stream: State<LogStream>) {
+    stream.set_paused(false);
 }
 
-fn open_log_reader(path: &PathBuf) -> Option<BufReader<std::fs::File>> {
-    let file = OpenOptions::new().read(true).open(path).ok()?;
-    let mut reader = BufReader::new(file);
-    if reader.seek(SeekFrom::End(0)).is_err() {
-        return None;
-    }
-    Some(reader)
+#[tauri::command]
+fn clear_log(app: AppHandle) -> Result<(), AppError> {
+    truncate_log(&resolve_log_path(&app)?)
 }
 
-fn trim_log_file(path: &PathBuf, keep_bytes: u64, max_bytes: u64) -> Result<bool, String> {
-    let meta = match fs::metadata(path) {
-        Ok(meta) => meta,
-        Err(_) => return Ok(false),
-    };
-    let len = meta.len();
-    if len <= max_bytes {
-        return Ok(false);
+fn truncate_log(path: &PathBuf) -> Result<(), AppError> {
+    if !path.exists() {
+        return Ok(());
     }
-    let keep = keep_bytes.min(len);
-    let start = len.saturating_sub(keep);
-    let mut file = fs::File::open(path).map_err(|e| err("LOG_ERROR", e.to_string()))?;
-    file.seek(SeekFrom::Start(start))
-        .map_err(|e| err("LOG_ERROR", e.to_string()))?;
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf)
-        .map_err(|e| err("LOG_ERROR", e.to_string()))?;
-
-    let mut out = OpenOptions::new()
+    OpenOptions::new()
         .write(true)
         .truncate(true)
         .open(path)
         .map_err(|e| err("LOG_ERROR", e.to_string()))?;
-    out.write_all(&buf)
-        .map_err(|e| err("LOG_ERROR", e.to_string()))?;
-    Ok(true)
+    Ok(())
 }
 
-fn show_main_window(app: &AppHandle) {
-    if let Some(window) = app.get_webview_window("main") {
-        let _ = window.show();
-        let _ = window.unminimize();
-        let _ = window.set_focus();
+fn open_existing_path(app: &AppHandle, path: PathBuf, missing_code: &str) -> Result<(), AppError> {
+    if !path.exists() {
+        return Err(err(missing_code, path.display().to_string()));
+    }
+    app.opener()
+        .open_path(path.to_string_lossy(), None::<&str>)
+        .map_err(|e| err("OPEN_FAILED", e.to_string()))
+}
+
+fn clash_api_get(
+    app: &AppHandle,
+    segments: &[&str],
+    query: &[(&str, &str)],
+) -> Result<ureq::Request, AppError> {
+    let endpoint = app.state::<ClashApi>().endpoint()?;
+    let mut url =
+        Url::parse(&endpoint.base).map_err(|e| err("CLASH_API_ERROR", e.to_string()))?;
+    url.path_segments_mut()
+        .map_err(|_| err("CLASH_API_ERROR", "invalid clash api url"))?
+        .extend(segments);
+    url.query_pairs_mut().extend_pairs(query);
+    Ok(ureq::get(url.as_str()).set("Authorization", &format!("Bearer {}", endpoint.secret)))
+}
+
+fn measure_delay(
+    app: &AppHandle,
+    tag: &str,
+    settings: &ProxySettings,
+) -> Result<Option<u64>, AppError> {
+    let timeout = settings.test_timeout();
+    let timeout_ms = timeout.as_millis().to_string();
+    let response = clash_api_get(
+        app,
+        &["proxies", tag, "delay"],
+        &[("url", settings.test_url.trim()), ("timeout", &timeout_ms)],
+    )?
+    .timeout(timeout + Duration::from_secs(1))
+    .call();
+    match response {
+        Ok(response) => {
+            let body = response
+                .into_string()
+                .map_err(|e| err("CLASH_API_ERROR", e.to_string()))?;
+            let body: Value =
+                serde_json::from_str(&body).map_err(|e| err("CLASH_API_ERROR", e.to_string()))?;
+            Ok(body.get("delay").and_then(Value::as_u64))
+        }
+        Err(ureq::Error::Status(_, _)) => Ok(None),
+        Err(e) => Err(err("CLASH_API_ERROR", e.to_string())),
     }
 }
 
-fn hide_main_window(app: &AppHandle) {
-    if let Some(window) = app.get_webview_window("main") {
-        let _ = window.hide();
+fn running_settings(state: &SharedState) -> Result<ProxySettings, AppError> {
+    let mut guard = state.lock().expect("state lock");
+    refresh_state(&mut guard);
+    if guard.child.is_none() {
+        return Err(err("NOT_RUNNING", "sing-box is not running"));
     }
+    Ok(guard.settings.clone())
 }
 
-fn current_status(app: &AppHandle, state: &mut ProxyState) -> ProxyStatus {
-    refresh_state(state);
-    let profile_path = resolve_profile_path(app)
-        .map(|path| path.display().to_string())
-        .unwrap_or_default();
-    let config_path = resolve_config_path(app)
-        .ok()
-        .and_then(|path| path.exists().then(|| path.display().to_string()));
-    let log_path = resolve_log_path(app)
-        .ok()
-        .and_then(|path| path.exists().then(|| path.display().to_string()));
-    let pid = state.child.as_ref().map(|child| child.id());
-
-    ProxyStatus {
-        running: state.child.is_some(),
-        mode: state.mode,
-        pid,
-        last_exit: state.last_exit,
-        last_error: state.last_error.clone(),
-        config_path,
-        profile_path,
-        log_path,
-    }
+fn selector_members(app: &AppHandle) -> Result<Vec<String>, AppError> {
+    let body = clash_api_get(app, &["proxies", "proxy"], &[])?
+        .timeout(CLASH_API_TIMEOUT)
+        .call()
+        .map_err(|e| err("CLASH_API_ERROR", e.to_string()))?
+        .into_string()
+        .map_err(|e| err("CLASH_API_ERROR", e.to_string()))?;
+    let body: Value =
+        serde_json::from_str(&body).map_err(|e| err("CLASH_API_ERROR", e.to_string()))?;
+    Ok(body
+        .get("all")
+        .and_then(Value::as_array)
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|tag| tag.to_string())
+                .collect()
+        })
+        .unwrap_or_default())
 }
 
-fn list_running_processes() -> Vec<ProcessEntry> {
-    let system = System::new_with_specifics(
-        RefreshKind::new().with_processes(
-            ProcessRefreshKind::new().with_exe(UpdateKind::OnlyIfNotSet),
-        ),
-    );
+#[tauri::command(async)]
+fn test_outbound_latency(
+    app: AppHandle,
+    state: State<SharedState>,
+    tag: String,
+) -> Result<Option<u64>, AppError> {
+    let settings = running_settings(state.inner())?;
+    measure_delay(&app, &tag, &settings)
+}
 
-    let mut entries: HashMap<String, ProcessEntry> = HashMap::new();
-    for (pid, process) in system.processes() {
-        let Some(path) = process.exe().and_then(|value| value.to_str()) else {
-            continue;
-        };
-        let lower = path.to_lowercase();
-        if !lower.ends_with(".exe") {
-            continue;
+/// Runs `measure` for every tag on at most `limit` threads and hands each result to
+/// `on_result` as soon as it is known. Failed and zero measurements count as `None`.
+fn measure_all<M, R>(
+    tags: Vec<String>,
+    limit: usize,
+    measure: M,
+    mut on_result: R,
+) -> HashMap<String, Option<u64>>
+where
+    M: Fn(&str) -> Result<Option<u64>, AppError> + Sync,
+    R: FnMut(&str, Option<u64>),
+{
+    let workers = limit.clamp(1, tags.len().max(1));
+    let queue = Mutex::new(tags.into_iter());
+    let (sender, receiver) = mpsc::channel();
+    let mut results = HashMap::new();
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let (queue, measure) = (&queue, &measure);
+            scope.spawn(move || loop {
+                let Some(tag) = queue.lock().expect("latency queue").next() else {
+                    break;
+                };
+                let delay = measure(&tag).ok().flatten().filter(|delay| *delay > 0);
+                if sender.send((tag, delay)).is_err() {
+                    break;
+                }
+            });
         }
-        let name = process.name().to_string();
-        let entry = entries.entry(path.to_string()).or_insert_with(|| {
-            let fallback = PathBuf::from(path)
-                .file_name()
-                .and_then(|value| value.to_str())
-                .unwrap_or(path)
-                .to_string();
-            ProcessEntry {
-                name: if name.is_empty() { fallback } else { name.clone() },
-                path: path.to_string(),
-                count: 0,
-                pids: Vec::new(),
-            }
-        });
-        if entry.name.is_empty() && !name.is_empty() {
-            entry.name = name.clone();
+        drop(sender);
+        for (tag, delay) in receiver {
+            on_result(&tag, delay);
+            results.insert(tag, delay);
         }
-        entry.count += 1;
-        entry.pids.push(pid.as_u32());
-    }
-
-    let mut list: Vec<ProcessEntry> = entries.into_values().collect();
-    list.sort_by(|a, b| {
-        a.name
-            .to_lowercase()
-            .cmp(&b.name.to_lowercase())
-            .then(a.path.cmp(&b.path))
     });
-    list
+    results
 }
 
-fn add_padding(value: &str) -> String {
-    let remainder = value.len() % 4;
-    if remainder == 0 {
-        value.to_string()
-    } else {
-        format!("{value}{}", "=".repeat(4 - remainder))
-    }
+#[tauri::command(async)]
+fn test_all_outbounds(
+    app: AppHandle,
+    state: State<SharedState>,
+) -> Result<HashMap<String, Option<u64>>, AppError> {
+    let settings = running_settings(state.inner())?;
+    let tags = selector_members(&app)?;
+    Ok(measure_all(
+        tags,
+        LATENCY_TEST_CONCURRENCY,
+        |tag| measure_delay(&app, tag, &settings),
+        |tag, delay| {
+            let _ = app.emit(
+                "latency-result",
+                LatencyResult {
+                    tag: tag.to_string(),
+                    delay,
+                },
+            );
+        },
+    ))
 }
 
-fn decode_base64_to_string(input: &str) -> Result<String, String> {
-    let cleaned = input.trim();
-    let candidates = vec![
-        cleaned.to_string(),
-        cleaned.replace('-', "+").replace('_', "/"),
-    ];
-    for candidate in candidates {
-        let padded = add_padding(&candidate);
-        for engine in [URL_SAFE_NO_PAD, URL_SAFE, STANDARD_NO_PAD, STANDARD] {
-            if let Ok(bytes) = engine.decode(candidate.as_bytes()) {
-                if let Ok(value) = String::from_utf8(bytes) {
-                    return Ok(value);
-                }
-            }
-            if candidate != padded {
-                if let Ok(bytes) = engine.decode(padded.as_bytes()) {
-                    if let Ok(value) = String::from_utf8(bytes) {
-                        return Ok(value);
-                    }
-                }
-            }
-        }
+fn ping_stats(tag: String, address: String, samples: &[Option<Duration>]) -> PingResult {
+    let millis: Vec<u64> = samples
+        .iter()
+        .flatten()
+        .map(|sample| sample.as_millis() as u64)
+        .collect();
+    let sent = samples.len();
+    let received = millis.len();
+    PingResult {
+        tag,
+        address,
+        sent,
+        received,
+        min_ms: millis.iter().min().copied(),
+        avg_ms: (received > 0).then(|| millis.iter().sum::<u64>() / received as u64),
+        max_ms: millis.iter().max().copied(),
+        loss_percent: if sent == 0 {
+            0.0
+        } else {
+            (sent - received) as f64 * 100.0 / sent as f64
+        },
     }
-    Err(err("IMPORT_INVALID", "base64 decode failed"))
 }
 
-fn query_map(url: &Url) -> HashMap<String, String> {
-    url.query_pairs()
-        .map(|(k, v)| (k.to_lowercase(), v.to_string()))
-        .collect()
+fn outbound_endpoint(app: &AppHandle, tag: &str) -> Result<(String, u16), AppError> {
+    let profile = load_profile_json(app)?;
+    let outbound = profile
+        .get("outbounds")
+        .and_then(Value::as_array)
+        .and_then(|items| {
+            items
+                .iter()
+                .find(|item| item.get("tag").and_then(Value::as_str) == Some(tag))
+        })
+        .ok_or_else(|| err("OUTBOUND_MISSING", tag))?;
+    let server = outbound
+        .get("server")
+        .and_then(Value::as_str)
+        .map(|server| server.trim().trim_start_matches('[').trim_end_matches(']'))
+        .filter(|server| !server.is_empty())
+        .ok_or_else(|| err("PING_UNSUPPORTED", format!("{tag} has no server")))?;
+    let port = outbound
+        .get("server_port")
+        .and_then(Value::as_u64)
+        .and_then(|port| u16::try_from(port).ok())
+        .ok_or_else(|| err("PING_UNSUPPORTED", format!("{tag} has no server_port")))?;
+    Ok((server.to_string(), port))
 }
 
-fn decode_query_component(value: &str) -> String {
-    let replaced = value.replace('+', " ");
-    percent_decode_str(&replaced)
-        .decode_utf8_lossy()
-        .into_owned()
+#[tauri::command(async)]
+fn tcp_ping(app: AppHandle, tag: String, attempts: Option<usize>) -> Result<PingResult, AppError> {
+    let attempts = attempts
+        .unwrap_or(TCP_PING_ATTEMPTS)
+        .clamp(1, MAX_TCP_PING_ATTEMPTS);
+    let timeout = load_app_state(&app).settings.test_timeout();
+    let (server, port) = outbound_endpoint(&app, &tag)?;
+    let addr = (server.as_str(), port)
+        .to_socket_addrs()
+        .map_err(|e| err("PING_FAILED", format!("cannot resolve {server}: {e}")))?
+        .next()
+        .ok_or_else(|| err("PING_FAILED", format!("cannot resolve {server}")))?;
+    let samples: Vec<Option<Duration>> = (0..attempts)
+        .map(|_| {
+            let started = Instant::now();
+            TcpStream::connect_timeout(&addr, timeout)
+                .ok()
+                .map(|_| started.elapsed())
+        })
+        .collect();
+    Ok(ping_stats(tag, addr.to_string(), &samples))
 }
 
-fn parse_ss_query(query: &str) -> HashMap<String, String> {
-    let mut params = HashMap::new();
-    for part in query.split('&') {
-        if part.is_empty() {
-            continue;
-        }
-        let (key, value) = part.split_once('=').unwrap_or((part, ""));
-        let key = decode_query_component(key).to_lowercase();
-        let value = decode_query_component(value);
-        params.insert(key, value);
+/// A throwaway config that runs only `outbound` behind a loopback mixed inbound on `port`,
+/// without TUN, so probing never touches the system routes.
+fn probe_config(mut outbound: Value, port: u16, settings: &ProxySettings) -> Value {
+    let tag = outbound.get("tag").cloned().unwrap_or(Value::Null);
+    if let Some(obj) = outbound.as_object_mut() {
+        obj.remove("detour");
+        obj.remove("domain_resolver");
     }
-    params
+    let mut outbounds = vec![outbound];
+    apply_default_fingerprint(&mut outbounds, settings.default_fingerprint.trim());
+    apply_default_udp_over_tcp(&mut outbounds, settings.ss_udp_over_tcp);
+    strip_app_fields(&mut outbounds);
+    json!({
+        "log": {
+            "level": "warn"
+        },
+        "inbounds": [{
+            "type": "mixed",
+            "tag": LOCAL_PROXY_TAG,
+            "listen": LOCAL_PROXY_HOST,
+            "listen_port": port
+        }],
+        "outbounds": outbounds,
+        "route": {
+            "final": tag
+        }
+    })
 }
 
-fn split_csv(value: &str) -> Vec<String> {
-    value
-        .split(',')
-        .map(|part| part.trim().to_string())
-        .filter(|part| !part.is_empty())
-        .collect()
+fn probe_through(child: &mut Child, port: u16, settings: &ProxySettings) -> Result<u64, String> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while !probe_port(addr, Duration::from_millis(200)) {
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(match status.code() {
+                Some(code) => format!("sing-box exited with code {code} during startup"),
+                None => "sing-box exited during startup".to_string(),
+            });
+        }
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "probe inbound did not accept connections within {}s",
+                STARTUP_TIMEOUT.as_secs()
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(150));
+    }
+    let proxy = ureq::Proxy::new(format!("http://{LOCAL_PROXY_HOST}:{port}"))
+        .map_err(|e| e.to_string())?;
+    let agent = ureq::AgentBuilder::new()
+        .proxy(proxy)
+        .timeout(settings.test_timeout())
+        .build();
+    let started = Instant::now();
+    agent
+        .get(settings.test_url.trim())
+        .call()
+        .map_err(|e| e.to_string())?;
+    Ok(started.elapsed().as_millis() as u64)
 }
 
-fn tls_from_params(params: &HashMap<String, String>, fallback_sni: Option<String>) -> Option<Value> {
-    let security = params
-        .get("security")
-        .or_else(|| params.get("tls"))
-        .map(|value| value.to_lowercase())
-        .unwrap_or_default();
+// Probes run as async commands and may overlap, each with its own sing-box.
+static PROBE_SEQUENCE: AtomicU64 = AtomicU64::new(0);
 
-    if security.is_empty() || security == "none" {
-        return None;
+#[tauri::command(async)]
+fn probe_active_node(app: AppHandle) -> Result<ProbeResult, AppError> {
+    let saved = load_app_state(&app);
+    let settings = saved.settings;
+    let tag = load_profile_state(&app)
+        .active_tag_for(saved.last_mode)
+        .ok_or_else(|| err("NO_ACTIVE_NODE", "no node is selected"))?;
+    let profile = load_profile_json(&app)?;
+    let outbound = profile
+        .get("outbounds")
+        .and_then(Value::as_array)
+        .and_then(|items| {
+            items
+                .iter()
+                .find(|item| item.get("tag").and_then(Value::as_str) == Some(tag.as_str()))
+        })
+        .cloned()
+        .ok_or_else(|| err("OUTBOUND_MISSING", tag.clone()))?;
+    let kind = outbound.get("type").and_then(Value::as_str).unwrap_or("");
+    if GROUP_OUTBOUND_TYPES_SKIPPED.contains(&kind) {
+        return Err(err(
+            "PROBE_UNSUPPORTED",
+            format!("{tag} is a {kind} outbound"),
+        ));
     }
 
-    let mut tls = json!({
-        "enabled": true
-    });
+    let port = TcpListener::bind((LOCAL_PROXY_HOST, 0))
+        .and_then(|listener| listener.local_addr())
+        .map_err(|e| err("PORT_IN_USE", e.to_string()))?
+        .port();
+    let exe_path = ensure_singbox_exe(&app)?;
+    let sequence = PROBE_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let config_path = ensure_app_data_dir(&app)?.join(format!(
+        "{PROBE_CONFIG_PREFIX}.{}-{sequence}.json",
+        std::process::id()
+    ));
+    let content = serde_json::to_string_pretty(&probe_config(outbound, port, &settings))
+        .map_err(|e| err("CONFIG_INVALID", e.to_string()))?;
+    fs::write(&config_path, content).map_err(|e| err("CONFIG_INVALID", e.to_string()))?;
 
-    if let Some(sni) = params
-        .get("sni")
-        .cloned()
-        .or(fallback_sni)
-        .or_else(|| params.get("server_name").cloned())
-    {
-        tls["server_name"] = json!(sni);
-    }
+    let mut cmd = Command::new(&exe_path);
+    cmd.arg("run")
+        .arg("-c")
+        .arg(&config_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
 
-    if let Some(insecure) = params.get("insecure") {
-        let enabled = insecure == "1" || insecure.eq_ignore_ascii_case("true");
-        if enabled {
-            tls["insecure"] = json!(true);
-        }
-    }
+    #[cfg(target_os = "windows")]
+    cmd.creation_flags(CREATE_NO_WINDOW);
 
-    if let Some(alpn) = params.get("alpn") {
-        let list = split_csv(alpn);
-        if !list.is_empty() {
-            tls["alpn"] = json!(list);
-        }
-    }
+    let probed = check_config(&exe_path, &config_path).and_then(|_| {
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| err("START_FAILED", e.to_string()))?;
+        let probed = probe_through(&mut child, port, &settings);
+        let _ = child.kill();
+        let _ = child.wait();
+        Ok(probed)
+    });
+    let _ = fs::remove_file(&config_path);
+    let probed = probed?;
+    Ok(ProbeResult {
+        tag,
+        ok: probed.is_ok(),
+        latency_ms: probed.as_ref().ok().copied(),
+        error: probed.err(),
+    })
+}
 
-    if let Some(fp) = params
-        .get("fp")
-        .or_else(|| params.get("fingerprint"))
-        .map(|value| value.trim())
-    {
-        if !fp.is_empty() && !fp.eq_ignore_ascii_case("none") {
-            tls["utls"] = json!({
-                "enabled": true,
-                "fingerprint": fp
-            });
-        }
+/// Asks the geolocation service through the running mixed inbound, so the query leaves
+/// via the selected node instead of exposing the user's address and node list to it.
+fn lookup_country(ip: IpAddr, settings: &ProxySettings) -> Result<String, AppError> {
+    let proxy = ureq::Proxy::new(settings.mixed_proxy_url())
+        .map_err(|e| err("COUNTRY_LOOKUP_FAILED", e.to_string()))?;
+    let agent = ureq::AgentBuilder::new()
+        .proxy(proxy)
+        .timeout(settings.test_timeout())
+        .build();
+    let body = agent
+        .get(&format!("{COUNTRY_LOOKUP_URL}{ip}"))
+        .query("fields", "success,message,country_code")
+        .call()
+        .map_err(|e| err("COUNTRY_LOOKUP_FAILED", e.to_string()))?
+        .into_string()
+        .map_err(|e| err("COUNTRY_LOOKUP_FAILED", e.to_string()))?;
+    let body: Value =
+        serde_json::from_str(&body).map_err(|e| err("COUNTRY_LOOKUP_FAILED", e.to_string()))?;
+    if body.get("success").and_then(Value::as_bool) != Some(true) {
+        let message = body
+            .get("message")
+            .and_then(Value::as_str)
+            .unwrap_or("lookup failed");
+        return Err(err("COUNTRY_LOOKUP_FAILED", format!("{ip}: {message}")));
     }
+    body.get("country_code")
+        .and_then(Value::as_str)
+        .filter(|code| code.len() == 2)
+        .map(str::to_ascii_uppercase)
+        .ok_or_else(|| err("COUNTRY_LOOKUP_FAILED", format!("{ip}: no country")))
+}
 
-    if security == "reality" {
-        let mut reality = json!({
-            "enabled": true
-        });
-        let mut has_reality = false;
+fn find_outbound_mut<'a>(profile: &'a mut Value, tag: &str) -> Option<&'a mut Value> {
+    profile
+        .get_mut("outbounds")
+        .and_then(Value::as_array_mut)?
+        .iter_mut()
+        .find(|item| item.get("tag").and_then(Value::as_str) == Some(tag))
+}
 
-        if let Some(pbk) = params
-            .get("pbk")
-            .or_else(|| params.get("public_key"))
-            .or_else(|| params.get("publickey"))
-        {
-            if !pbk.is_empty() {
-                reality["public_key"] = json!(pbk);
-                has_reality = true;
-            }
-        }
+fn cached_node_country(profile: &Value, tag: &str, server: &str) -> Option<String> {
+    profile
+        .get("outbounds")
+        .and_then(Value::as_array)?
+        .iter()
+        .find(|item| item.get("tag").and_then(Value::as_str) == Some(tag))?
+        .get(NODE_COUNTRY_FIELD)
+        .filter(|cached| cached.get("server").and_then(Value::as_str) == Some(server))
+        .and_then(|cached| cached.get("code").and_then(Value::as_str))
+        .map(str::to_string)
+}
 
-        if params.contains_key("sid") {
-            if let Some(sid) = params.get("sid") {
-                reality["short_id"] = json!(sid);
-                has_reality = true;
-            }
-        } else if params.contains_key("short_id") {
-            if let Some(sid) = params.get("short_id") {
-                reality["short_id"] = json!(sid);
-                has_reality = true;
-            }
-        } else if params.contains_key("shortid") {
-            if let Some(sid) = params.get("shortid") {
-                reality["short_id"] = json!(sid);
-                has_reality = true;
-            }
-        }
+fn cache_node_country(
+    profile: &mut Value,
+    tag: &str,
+    server: &str,
+    code: &str,
+) -> Result<(), AppError> {
+    let outbound = find_outbound_mut(profile, tag).ok_or_else(|| err("OUTBOUND_MISSING", tag))?;
+    outbound[NODE_COUNTRY_FIELD] = json!({
+        "code": code,
+        "server": server
+    });
+    Ok(())
+}
 
-        if has_reality {
-            tls["reality"] = reality;
-        }
+/// Best effort: this locates the server address, which for CDN-fronted or relayed nodes is
+/// not where traffic exits. The result is cached on the outbound per server address.
+/// Uncached lookups need sing-box running, since the query is sent through the tunnel.
+#[tauri::command(async)]
+fn resolve_node_country(
+    app: AppHandle,
+    state: State<SharedState>,
+    tag: String,
+) -> Result<String, AppError> {
+    let (server, port) = outbound_endpoint(&app, &tag)?;
+    if let Some(code) = cached_node_country(&load_profile_json(&app)?, &tag, &server) {
+        return Ok(code);
     }
-
-    Some(tls)
+    let settings = running_settings(state.inner())?;
+
+    let ip = match server.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => (server.as_str(), port)
+            .to_socket_addrs()
+            .map_err(|e| {
+                err(
+                    "COUNTRY_LOOKUP_FAILED",
+                    format!("cannot resolve {server}: {e}"),
+                )
+            })?
+            .next()
+            .map(|addr| addr.ip())
+            .ok_or_else(|| err("COUNTRY_LOOKUP_FAILED", format!("cannot resolve {server}")))?,
+    };
+    let code = lookup_country(ip, &settings)?;
+    // The lookup can take seconds; edits saved meanwhile must not be overwritten.
+    let mut profile = load_profile_json(&app)?;
+    cache_node_country(&mut profile, &tag, &server, &code)?;
+    save_profile_json(&app, &profile)?;
+    let _ = app.emit("profile-changed", profile_data(&app, &profile));
+    Ok(code)
 }
 
-fn build_transport(params: &HashMap<String, String>, network: &str) -> Option<Value> {
-    match network {
-        "ws" => {
-            let mut transport = json!({
-                "type": "ws"
-            });
-            if let Some(path) = params.get("path") {
-                transport["path"] = json!(path);
-            }
-            if let Some(host) = params.get("host") {
-                transport["headers"] = json!({ "Host": host });
-            }
-            Some(transport)
-        }
-        "http" | "h2" => {
-            let mut transport = json!({
-                "type": "http"
-            });
-            if let Some(host) = params.get("host") {
-                let hosts = split_csv(host);
-                if !hosts.is_empty() {
-                    transport["host"] = json!(hosts);
-                }
-            }
-            if let Some(path) = params.get("path") {
-                transport["path"] = json!(path);
-            }
-            Some(transport)
-        }
-        "httpupgrade" => {
-            let mut transport = json!({
-                "type": "httpupgrade"
-            });
-            if let Some(host) = params.get("host") {
-                transport["host"] = json!(host);
-            }
-            if let Some(path) = params.get("path") {
-                transport["path"] = json!(path);
-            }
-            Some(transport)
-        }
-        "grpc" => {
-            let mut transport = json!({
-                "type": "grpc"
-            });
-            if let Some(service) = params
-                .get("service_name")
-                .or_else(|| params.get("servicename"))
-                .or_else(|| params.get("path"))
-            {
-                transport["service_name"] = json!(service);
-            }
-            Some(transport)
-        }
-        "quic" => Some(json!({
-            "type": "quic"
-        })),
-        _ => None,
+#[tauri::command]
+fn get_tunnel_info(state: State<SharedState>) -> Result<TunnelInfo, AppError> {
+    let settings = running_settings(state.inner())?;
+    if !settings.tun_enabled {
+        return Err(err("TUN_DISABLED", "TUN inbound is disabled in settings"));
     }
+    let networks = Networks::new_with_refreshed_list();
+    let traffic = networks
+        .list()
+        .get(TUN_INTERFACE_NAME)
+        .map(|data| (data.total_received(), data.total_transmitted()));
+    Ok(tunnel_info(&settings, traffic))
 }
 
-fn unique_tag(base: &str, used: &mut HashSet<String>) -> String {
-    let mut candidate = base.to_string();
-    let mut index = 2;
-    while used.contains(&candidate) {
-        candidate = format!("{base}-{index}");
-        index += 1;
+/// `traffic` is the interface's received and transmitted byte counts, `None` while it is down.
+fn tunnel_info(settings: &ProxySettings, traffic: Option<(u64, u64)>) -> TunnelInfo {
+    let (received_bytes, transmitted_bytes) = traffic.unwrap_or_default();
+    TunnelInfo {
+        interface_name: TUN_INTERFACE_NAME.to_string(),
+        addresses: settings.tun_addresses(),
+        auto_route: true,
+        interface_up: traffic.is_some(),
+        received_bytes,
+        transmitted_bytes,
     }
-    used.insert(candidate.clone());
-    candidate
 }
 
-fn guess_tag(raw: &Value, fallback: &str) -> String {
-    raw.get("tag")
-        .and_then(Value::as_str)
-        .filter(|tag| !tag.trim().is_empty())
-        .map(|tag| tag.to_string())
-        .or_else(|| raw.get("ps").and_then(Value::as_str).map(|tag| tag.to_string()))
-        .unwrap_or_else(|| fallback.to_string())
+/// Arguments the autostart entry launches the app with. `set_autostart` re-registers the
+/// entry through the plugin, so it keeps whatever `run` passed here.
+fn autostart_args() -> Vec<&'static str> {
+    vec![AUTOSTART_ARG]
 }
 
-fn parse_ss_userinfo(value: &str) -> Result<(String, String), String> {
-    let decoded = if value.contains(':') {
-        value.to_string()
-    } else {
-        decode_base64_to_string(value)?
-    };
-    let (method, password) = decoded
-        .split_once(':')
-        .ok_or_else(|| err("IMPORT_INVALID", "missing method/password"))?;
-    Ok((method.to_string(), password.to_string()))
-}
-
-fn parse_ss_host_port(value: &str) -> Result<(String, u16), String> {
-    let trimmed = value.trim();
-    let host_port = trimmed
-        .split_once('/')
-        .map(|(head, _)| head)
-        .unwrap_or(trimmed);
-
-    if host_port.starts_with('[') {
-        let end = host_port
-            .find(']')
-            .ok_or_else(|| err("IMPORT_INVALID", "invalid ipv6 host"))?;
-        let host = &host_port[1..end];
-        let port_str = host_port[end + 1..]
-            .strip_prefix(':')
-            .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
-        let port = port_str
-            .parse::<u16>()
-            .map_err(|_| err("IMPORT_INVALID", "invalid port number"))?;
-        return Ok((host.to_string(), port));
-    }
-
-    let (host, port_str) = host_port
-        .rsplit_once(':')
-        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
-    if host.is_empty() {
-        return Err(err("IMPORT_INVALID", "missing server"));
-    }
-    let port = port_str
-        .parse::<u16>()
-        .map_err(|_| err("IMPORT_INVALID", "invalid port number"))?;
-    Ok((host.to_string(), port))
-}
-
-fn parse_ss_payload(value: &str) -> Result<(String, String, String, u16), String> {
-    if let Some(at_pos) = value.rfind('@') {
-        let (userinfo, hostpart) = value.split_at(at_pos);
-        let hostpart = &hostpart[1..];
-        let (method, password) = parse_ss_userinfo(userinfo)?;
-        let (server, port) = parse_ss_host_port(hostpart)?;
-        return Ok((method, password, server, port));
-    }
-
-    let decoded = decode_base64_to_string(value)?;
-    if let Some(at_pos) = decoded.rfind('@') {
-        let (userinfo, hostpart) = decoded.split_at(at_pos);
-        let hostpart = &hostpart[1..];
-        let (method, password) = parse_ss_userinfo(userinfo)?;
-        let (server, port) = parse_ss_host_port(hostpart)?;
-        return Ok((method, password, server, port));
-    }
-
-    Err(err("IMPORT_INVALID", "missing server"))
-}
-
-fn parse_ss(link: &str) -> Result<Value, String> {
-    let raw = link.trim().trim_start_matches("ss://");
-    let (payload, fragment) = raw.split_once('#').unwrap_or((raw, ""));
-    let (payload, query) = payload.split_once('?').unwrap_or((payload, ""));
-    let (method, password, server, port) = parse_ss_payload(payload)?;
-
-    let mut tag = fragment.to_string();
-    let params = if query.is_empty() {
-        HashMap::new()
-    } else {
-        parse_ss_query(query)
-    };
-    if tag.is_empty() {
-        if let Some(name) = params.get("name") {
-            tag = name.to_string();
-        }
+#[tauri::command]
+fn get_autostart(app: AppHandle) -> Result<bool, AppError> {
+    app.autolaunch()
+        .is_enabled()
+        .map_err(|e| err("AUTOSTART_ERROR", e.to_string()))
+}
+
+#[tauri::command]
+fn set_autostart(app: AppHandle, enabled: bool) -> Result<bool, AppError> {
+    let manager = app.autolaunch();
+    let current = manager
+        .is_enabled()
+        .map_err(|e| err("AUTOSTART_ERROR", e.to_string()))?;
+    if current {
+        manager
+            .disable()
+            .map_err(|e| err("AUTOSTART_ERROR", e.to_string()))?;
     }
-    let tag = if tag.is_empty() {
-        format!("ss-{server}:{port}")
-    } else {
-        tag
-    };
+    if enabled {
+        manager
+            .enable()
+            .map_err(|e| err("AUTOSTART_ERROR", e.to_string()))?;
+    }
+    get_autostart(app)
+}
 
-    let mut outbound = json!({
-        "type": "shadowsocks",
-        "tag": tag,
-        "server": server,
-        "server_port": port,
-        "method": method,
-        "password": password
-    });
+/// The config `build_config` would write, without writing it. While sing-box runs the
+/// preview keeps its clash api address, so it matches the file it was started with.
+#[tauri::command(async)]
+fn get_effective_config(
+    app: AppHandle,
+    state: State<SharedState>,
+    mode: ProxyMode,
+    app_rules: Vec<AppRule>,
+    force_ipv4_ru: Option<bool>,
+) -> Result<Value, AppError> {
+    let saved = load_app_state(&app);
+    let force_ipv4_ru = force_ipv4_ru.unwrap_or(saved.force_ipv4_ru);
+    let app_rules = normalize_app_rules(app_rules)?;
+    let running = {
+        let mut guard = state.lock().expect("state lock");
+        refresh_state(&mut guard);
+        guard.child.is_some()
+    };
+    let controller = running
+        .then(|| app.state::<ClashApi>().controller())
+        .flatten();
+    let composed = generate_config(
+        &app,
+        mode,
+        app_rules,
+        force_ipv4_ru,
+        &saved.settings,
+        controller,
+    )?;
+    Ok(composed.config)
+}
 
-    if let Some(plugin) = params.get("plugin") {
-        let mut parts = plugin.split(';');
-        if let Some(plugin_name) = parts.next() {
-            if !plugin_name.is_empty() {
-                outbound["plugin"] = json!(plugin_name);
-            }
-        }
-        let opts: Vec<&str> = parts.filter(|item| !item.is_empty()).collect();
-        if !opts.is_empty() {
-            outbound["plugin_opts"] = json!(opts.join(";"));
-        }
-    }
+#[tauri::command]
+fn open_config_folder(app: AppHandle) -> Result<(), AppError> {
+    let dir = ensure_app_data_dir(&app)?;
+    open_existing_path(&app, dir, "DATA_DIR_NOT_FOUND")
+}
 
-    Ok(outbound)
+#[tauri::command]
+fn open_config_file(app: AppHandle) -> Result<(), AppError> {
+    let path = resolve_config_path(&app)?;
+    open_existing_path(&app, path, "CONFIG_NOT_FOUND")
 }
 
-fn parse_vmess(link: &str) -> Result<Value, String> {
-    let encoded = link.trim().trim_start_matches("vmess://");
-    let decoded = decode_base64_to_string(encoded)?;
-    let raw: Value =
-        serde_json::from_str(&decoded).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
-    let obj = raw
-        .as_object()
-        .ok_or_else(|| err("IMPORT_INVALID", "invalid vmess json"))?;
+#[tauri::command]
+fn open_log_file(app: AppHandle) -> Result<(), AppError> {
+    let path = resolve_log_path(&app)?;
+    open_existing_path(&app, path, "LOG_NOT_FOUND")
+}
 
-    let server = obj
-        .get("add")
-        .and_then(Value::as_str)
-        .ok_or_else(|| err("IMPORT_INVALID", "missing server"))?;
-    let port = obj
-        .get("port")
-        .and_then(|value| {
-            value
-                .as_str()
-                .and_then(|s| s.parse::<u16>().ok())
-                .or_else(|| value.as_u64().map(|v| v as u16))
-        })
-        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
-    let uuid = obj
-        .get("id")
-        .and_then(Value::as_str)
-        .ok_or_else(|| err("IMPORT_INVALID", "missing uuid"))?;
+#[tauri::command]
+fn apply_mode(
+    app: &AppHandle,
+    state: &SharedState,
+    mode: ProxyMode,
+    app_rules: Vec<AppRule>,
+    force_ipv4_ru: bool,
+) -> Result<ProxyStatus, AppError> {
+    let result = restart_proxy(app, state, mode, app_rules, force_ipv4_ru);
+    emit_proxy_state(app);
+    refresh_tray(app);
+    result
+}
 
-    let mut params: HashMap<String, String> = HashMap::new();
-    for key in ["net", "type", "host", "path", "tls", "sni", "alpn"] {
-        if let Some(value) = obj.get(key).and_then(Value::as_str) {
-            params.insert(key.to_string(), value.to_string());
+fn kill_child(app: &AppHandle, state: &mut ProxyState) {
+    if let Some(mut child) = state.child.take() {
+        stop_child(&mut child, &state.elevation);
+        remove_pid_lock(app);
+        let lived_long = state
+            .started_at
+            .take()
+            .is_some_and(|started_at| started_at.elapsed() >= FAST_EXIT_WINDOW);
+        if lived_long {
+            state.fast_exits = 0;
         }
     }
+}
 
-    let ps = obj.get("ps").and_then(Value::as_str).unwrap_or("");
-    let tag = if ps.trim().is_empty() {
-        format!("vmess-{server}:{port}")
-    } else {
-        ps.to_string()
-    };
-
-    let mut outbound = json!({
-        "type": "vmess",
-        "tag": tag,
-        "server": server,
-        "server_port": port,
-        "uuid": uuid
-    });
+fn restart_proxy(
+    app: &AppHandle,
+    state: &SharedState,
+    mode: ProxyMode,
+    app_rules: Vec<AppRule>,
+    force_ipv4_ru: bool,
+) -> Result<ProxyStatus, AppError> {
+    let app_rules = normalize_app_rules(app_rules)?;
+    let mut saved = load_app_state(app);
+    saved.remember_request(mode, app_rules.clone(), force_ipv4_ru);
+    let _ = save_app_state(app, &saved);
+    let settings = saved.settings;
 
-    if let Some(security) = obj
-        .get("scy")
-        .and_then(Value::as_str)
-        .or_else(|| obj.get("security").and_then(Value::as_str))
-    {
-        outbound["security"] = json!(security);
-    }
+    let mut guard = state.lock().expect("state lock");
 
-    if let Some(alter_id) = obj
-        .get("aid")
-        .and_then(|value| {
-            value
-                .as_str()
-                .and_then(|s| s.parse::<u32>().ok())
-                .or_else(|| value.as_u64().map(|v| v as u32))
-        })
-    {
-        outbound["alter_id"] = json!(alter_id);
-    }
+    kill_child(app, &mut guard);
+    guard.mode = ProxyMode::Off;
+    guard.last_error = None;
+    guard.watch_token = guard.watch_token.wrapping_add(1);
+    let token = guard.watch_token;
 
-    let network = params
-        .get("net")
-        .cloned()
-        .unwrap_or_else(|| "tcp".to_string());
-    if let Some(transport) = build_transport(&params, network.as_str()) {
-        outbound["transport"] = transport;
+    if mode == ProxyMode::Off {
+        return Ok(current_status(app, &mut guard));
     }
 
-    let mut tls_params = params.clone();
-    if !tls_params.contains_key("security") && !tls_params.contains_key("tls") {
-        tls_params.insert("security".to_string(), "tls".to_string());
+    if let Some(remaining) = guard.crash_loop_remaining(Instant::now()) {
+        let mut detail = format!(
+            "sing-box exited {} times in a row right after start; retry in {}s",
+            guard.fast_exits,
+            remaining.as_secs().max(1)
+        );
+        let recent = resolve_log_path(app)
+            .and_then(|path| tail_log_lines(&path, CRASH_LOOP_LOG_LINES, None))
+            .unwrap_or_default();
+        append_lines(&mut detail, recent);
+        let error = err("CRASH_LOOP", detail);
+        guard.last_error = Some(error.to_string());
+        return Err(error);
     }
-    if let Some(tls) = tls_from_params(&tls_params, Some(server.to_string())) {
-        outbound["tls"] = tls;
+    // Port checks, config generation and `sing-box check` can take seconds;
+    // status polls and the tray must not wait on them.
+    drop(guard);
+
+    let fail = |error: AppError| {
+        let mut guard = state.lock().expect("state lock");
+        if guard.watch_token == token {
+            guard.last_error = Some(error.to_string());
+        }
+        error
+    };
+    ensure_port_free(mixed_listen_host(settings.allow_lan), LOCAL_PROXY_PORT).map_err(fail)?;
+    let rule_conflicts = normalize_rules(app_rules.clone()).conflicts;
+    let (config_path, selector_change, elevation) =
+        build_config(app, mode, app_rules, force_ipv4_ru, &settings).map_err(fail)?;
+    // A profile or template may pin its own clash api port, which can be taken as well.
+    if let Some((host, port)) = app
+        .state::<ClashApi>()
+        .controller()
+        .as_deref()
+        .and_then(controller_listen_addr)
+    {
+        ensure_port_free(&host, port).map_err(fail)?;
     }
+    let log_path = resolve_log_path(app).map_err(fail)?;
+    let exe_path = ensure_singbox_exe(app).map_err(fail)?;
+    check_config(&exe_path, &config_path).map_err(fail)?;
 
-    Ok(outbound)
-}
-
-fn parse_vless(link: &str) -> Result<Value, String> {
-    let url = Url::parse(link).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
-    let uuid = url.username();
-    if uuid.is_empty() {
-        return Err(err("IMPORT_INVALID", "missing uuid"));
+    let mut guard = state.lock().expect("state lock");
+    if guard.watch_token != token {
+        // A stop or another mode change arrived while the config was prepared.
+        return Ok(current_status(app, &mut guard));
     }
-    let server = url
-        .host_str()
-        .ok_or_else(|| err("IMPORT_INVALID", "missing server"))?;
-    let port = url
-        .port()
-        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
-    let tag = url.fragment().unwrap_or("");
-    let tag = if tag.is_empty() {
-        format!("vless-{server}:{port}")
-    } else {
-        tag.to_string()
-    };
-    let params = query_map(&url);
-
-    let mut outbound = json!({
-        "type": "vless",
-        "tag": tag,
-        "server": server,
-        "server_port": port,
-        "uuid": uuid
-    });
 
-    if let Some(flow) = params.get("flow") {
-        outbound["flow"] = json!(flow);
-    }
+    let log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .map_err(|e| {
+            let message = err("LOG_ERROR", e.to_string());
+            guard.last_error = Some(message.to_string());
+            message
+        })?;
 
-    let network = params
-        .get("type")
-        .cloned()
-        .unwrap_or_else(|| "tcp".to_string());
-    if let Some(transport) = build_transport(&params, network.as_str()) {
-        outbound["transport"] = transport;
-    }
+    let mut cmd = elevation
+        .wrap(Launch::singbox_run(&exe_path, &config_path))
+        .command();
+    cmd.stdout(Stdio::from(log_file.try_clone().map_err(|e| {
+        let message = err("LOG_ERROR", e.to_string());
+        guard.last_error = Some(message.to_string());
+        message
+    })?));
+    cmd.stderr(Stdio::from(log_file));
 
-    let mut tls_params = params.clone();
-    if !tls_params.contains_key("security") && !tls_params.contains_key("tls") {
-        tls_params.insert("security".to_string(), "tls".to_string());
-    }
-    if let Some(tls) = tls_from_params(&tls_params, Some(server.to_string())) {
-        outbound["tls"] = tls;
-    }
+    #[cfg(target_os = "windows")]
+    cmd.creation_flags(CREATE_NO_WINDOW | CREATE_NEW_PROCESS_GROUP);
 
-    Ok(outbound)
-}
+    let child = cmd.spawn().map_err(|e| {
+        let message = err("START_FAILED", e.to_string());
+        guard.last_error = Some(message.to_string());
+        message
+    })?;
 
-fn parse_trojan(link: &str) -> Result<Value, String> {
-    let url = Url::parse(link).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
-    let server = url
-        .host_str()
-        .ok_or_else(|| err("IMPORT_INVALID", "missing server"))?;
-    let port = url
-        .port()
-        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
-    let mut password = url.username().to_string();
-    if password.is_empty() {
-        if let Some(pass) = url.password() {
-            password = pass.to_string();
+    #[cfg(target_os = "windows")]
+    {
+        if guard.job.is_none() {
+            if let Ok(job) = create_job_object() {
+                guard.job = Some(job);
+            }
+        }
+        if let Some(job) = guard.job.as_ref() {
+            let _ = unsafe { AssignProcessToJobObject(job.0, child.as_raw_handle() as isize) };
         }
-    } else if let Some(pass) = url.password() {
-        password = format!("{password}:{pass}");
     }
 
-    if password.is_empty() {
-        return Err(err("IMPORT_INVALID", "missing password"));
-    }
-    let tag = url.fragment().unwrap_or("");
-    let tag = if tag.is_empty() {
-        format!("trojan-{server}:{port}")
-    } else {
-        tag.to_string()
-    };
-    let params = query_map(&url);
-
-    let mut outbound = json!({
-        "type": "trojan",
-        "tag": tag,
-        "server": server,
-        "server_port": port,
-        "password": password
-    });
+    write_pid_lock(app, child.id());
+    guard.child = Some(child);
+    guard.mode = mode;
+    guard.config_path = Some(config_path);
+    guard.last_exit = None;
+    guard.started_at = Some(Instant::now());
+    guard.settings = settings;
+    guard.selector_change = Some(selector_change);
+    guard.rule_conflicts = rule_conflicts;
+    guard.elevation = elevation;
 
-    let network = params
-        .get("type")
-        .cloned()
-        .unwrap_or_else(|| "tcp".to_string());
-    if let Some(transport) = build_transport(&params, network.as_str()) {
-        outbound["transport"] = transport;
-    }
+    guard.watch_token = guard.watch_token.wrapping_add(1);
+    let token = guard.watch_token;
+    let state_clone = state.clone();
+    spawn_monitor(app.clone(), state_clone, token, log_path.clone());
+    let log_state = state.clone();
+    spawn_log_tailer(app.clone(), log_state, token, log_path.clone());
+    drop(guard);
 
-    if let Some(tls) = tls_from_params(&params, Some(server.to_string())) {
-        outbound["tls"] = tls;
+    if let Err(mut error) = wait_for_startup(state, token, &log_path) {
+        let mut guard = state.lock().expect("state lock");
+        if guard.elevation.declined(&error.detail) {
+            error = err("NEEDS_ELEVATION", "administrator access was declined");
+        }
+        if guard.watch_token == token {
+            kill_child(app, &mut guard);
+            guard.mode = ProxyMode::Off;
+            guard.watch_token = guard.watch_token.wrapping_add(1);
+            guard.last_error = Some(error.to_string());
+        }
+        return Err(error);
     }
 
-    Ok(outbound)
+    let mut guard = state.lock().expect("state lock");
+    Ok(current_status(app, &mut guard))
 }
 
-fn parse_hysteria(link: &str) -> Result<Value, String> {
-    let url = Url::parse(link).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
-    let server = url
-        .host_str()
-        .ok_or_else(|| err("IMPORT_INVALID", "missing server"))?;
-    let port = url
-        .port()
-        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
-    let params = query_map(&url);
-    let tag = url.fragment().unwrap_or("");
-    let tag = if tag.is_empty() {
-        format!("hysteria-{server}:{port}")
+fn is_bundled_singbox(exe: &PathBuf, bundled: &PathBuf) -> bool {
+    let canonical = |path: &PathBuf| fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+    let (exe, bundled) = (canonical(exe), canonical(bundled));
+    if cfg!(target_os = "windows") {
+        exe.to_string_lossy().to_lowercase() == bundled.to_string_lossy().to_lowercase()
     } else {
-        tag.to_string()
-    };
-
-    let mut outbound = json!({
-        "type": "hysteria",
-        "tag": tag,
-        "server": server,
-        "server_port": port
-    });
-
-    if let Some(auth) = params.get("auth").or_else(|| params.get("auth_str")) {
-        outbound["auth_str"] = json!(auth);
-    }
-    if let Some(obfs) = params.get("obfs") {
-        outbound["obfs"] = json!(obfs);
-    }
-    if let Some(up) = params.get("upmbps").or_else(|| params.get("up")) {
-        if let Ok(value) = up.parse::<u32>() {
-            outbound["up_mbps"] = json!(value);
-        }
-    }
-    if let Some(down) = params.get("downmbps").or_else(|| params.get("down")) {
-        if let Ok(value) = down.parse::<u32>() {
-            outbound["down_mbps"] = json!(value);
-        }
+        exe == bundled
     }
+}
 
-    let mut tls_params = params.clone();
-    if !tls_params.contains_key("security") {
-        tls_params.insert("security".to_string(), "tls".to_string());
-    }
-    if let Some(peer) = params.get("peer") {
-        tls_params.insert("sni".to_string(), peer.to_string());
-    }
-    if let Some(tls) = tls_from_params(&tls_params, Some(server.to_string())) {
-        outbound["tls"] = tls;
-    }
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PidLock {
+    pid: u32,
+    started_at: u64,
+}
 
-    Ok(outbound)
+fn write_pid_lock(app: &AppHandle, pid: u32) {
+    let Ok(path) = resolve_pid_path(app) else {
+        return;
+    };
+    let pid_key = Pid::from_u32(pid);
+    let mut system = System::new();
+    system.refresh_process_specifics(pid_key, ProcessRefreshKind::new());
+    let started_at = system
+        .process(pid_key)
+        .map(|process| process.start_time())
+        .unwrap_or_default();
+    store_pid_lock(&path, &PidLock { pid, started_at });
 }
 
-fn parse_hysteria2(link: &str) -> Result<Value, String> {
-    let url = Url::parse(link).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
-    let server = url
-        .host_str()
-        .ok_or_else(|| err("IMPORT_INVALID", "missing server"))?;
-    let port = url
-        .port()
-        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
-    let password = url.username();
-    if password.is_empty() {
-        return Err(err("IMPORT_INVALID", "missing password"));
+fn store_pid_lock(path: &PathBuf, lock: &PidLock) {
+    if let Ok(data) = serde_json::to_string(lock) {
+        let _ = fs::write(path, data);
     }
-    let params = query_map(&url);
-    let tag = url.fragment().unwrap_or("");
-    let tag = if tag.is_empty() {
-        format!("hysteria2-{server}:{port}")
-    } else {
-        tag.to_string()
-    };
+}
 
-    let mut outbound = json!({
-        "type": "hysteria2",
-        "tag": tag,
-        "server": server,
-        "server_port": port,
-        "password": password
-    });
+fn read_pid_lock(path: &PathBuf) -> Option<PidLock> {
+    let data = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&data).ok()
+}
 
-    if let Some(obfs) = params.get("obfs") {
-        if obfs == "salamander" {
-            let mut obfs_obj = json!({
-                "type": "salamander"
-            });
-            if let Some(password) = params
-                .get("obfs-password")
-                .or_else(|| params.get("obfs_password"))
-            {
-                obfs_obj["password"] = json!(password);
-            }
-            outbound["obfs"] = obfs_obj;
-        }
+fn remove_pid_lock(app: &AppHandle) {
+    if let Ok(path) = resolve_pid_path(app) {
+        let _ = fs::remove_file(path);
     }
+}
 
-    if let Some(up) = params.get("upmbps").or_else(|| params.get("up")) {
-        if let Ok(value) = up.parse::<u32>() {
-            outbound["up_mbps"] = json!(value);
+/// Stops the sing-box this app spawned before it last exited. The PID recorded at spawn
+/// is checked first; without a pid file, any process running our copied binary is ours.
+/// A system-wide sing-box installed elsewhere is never touched.
+fn kill_orphaned_singbox(app: &AppHandle) {
+    let Ok(data_dir) = ensure_app_data_dir(app) else {
+        return;
+    };
+    let bundled = data_dir.join(BIN_DIR).join(SINGBOX_EXE);
+    let lock = resolve_pid_path(app)
+        .ok()
+        .and_then(|path| read_pid_lock(&path));
+    let mut system = System::new();
+    match lock {
+        Some(lock) => {
+            let pid = Pid::from_u32(lock.pid);
+            system.refresh_process_specifics(
+                pid,
+                ProcessRefreshKind::new().with_exe(UpdateKind::OnlyIfNotSet),
+            );
+            if let Some(process) = system.process(pid) {
+                // A process whose exe cannot be read may be anything that reused the PID.
+                let ours = process.start_time() == lock.started_at
+                    && process
+                        .exe()
+                        .is_some_and(|exe| is_bundled_singbox(&exe.to_path_buf(), &bundled));
+                if ours && process.kill() {
+                    process.wait();
+                }
+            }
         }
-    }
-    if let Some(down) = params.get("downmbps").or_else(|| params.get("down")) {
-        if let Ok(value) = down.parse::<u32>() {
-            outbound["down_mbps"] = json!(value);
+        None => {
+            system.refresh_processes_specifics(
+                ProcessRefreshKind::new().with_exe(UpdateKind::OnlyIfNotSet),
+            );
+            for process in system.processes().values() {
+                let ours = process
+                    .exe()
+                    .is_some_and(|exe| is_bundled_singbox(&exe.to_path_buf(), &bundled));
+                if ours && process.kill() {
+                    process.wait();
+                }
+            }
         }
     }
+    remove_pid_lock(app);
+}
 
-    let mut tls_params = params.clone();
-    if !tls_params.contains_key("security") {
-        tls_params.insert("security".to_string(), "tls".to_string());
-    }
-    if let Some(tls) = tls_from_params(&tls_params, Some(server.to_string())) {
-        outbound["tls"] = tls;
-    }
-
-    Ok(outbound)
+/// Finds the PID listening on `port` in `netstat -ano -p TCP` output. Listeners are the
+/// rows with a `:0` remote address, which holds even where the state column is localized.
+#[cfg(any(target_os = "windows", test))]
+fn netstat_listener_pid(output: &str, port: u16) -> Option<u32> {
+    output.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [proto, local, remote, .., pid] = fields.as_slice() else {
+            return None;
+        };
+        let local_port = local.rsplit_once(':')?.1.parse::<u16>().ok()?;
+        let listening = remote.ends_with(":0") || *remote == "*:*";
+        (proto.eq_ignore_ascii_case("tcp") && local_port == port && listening)
+            .then(|| pid.parse().ok())
+            .flatten()
+    })
 }
 
-fn parse_tuic(link: &str) -> Result<Value, String> {
-    let url = Url::parse(link).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
-    let server = url
-        .host_str()
-        .ok_or_else(|| err("IMPORT_INVALID", "missing server"))?;
-    let port = url
-        .port()
-        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
-    let mut uuid = url.username().to_string();
-    let mut password = url.password().unwrap_or("").to_string();
-    if password.is_empty() {
-        if let Some((left, right)) = uuid.split_once(':') {
-            let left = left.to_string();
-            let right = right.to_string();
-            uuid = left;
-            password = right;
-        }
-    }
-    if uuid.is_empty() || password.is_empty() {
-        return Err(err("IMPORT_INVALID", "missing uuid/password"));
-    }
-    let params = query_map(&url);
-    let tag = url.fragment().unwrap_or("");
-    let tag = if tag.is_empty() {
-        format!("tuic-{server}:{port}")
-    } else {
-        tag.to_string()
-    };
+/// Socket inodes listening on `port` in a `/proc/net/tcp` or `/proc/net/tcp6` table.
+#[cfg(any(target_os = "linux", test))]
+fn proc_net_listener_inodes(table: &str, port: u16) -> Vec<u64> {
+    const TCP_LISTEN: &str = "0A";
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local_port = fields.get(1)?.rsplit_once(':')?.1;
+            let listening = *fields.get(3)? == TCP_LISTEN;
+            (u16::from_str_radix(local_port, 16).ok()? == port && listening)
+                .then(|| fields.get(9)?.parse().ok())
+                .flatten()
+        })
+        .collect()
+}
 
-    let mut outbound = json!({
-        "type": "tuic",
-        "tag": tag,
-        "server": server,
-        "server_port": port,
-        "uuid": uuid,
-        "password": password
-    });
+#[cfg(target_os = "windows")]
+fn port_owner_pid(port: u16) -> Option<u32> {
+    let mut cmd = Command::new("netstat");
+    cmd.args(["-ano", "-p", "TCP"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null());
+    cmd.creation_flags(CREATE_NO_WINDOW);
+    let output = cmd.output().ok()?;
+    netstat_listener_pid(&String::from_utf8_lossy(&output.stdout), port)
+}
 
-    if let Some(congestion) = params.get("congestion_control") {
-        outbound["congestion_control"] = json!(congestion);
-    }
-    if let Some(udp_mode) = params.get("udp_relay_mode") {
-        outbound["udp_relay_mode"] = json!(udp_mode);
+#[cfg(target_os = "linux")]
+fn port_owner_pid(port: u16) -> Option<u32> {
+    let inodes: HashSet<u64> = ["/proc/net/tcp", "/proc/net/tcp6"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|table| proc_net_listener_inodes(&table, port))
+        .collect();
+    if inodes.is_empty() {
+        return None;
     }
+    fs::read_dir("/proc").ok()?.flatten().find_map(|entry| {
+        let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+        let owns = fs::read_dir(entry.path().join("fd"))
+            .ok()?
+            .flatten()
+            .any(|fd| {
+                fs::read_link(fd.path()).is_ok_and(|target| {
+                    let target = target.to_string_lossy();
+                    target
+                        .strip_prefix("socket:[")
+                        .and_then(|rest| rest.strip_suffix(']'))
+                        .and_then(|inode| inode.parse().ok())
+                        .is_some_and(|inode| inodes.contains(&inode))
+                })
+            });
+        owns.then_some(pid)
+    })
+}
 
-    if let Some(tls) = tls_from_params(&params, Some(server.to_string())) {
-        outbound["tls"] = tls;
-    }
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn port_owner_pid(port: u16) -> Option<u32> {
+    let output = Command::new("lsof")
+        .args(["-nP", &format!("-iTCP:{port}"), "-sTCP:LISTEN", "-t"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.trim().parse().ok())
+}
 
-    Ok(outbound)
+fn port_owner(port: u16) -> Option<String> {
+    let pid = port_owner_pid(port)?;
+    let pid_key = Pid::from_u32(pid);
+    let mut system = System::new();
+    system.refresh_process_specifics(pid_key, ProcessRefreshKind::new());
+    Some(match system.process(pid_key) {
+        Some(process) => format!("{} (pid {pid})", process.name()),
+        None => format!("pid {pid}"),
+    })
 }
 
-fn parse_share_link(link: &str) -> Result<Value, String> {
-    let trimmed = link.trim();
-    if trimmed.starts_with("ss://") {
-        return parse_ss(trimmed);
-    }
-    if trimmed.starts_with("vmess://") {
-        return parse_vmess(trimmed);
-    }
-    if trimmed.starts_with("vless://") {
-        return parse_vless(trimmed);
-    }
-    if trimmed.starts_with("trojan://") {
-        return parse_trojan(trimmed);
-    }
-    if trimmed.starts_with("hysteria2://") || trimmed.starts_with("hy2://") {
-        return parse_hysteria2(trimmed);
-    }
-    if trimmed.starts_with("hysteria://") {
-        return parse_hysteria(trimmed);
-    }
-    if trimmed.starts_with("tuic://") {
-        return parse_tuic(trimmed);
+fn ensure_port_free(host: &str, port: u16) -> Result<(), AppError> {
+    match TcpListener::bind((host, port)) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            let mut detail = format!("port {port} on {host} is already in use");
+            if let Some(owner) = port_owner(port) {
+                detail.push_str(&format!(" by {owner}"));
+            }
+            Err(err("PORT_IN_USE", detail))
+        }
+        Err(e) => Err(err("PORT_IN_USE", format!("cannot bind {host}:{port}: {e}"))),
     }
-    Err(err("IMPORT_UNSUPPORTED", "unsupported share link"))
 }
 
-fn append_outbounds(app: &AppHandle, mut new_outbounds: Vec<Value>) -> Result<ImportResult, String> {
-    let mut profile = load_profile_json(app)?;
-    let profile_obj = profile
-        .as_object_mut()
-        .ok_or_else(|| err("PROFILE_INVALID", "root must be an object"))?;
-    let existing_outbounds = profile_obj
-        .get("outbounds")
-        .and_then(Value::as_array)
-        .cloned()
-        .unwrap_or_default();
+/// Host and port sing-box binds for an `external_controller` value; an empty host
+/// listens on every interface.
+fn controller_listen_addr(controller: &str) -> Option<(String, u16)> {
+    let (host, port) = controller.trim().rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    let host = match host.trim_matches(['[', ']']) {
+        "" => LAN_PROXY_HOST,
+        host => host,
+    };
+    Some((host.to_string(), port))
+}
 
-    let mut outbounds = existing_outbounds;
-    let mut used_tags: HashSet<String> = outbounds
-        .iter()
-        .filter_map(|item| item.get("tag").and_then(Value::as_str))
-        .map(|tag| tag.to_string())
-        .collect();
+fn free_local_address() -> Result<String, AppError> {
+    let addr = TcpListener::bind((LOCAL_PROXY_HOST, 0))
+        .and_then(|listener| listener.local_addr())
+        .map_err(|e| err("PORT_IN_USE", e.to_string()))?;
+    Ok(addr.to_string())
+}
 
-    let mut added = 0;
-    let mut errors = Vec::new();
-    let mut first_added: Option<String> = None;
-    for outbound in new_outbounds.drain(..) {
-        let Some(obj) = outbound.as_object() else {
-            errors.push("Invalid outbound object".to_string());
+fn probe_port(addr: SocketAddr, timeout: Duration) -> bool {
+    TcpStream::connect_timeout(&addr, timeout).is_ok()
+}
+
+fn wait_for_startup(state: &SharedState, token: u64, log_path: &PathBuf) -> Result<(), AppError> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], LOCAL_PROXY_PORT));
+    let spawned = Instant::now();
+    // The startup budget only runs once sing-box is up, not while a password prompt is open.
+    let mut deadline = None;
+    let failure = loop {
+        {
+            let mut guard = state.lock().expect("state lock");
+            if guard.watch_token != token {
+                return Ok(());
+            }
+            refresh_state(&mut guard);
+            if guard.child.is_none() {
+                break match guard.last_exit {
+                    Some(code) => format!("sing-box exited with code {code} during startup"),
+                    None => "sing-box exited during startup".to_string(),
+                };
+            }
+            if deadline.is_none() && guard.elevation.launched() {
+                deadline = Some(Instant::now() + STARTUP_TIMEOUT);
+            }
+        }
+        let Some(deadline) = deadline else {
+            if spawned.elapsed() >= ELEVATION_PROMPT_TIMEOUT {
+                break format!(
+                    "administrator prompt was not answered within {}s",
+                    ELEVATION_PROMPT_TIMEOUT.as_secs()
+                );
+            }
+            std::thread::sleep(Duration::from_millis(150));
             continue;
         };
-
-        let fallback = obj
-            .get("type")
-            .and_then(Value::as_str)
-            .unwrap_or("profile");
-        let tag = guess_tag(&outbound, fallback);
-        let unique = unique_tag(&tag, &mut used_tags);
-        let mut outbound = outbound;
-        outbound["tag"] = json!(unique.clone());
-        if first_added.is_none() {
-            first_added = Some(unique.clone());
+        if probe_port(addr, Duration::from_millis(200)) {
+            return Ok(());
         }
-        outbounds.push(outbound);
-        added += 1;
-    }
+        if Instant::now() >= deadline {
+            break format!(
+                "mixed inbound did not accept connections within {}s",
+                STARTUP_TIMEOUT.as_secs()
+            );
+        }
+        std::thread::sleep(Duration::from_millis(150));
+    };
 
-    profile_obj.insert("outbounds".to_string(), Value::Array(outbounds));
-    save_profile_json(app, &profile)?;
+    let mut detail = failure;
+    append_lines(
+        &mut detail,
+        tail_log_lines(log_path, STARTUP_LOG_LINES, None).unwrap_or_default(),
+    );
+    Err(err("START_FAILED", detail))
+}
 
-    let mut state = load_profile_state(app);
-    if state.active_tag.is_none() {
-        if let Some(tag) = first_added {
-            state.active_tag = Some(tag);
-            let _ = save_profile_state(app, &state);
-        }
+fn append_lines(detail: &mut String, lines: Vec<String>) {
+    for line in lines {
+        detail.push('\n');
+        detail.push_str(&line);
     }
-
-    Ok(ImportResult {
-        profile: profile_data(app, &profile),
-        added,
-        errors,
-    })
 }
 
-#[tauri::command]
-fn get_status(app: AppHandle, state: State<SharedState>) -> ProxyStatus {
-    let mut guard = state.lock().expect("state lock");
-    current_status(&app, &mut guard)
+fn check_config(exe_path: &PathBuf, config_path: &PathBuf) -> Result<(), AppError> {
+    let mut cmd = Command::new(exe_path);
+    cmd.arg("check")
+        .arg("-c")
+        .arg(config_path)
+        .stdin(Stdio::null());
+
+    #[cfg(target_os = "windows")]
+    cmd.creation_flags(CREATE_NO_WINDOW);
+
+    let output = cmd
+        .output()
+        .map_err(|e| err("START_FAILED", e.to_string()))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let captured = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stderr),
+        String::from_utf8_lossy(&output.stdout)
+    );
+    let lines: Vec<String> = captured
+        .lines()
+        .map(|line| strip_ansi(line).trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+    let mut detail = match output.status.code() {
+        Some(code) => format!("sing-box check failed with code {code}"),
+        None => "sing-box check failed".to_string(),
+    };
+    append_lines(
+        &mut detail,
+        lines[lines.len().saturating_sub(CHECK_OUTPUT_LINES)..].to_vec(),
+    );
+    Err(err("CONFIG_INVALID", detail))
 }
 
-#[tauri::command]
-fn get_saved_state(app: AppHandle) -> AppState {
-    load_app_state(&app)
+// Dry runs and profile validation run as async commands and may overlap.
+static DRY_RUN_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Runs `sing-box check` on `config` from a scratch file, so the config the running
+/// proxy was started with stays as it is.
+fn check_config_value(exe_path: &PathBuf, data_dir: &Path, config: &Value) -> Result<(), AppError> {
+    let sequence = DRY_RUN_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let config_path = data_dir.join(format!(
+        "{DRY_RUN_CONFIG_PREFIX}.{}-{sequence}.json",
+        std::process::id()
+    ));
+    let content =
+        serde_json::to_string_pretty(config).map_err(|e| err("CONFIG_INVALID", e.to_string()))?;
+    fs::write(&config_path, content).map_err(|e| err("CONFIG_INVALID", e.to_string()))?;
+    let checked = check_config(exe_path, &config_path);
+    let _ = fs::remove_file(&config_path);
+    checked
 }
 
-#[tauri::command]
-fn list_processes() -> Vec<ProcessEntry> {
-    list_running_processes()
+fn check_generated_config(app: &AppHandle, config: &Value) -> Result<(), AppError> {
+    let data_dir = ensure_app_data_dir(app)?;
+    let exe_path = ensure_singbox_exe(app)?;
+    check_config_value(&exe_path, &data_dir, config)
 }
 
-#[tauri::command]
-fn read_log_tail(app: AppHandle, limit: Option<usize>) -> Result<Vec<String>, String> {
-    let limit = limit.unwrap_or(200).max(1);
-    let path = resolve_log_path(&app)?;
-    if !path.exists() {
-        return Ok(Vec::new());
-    }
-    let mut file = fs::File::open(&path).map_err(|e| err("LOG_ERROR", e.to_string()))?;
-    let file_len = file
-        .metadata()
-        .map_err(|e| err("LOG_ERROR", e.to_string()))?
-        .len();
-    if file_len == 0 {
-        return Ok(Vec::new());
-    }
+fn validate_modes(mut check: impl FnMut(ProxyMode) -> Result<(), AppError>) -> Vec<ModeValidation> {
+    [ProxyMode::Off, ProxyMode::Selected, ProxyMode::Full]
+        .into_iter()
+        .map(|mode| {
+            let checked = check(mode);
+            ModeValidation {
+                mode,
+                ok: checked.is_ok(),
+                error: checked.err(),
+            }
+        })
+        .collect()
+}
 
-    let mut read_size: u64 = 64 * 1024;
-    let mut lines: Vec<String> = Vec::new();
-    loop {
-        let start = if file_len > read_size {
-            file_len - read_size
-        } else {
-            0
-        };
-        file.seek(SeekFrom::Start(start))
-            .map_err(|e| err("LOG_ERROR", e.to_string()))?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)
-            .map_err(|e| err("LOG_ERROR", e.to_string()))?;
-        let text = String::from_utf8_lossy(&buf);
-        lines.clear();
-        lines.extend(text.lines().map(|line| line.to_string()));
-        if lines.len() >= limit || start == 0 {
-            break;
-        }
-        read_size = (read_size * 2).min(file_len);
-    }
+#[tauri::command(async)]
+fn validate_profile(app: AppHandle) -> Result<Vec<ModeValidation>, AppError> {
+    let saved = load_app_state(&app);
+    let app_rules = normalize_app_rules(saved.active_rules())?;
+    Ok(validate_modes(|mode| {
+        let composed = generate_config(
+            &app,
+            mode,
+            app_rules.clone(),
+            saved.force_ipv4_ru,
+            &saved.settings,
+            None,
+        )?;
+        check_generated_config(&app, &composed.config)
+    }))
+}
 
-    if lines.len() > limit {
-        lines = lines.split_off(lines.len() - limit);
+#[tauri::command(async)]
+fn set_mode(
+    app: AppHandle,
+    state: State<SharedState>,
+    mode: ProxyMode,
+    app_rules: Vec<AppRule>,
+    force_ipv4_ru: bool,
+    dry_run: Option<bool>,
+) -> Result<ProxyStatus, AppError> {
+    if dry_run.unwrap_or(false) {
+        return dry_run_mode(&app, state.inner(), mode, app_rules, force_ipv4_ru);
     }
-    Ok(lines)
+    apply_mode(&app, state.inner(), mode, app_rules, force_ipv4_ru)
 }
 
-#[tauri::command]
-fn apply_mode(
+fn dry_run_mode(
     app: &AppHandle,
     state: &SharedState,
     mode: ProxyMode,
     app_rules: Vec<AppRule>,
     force_ipv4_ru: bool,
-) -> Result<ProxyStatus, String> {
-    let _ = save_app_state(
-        app,
-        &AppState {
-            last_mode: mode,
-            app_rules: app_rules.clone(),
-            force_ipv4_ru,
+) -> Result<ProxyStatus, AppError> {
+    let settings = load_app_state(app).settings;
+    let data_dir = ensure_app_data_dir(app)?;
+    dry_run_in(
+        state,
+        &data_dir,
+        mode,
+        app_rules,
+        |app_rules| {
+            let composed =
+                generate_config(app, mode, app_rules, force_ipv4_ru, &settings, None)?;
+            Ok((composed, ensure_singbox_exe(app)?))
         },
-    );
-
-    let mut guard = state.lock().expect("state lock");
+        |guard| current_status(app, guard),
+    )
+}
 
-    if let Some(mut child) = guard.child.take() {
-        let _ = child.kill();
-        let _ = child.wait();
+/// Checks the config `mode` would run with and reports it over the live status. Nothing
+/// is saved, the running child keeps its config and token, and the check uses its own
+/// scratch file under `data_dir`.
+fn dry_run_in(
+    state: &SharedState,
+    data_dir: &Path,
+    mode: ProxyMode,
+    app_rules: Vec<AppRule>,
+    compose: impl FnOnce(Vec<AppRule>) -> Result<(ComposedConfig, PathBuf), AppError>,
+    status: impl FnOnce(&mut ProxyState) -> ProxyStatus,
+) -> Result<ProxyStatus, AppError> {
+    let app_rules = normalize_app_rules(app_rules)?;
+    let rule_conflicts = normalize_rules(app_rules.clone()).conflicts;
+    let mut selector_change = None;
+    if mode != ProxyMode::Off {
+        let (composed, exe_path) = compose(app_rules)?;
+        check_config_value(&exe_path, data_dir, &composed.config)?;
+        selector_change = Some(composed.selector_change);
     }
 
-    guard.mode = ProxyMode::Off;
-    guard.last_error = None;
+    let mut guard = state.lock().expect("state lock");
+    let mut status = status(&mut guard);
+    status.mode = mode;
+    status.selector_change = selector_change;
+    status.rule_conflicts = rule_conflicts;
+    Ok(status)
+}
 
-    if mode == ProxyMode::Off {
-        guard.watch_token = guard.watch_token.wrapping_add(1);
-        return Ok(current_status(app, &mut guard));
+/// Takes the runtime down to Off without touching the saved `last_mode`.
+fn stop_runtime(state: &mut ProxyState, kill: impl FnOnce(&mut ProxyState)) {
+    kill(state);
+    state.mode = ProxyMode::Off;
+    state.last_error = None;
+    state.watch_token = state.watch_token.wrapping_add(1);
+}
+
+fn resume_mode(saved: &AppState) -> Result<ProxyMode, AppError> {
+    if saved.last_mode == ProxyMode::Off {
+        return Err(err("MODE_OFF", "no saved mode to resume"));
     }
+    Ok(saved.last_mode)
+}
 
-    let config_path = match build_config(app, mode, app_rules, force_ipv4_ru) {
-        Ok(path) => path,
-        Err(err) => {
-            guard.last_error = Some(err.clone());
-            return Err(err);
-        }
-    };
-    let log_path = resolve_log_path(app)?;
-    let exe_path = match ensure_singbox_exe(app) {
-        Ok(path) => path,
-        Err(err) => {
-            guard.last_error = Some(err.clone());
-            return Err(err);
-        }
+#[tauri::command(async)]
+fn stop_proxy(app: AppHandle, state: State<SharedState>) -> ProxyStatus {
+    let status = {
+        let mut guard = state.lock().expect("state lock");
+        stop_runtime(&mut guard, |guard| kill_child(&app, guard));
+        current_status(&app, &mut guard)
     };
+    emit_proxy_state(&app);
+    refresh_tray(&app);
+    status
+}
 
-    let log_file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&log_path)
-        .map_err(|e| {
-            let message = err("LOG_ERROR", e.to_string());
-            guard.last_error = Some(message.clone());
-            message
-        })?;
-
-    let mut cmd = Command::new(exe_path);
-    cmd.arg("run").arg("-c").arg(&config_path);
-    cmd.stdout(Stdio::from(
-        log_file
-            .try_clone()
-            .map_err(|e| err("LOG_ERROR", e.to_string()))?,
-    ));
-    cmd.stderr(Stdio::from(log_file));
+#[tauri::command(async)]
+fn start_proxy(app: AppHandle, state: State<SharedState>) -> Result<ProxyStatus, AppError> {
+    let saved = load_app_state(&app);
+    let mode = resume_mode(&saved)?;
+    apply_mode(
+        &app,
+        state.inner(),
+        mode,
+        saved.active_rules(),
+        saved.force_ipv4_ru,
+    )
+}
 
-    #[cfg(target_os = "windows")]
-    cmd.creation_flags(CREATE_NO_WINDOW);
+/// A leading flag emoji groups by its country code ("🇺🇸 New York" -> "US"); otherwise the
+/// text before the first separator is used ("US-01" -> "US", "Provider | HK" -> "Provider").
+fn default_group_key(tag: &str) -> Option<String> {
+    let tag = tag.trim();
+    let flag: String = tag
+        .chars()
+        .take(2)
+        .filter_map(|ch| {
+            let offset = (ch as u32)
+                .checked_sub(0x1F1E6)
+                .filter(|offset| *offset < 26)?;
+            Some(char::from(b'A' + offset as u8))
+        })
+        .collect();
+    if flag.len() == 2 {
+        return Some(flag);
+    }
+    let prefix = tag.split(GROUP_SEPARATORS).next()?.trim();
+    if prefix.is_empty()
+        || prefix.len() == tag.len()
+        || prefix.chars().all(|ch| ch.is_ascii_digit())
+    {
+        return None;
+    }
+    Some(prefix.to_string())
+}
 
-    let child = cmd.spawn().map_err(|e| {
-        let message = err("START_FAILED", e.to_string());
-        guard.last_error = Some(message.clone());
-        message
-    })?;
+fn group_key(tag: &str, pattern: Option<&Regex>) -> String {
+    let custom = pattern.and_then(|pattern| {
+        let captures = pattern.captures(tag)?;
+        let matched = captures.get(1).or_else(|| captures.get(0))?;
+        Some(matched.as_str().trim().to_string()).filter(|key| !key.is_empty())
+    });
+    custom
+        .or_else(|| default_group_key(tag))
+        .unwrap_or_else(|| OTHER_GROUP.to_string())
+}
 
-    #[cfg(target_os = "windows")]
-    {
-        if guard.job.is_none() {
-            if let Ok(job) = create_job_object() {
-                guard.job = Some(job);
-            }
-        }
-        if let Some(job) = guard.job.as_ref() {
-            let _ = unsafe { AssignProcessToJobObject(job.0, child.as_raw_handle() as isize) };
+fn group_tags(tags: Vec<String>, pattern: Option<&Regex>) -> Vec<OutboundGroup> {
+    let mut groups: Vec<OutboundGroup> = Vec::new();
+    for tag in tags {
+        let name = group_key(&tag, pattern);
+        match groups.iter_mut().find(|group| group.name == name) {
+            Some(group) => group.tags.push(tag),
+            None => groups.push(OutboundGroup {
+                name,
+                tags: vec![tag],
+            }),
         }
     }
+    groups.sort_by_key(|group| group.name == OTHER_GROUP);
+    groups
+}
 
-    guard.child = Some(child);
-    guard.mode = mode;
-    guard.config_path = Some(config_path);
-    guard.last_exit = None;
-
-    guard.watch_token = guard.watch_token.wrapping_add(1);
-    let token = guard.watch_token;
-    let state_clone = state.clone();
-    spawn_monitor(app.clone(), state_clone, token);
-    let log_state = state.clone();
-    spawn_log_tailer(app.clone(), log_state, token, log_path);
+#[tauri::command]
+fn group_outbounds(app: AppHandle) -> Result<Vec<OutboundGroup>, AppError> {
+    let settings = load_app_state(&app).settings;
+    let pattern = settings
+        .group_pattern
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .map_err(|e| err("SETTINGS_INVALID", format!("invalid group pattern: {e}")))?;
+    let profile = load_profile_json(&app)?;
+    let tags: Vec<String> = profile
+        .get("outbounds")
+        .and_then(Value::as_array)
+        .map(|items| {
+            items
+                .iter()
+                .filter(|item| {
+                    let kind = item.get("type").and_then(Value::as_str).unwrap_or("");
+                    !GROUP_OUTBOUND_TYPES_SKIPPED.contains(&kind)
+                })
+                .filter_map(|item| item.get("tag").and_then(Value::as_str))
+                .filter(|tag| *tag != "proxy")
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(group_tags(tags, pattern.as_ref()))
+}
 
-    Ok(current_status(app, &mut guard))
+#[tauri::command]
+fn get_template(app: AppHandle) -> Result<Option<Value>, AppError> {
+    load_template(&app)
 }
 
 #[tauri::command]
-fn set_mode(
-    app: AppHandle,
-    state: State<SharedState>,
-    mode: ProxyMode,
-    app_rules: Vec<AppRule>,
-    force_ipv4_ru: bool,
-) -> Result<ProxyStatus, String> {
-    apply_mode(&app, state.inner(), mode, app_rules, force_ipv4_ru)
+fn set_template(app: AppHandle, template: Option<Value>) -> Result<(), AppError> {
+    let path = resolve_template_path(&app)?;
+    match template.filter(|value| !value.is_null()) {
+        Some(template) => {
+            validate_template(&template)?;
+            let content = serde_json::to_string_pretty(&template)
+                .map_err(|e| err("TEMPLATE_INVALID", e.to_string()))?;
+            fs::write(&path, content).map_err(|e| err("TEMPLATE_INVALID", e.to_string()))
+        }
+        None => match fs::remove_file(&path) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                Err(err("TEMPLATE_INVALID", error.to_string()))
+            }
+            _ => Ok(()),
+        },
+    }
 }
 
 #[tauri::command]
-fn get_profiles(app: AppHandle) -> Result<ProfileData, String> {
+fn get_profiles(app: AppHandle) -> Result<ProfileData, AppError> {
     let profile = load_profile_json(&app)?;
     Ok(profile_data(&app, &profile))
 }
 
 #[tauri::command]
-fn set_active_profile(app: AppHandle, tag: String) -> Result<ProfileData, String> {
-    let mut state = load_profile_state(&app);
-    state.active_tag = Some(tag);
-    save_profile_state(&app, &state)?;
+fn set_active_profile(
+    app: AppHandle,
+    tag: String,
+    mode: Option<ProxyMode>,
+) -> Result<ProfileData, AppError> {
+    select_node(&app, &tag, mode)?;
+    refresh_tray(&app);
     let profile = load_profile_json(&app)?;
     Ok(profile_data(&app, &profile))
 }
 
 #[tauri::command]
-fn remove_outbound(app: AppHandle, tag: String) -> Result<ProfileData, String> {
+fn remove_outbound(app: AppHandle, tag: String) -> Result<ProfileData, AppError> {
     let mut profile = load_profile_json(&app)?;
     let profile_obj = profile
         .as_object_mut()
@@ -2038,26 +5914,247 @@ fn remove_outbound(app: AppHandle, tag: String) -> Result<ProfileData, String> {
     save_profile_json(&app, &profile)?;
 
     let mut state = load_profile_state(&app);
-    if state.active_tag.as_deref() == Some(tag.as_str()) {
+    let by_mode_len = state.active_tag_by_mode.len();
+    state.active_tag_by_mode.retain(|_, active| *active != tag);
+    let clear_active = state.active_tag.as_deref() == Some(tag.as_str());
+    if clear_active {
         state.active_tag = None;
+    }
+    if clear_active || state.active_tag_by_mode.len() != by_mode_len {
         let _ = save_profile_state(&app, &state);
     }
+    refresh_tray(&app);
     Ok(profile_data(&app, &profile))
 }
 
 #[tauri::command]
-fn import_share_links(app: AppHandle, links: Vec<String>) -> Result<ImportResult, String> {
+fn export_profile(app: AppHandle) -> Result<String, AppError> {
+    let state = load_profile_state(&app);
+    let export = ProfileExport {
+        version: PROFILE_EXPORT_VERSION,
+        profile: load_profile_json(&app)?,
+        active_tag: state.active_tag,
+        active_tag_by_mode: state.active_tag_by_mode,
+        app_rules: load_app_state(&app).active_rules(),
+    };
+    serde_json::to_string_pretty(&export).map_err(|e| err("EXPORT_FAILED", e.to_string()))
+}
+
+#[tauri::command]
+fn import_profile(app: AppHandle, payload: String) -> Result<ProfileData, AppError> {
+    let value: Value =
+        serde_json::from_str(&payload).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| err("IMPORT_INVALID", "missing version"))?;
+    if version != PROFILE_EXPORT_VERSION {
+        return Err(err("IMPORT_VERSION_UNSUPPORTED", version.to_string()));
+    }
+    let export: ProfileExport =
+        serde_json::from_value(value).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
+    validate_profile_shape(&export.profile).map_err(|error| err("IMPORT_INVALID", error.detail))?;
+    let outbounds = export
+        .profile
+        .get("outbounds")
+        .and_then(Value::as_array)
+        .ok_or_else(|| err("IMPORT_INVALID", "profile must contain an outbounds array"))?;
+    let errors: Vec<String> = outbounds
+        .iter()
+        .filter_map(|outbound| {
+            validate_outbound(outbound)
+                .err()
+                .map(|reason| format!("{}: {reason}", guess_tag(outbound, "outbound")))
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(err("IMPORT_INVALID", errors.join("\n")));
+    }
+
+    backup_state(&app)?;
+    save_profile_json(&app, &export.profile)?;
+    save_profile_state(
+        &app,
+        &ProfileState {
+            active_tag: export.active_tag,
+            active_tag_by_mode: export.active_tag_by_mode,
+            ..ProfileState::default()
+        },
+    )?;
+    let mut app_state = load_app_state(&app);
+    app_state.store_rules(export.app_rules);
+    save_app_state(&app, &app_state)?;
+    refresh_tray(&app);
+
+    Ok(profile_data(&app, &export.profile))
+}
+
+#[tauri::command]
+fn list_profile_groups(app: AppHandle) -> ProfileGroups {
+    load_profile_groups(&app)
+}
+
+#[tauri::command]
+fn create_profile_group(app: AppHandle, name: String) -> Result<ProfileGroups, AppError> {
+    let name = validate_group_name(&name)?;
+    let mut groups = load_profile_groups(&app);
+    let profile_path = resolve_group_profile_path(&app, &name)?;
+    groups.add(name)?;
+    if !profile_path.exists() {
+        let content = serde_json::to_string_pretty(&json!({ "outbounds": [] }))
+            .map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
+        fs::write(&profile_path, content).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
+    }
+    save_profile_groups(&app, &groups)?;
+    Ok(groups)
+}
+
+#[tauri::command(async)]
+fn switch_profile_group(app: AppHandle, name: String) -> Result<ProfileGroups, AppError> {
+    let mut groups = load_profile_groups(&app);
+    let running = running_mode(&app);
+    let Some(previous) = groups.switch_to(name)? else {
+        return Ok(groups);
+    };
+    backup_state(&app)?;
+    save_profile_groups(&app, &groups)?;
+    if let Some(mode) = running {
+        if let Err(error) = reapply_mode(&app, mode) {
+            // The proxy was already stopped, so bring it back on the group that worked.
+            groups.switch_to(previous)?;
+            save_profile_groups(&app, &groups)?;
+            let _ = reapply_mode(&app, mode);
+            refresh_tray(&app);
+            return Err(error);
+        }
+    }
+    refresh_tray(&app);
+    Ok(groups)
+}
+
+#[tauri::command]
+fn delete_profile_group(app: AppHandle, name: String) -> Result<ProfileGroups, AppError> {
+    let mut groups = load_profile_groups(&app);
+    groups.remove(&name)?;
+    for path in [
+        resolve_group_profile_path(&app, &name)?,
+        resolve_group_state_path(&app, &name)?,
+    ] {
+        if path.exists() {
+            fs::remove_file(&path).map_err(|e| err("PROFILE_INVALID", e.to_string()))?;
+        }
+    }
+    save_profile_groups(&app, &groups)?;
+    Ok(groups)
+}
+
+fn validate_preset_name(name: &str) -> Result<String, AppError> {
+    let trimmed = name.trim();
+    if trimmed.is_empty() {
+        return Err(err("PRESET_INVALID", "name is empty"));
+    }
+    if trimmed.chars().count() > 64 {
+        return Err(err("PRESET_INVALID", "name is too long"));
+    }
+    Ok(trimmed.to_string())
+}
+
+#[tauri::command]
+fn list_rule_presets(app: AppHandle) -> RulePresets {
+    load_app_state(&app).rule_presets()
+}
+
+#[tauri::command]
+fn create_rule_preset(
+    app: AppHandle,
+    name: String,
+    rules: Option<Vec<AppRule>>,
+) -> Result<RulePresets, AppError> {
+    let name = validate_preset_name(&name)?;
+    let rules = normalize_app_rules(rules.unwrap_or_default())?;
+    let mut saved = load_app_state(&app);
+    if saved
+        .rule_presets
+        .iter()
+        .any(|preset| preset.name.eq_ignore_ascii_case(&name))
+    {
+        return Err(err("PRESET_EXISTS", name));
+    }
+    saved.rule_presets.push(RulePreset { name, rules });
+    save_app_state(&app, &saved)?;
+    Ok(saved.rule_presets())
+}
+
+#[tauri::command(async)]
+fn select_rule_preset(app: AppHandle, name: String) -> Result<RulePresets, AppError> {
+    let mut saved = load_app_state(&app);
+    if !saved.select_preset(name)? {
+        return Ok(saved.rule_presets());
+    }
+    save_app_state(&app, &saved)?;
+    restart_if_running(&app)?;
+    Ok(saved.rule_presets())
+}
+
+#[tauri::command]
+fn delete_rule_preset(app: AppHandle, name: String) -> Result<RulePresets, AppError> {
+    let mut saved = load_app_state(&app);
+    if !saved.rule_presets.iter().any(|preset| preset.name == name) {
+        return Err(err("PRESET_MISSING", name));
+    }
+    if saved.active_preset == name {
+        return Err(err("PRESET_ACTIVE", name));
+    }
+    if name == DEFAULT_RULE_PRESET {
+        return Err(err("PRESET_INVALID", "default preset cannot be deleted"));
+    }
+    saved.rule_presets.retain(|preset| preset.name != name);
+    save_app_state(&app, &saved)?;
+    Ok(saved.rule_presets())
+}
+
+/// Parses share links, reporting progress after every `batch_size` links and after the last one.
+fn parse_links_in_batches(
+    links: Vec<String>,
+    batch_size: usize,
+    mut progress: impl FnMut(ImportProgress),
+) -> (Vec<Value>, Vec<String>) {
+    let total = links.len();
+    let batch_size = batch_size.max(1);
     let mut errors = Vec::new();
     let mut outbounds = Vec::new();
-    for link in links {
-        if link.trim().is_empty() {
-            continue;
-        }
+    for (index, link) in links.into_iter().enumerate() {
         match parse_share_link(link.as_str()) {
             Ok(outbound) => outbounds.push(outbound),
             Err(error) => errors.push(format!("{link}: {error}")),
         }
+        let parsed = index + 1;
+        if parsed % batch_size == 0 || parsed == total {
+            progress(ImportProgress {
+                parsed,
+                total,
+                failed: errors.len(),
+            });
+        }
     }
+    (outbounds, errors)
+}
+
+#[tauri::command(async)]
+fn import_share_links(
+    app: AppHandle,
+    links: Vec<String>,
+    dedupe: Option<bool>,
+    batch_size: Option<usize>,
+) -> Result<ImportResult, AppError> {
+    let links: Vec<String> = expand_subscriptions(links)
+        .into_iter()
+        .filter(|link| !link.trim().is_empty())
+        .collect();
+    let batch_size = batch_size.unwrap_or(IMPORT_PROGRESS_BATCH);
+    let (outbounds, errors) = parse_links_in_batches(links, batch_size, |progress| {
+        let _ = app.emit("import-progress", progress);
+    });
 
     if outbounds.is_empty() {
         return Err(err(
@@ -2070,15 +6167,96 @@ fn import_share_links(app: AppHandle, links: Vec<String>) -> Result<ImportResult
         ));
     }
 
-    let mut result = append_outbounds(&app, outbounds)?;
+    let warnings: Vec<String> = outbounds.iter().filter_map(legacy_vmess_warning).collect();
+    let mut result = append_outbounds(&app, outbounds, dedupe.unwrap_or(false))?;
+    result.errors.extend(errors);
+    result.errors.extend(warnings);
+    Ok(result)
+}
+
+#[cfg(feature = "clash")]
+fn parse_clash_yaml(payload: &str) -> Result<(Vec<Value>, Vec<String>), AppError> {
+    clash::parse_proxies(payload)
+}
+
+#[cfg(not(feature = "clash"))]
+fn parse_clash_yaml(_payload: &str) -> Result<(Vec<Value>, Vec<String>), AppError> {
+    Err(err("FEATURE_DISABLED", "clash YAML support is not enabled"))
+}
+
+#[tauri::command]
+fn import_clash_yaml(
+    app: AppHandle,
+    payload: String,
+    dedupe: Option<bool>,
+) -> Result<ImportResult, AppError> {
+    let (outbounds, errors) = parse_clash_yaml(&payload)?;
+    if outbounds.is_empty() {
+        return Err(err(
+            "IMPORT_FAILED",
+            if errors.is_empty() {
+                "no proxies found".to_string()
+            } else {
+                errors.join("\n")
+            },
+        ));
+    }
+
+    let warnings: Vec<String> = outbounds.iter().filter_map(legacy_vmess_warning).collect();
+    let mut result = append_outbounds(&app, outbounds, dedupe.unwrap_or(false))?;
     result.errors.extend(errors);
+    result.errors.extend(warnings);
     Ok(result)
 }
 
 #[tauri::command]
-fn import_outbound_json(app: AppHandle, payload: String) -> Result<ImportResult, String> {
+fn preview_share_link(link: String) -> Result<Value, AppError> {
+    parse_share_link(&link)
+}
+
+#[tauri::command]
+fn preview_links(links: Vec<String>) -> Vec<PreviewResult> {
+    expand_subscriptions(links)
+        .into_iter()
+        .filter(|link| !link.trim().is_empty())
+        .map(|link| match parse_share_link(&link) {
+            Ok(outbound) => PreviewResult {
+                input: link,
+                outbound: Some(outbound),
+                error: None,
+            },
+            Err(error) => PreviewResult {
+                input: link,
+                outbound: None,
+                error: Some(error),
+            },
+        })
+        .collect()
+}
+
+struct ParsedOutbounds {
+    outbounds: Vec<Value>,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+#[tauri::command]
+fn import_outbound_json(app: AppHandle, payload: String) -> Result<ImportResult, AppError> {
+    let ParsedOutbounds {
+        outbounds,
+        errors,
+        warnings,
+    } = parse_outbound_json(&payload)?;
+    let mut result = append_outbounds(&app, outbounds, false)?;
+    result.errors.extend(errors);
+    result.errors.extend(warnings);
+    Ok(result)
+}
+
+/// Entries may be outbound objects or share links; a bad entry is reported, not fatal.
+fn parse_outbound_json(payload: &str) -> Result<ParsedOutbounds, AppError> {
     let value: Value =
-        serde_json::from_str(&payload).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
+        serde_json::from_str(payload).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
     let mut outbounds = Vec::new();
     match value {
         Value::Array(values) => {
@@ -2100,7 +6278,37 @@ fn import_outbound_json(app: AppHandle, payload: String) -> Result<ImportResult,
     if outbounds.is_empty() {
         return Err(err("IMPORT_INVALID", "no outbounds found"));
     }
-    append_outbounds(&app, outbounds)
+    let mut valid = Vec::new();
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    for outbound in outbounds {
+        if let Value::String(link) = &outbound {
+            let link = link.trim();
+            if link.is_empty() {
+                continue;
+            }
+            match parse_share_link(link) {
+                Ok(parsed) => {
+                    warnings.extend(legacy_vmess_warning(&parsed));
+                    valid.push(parsed);
+                }
+                Err(error) => errors.push(format!("{link}: {error}")),
+            }
+            continue;
+        }
+        match validate_outbound(&outbound) {
+            Ok(()) => valid.push(outbound),
+            Err(reason) => errors.push(format!("{}: {reason}", guess_tag(&outbound, "outbound"))),
+        }
+    }
+    if valid.is_empty() {
+        return Err(err("IMPORT_FAILED", errors.join("\n")));
+    }
+    Ok(ParsedOutbounds {
+        outbounds: valid,
+        errors,
+        warnings,
+    })
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -2109,23 +6317,38 @@ pub fn run() {
     let app = tauri::Builder::default()
         .plugin(tauri_plugin_autostart::init(
             MacosLauncher::LaunchAgent,
-            Some(vec![AUTOSTART_ARG]),
+            Some(autostart_args()),
         ))
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_opener::init())
         .manage(ExitFlag::default())
+        .manage(LogStream::default())
+        .manage(StateRecoveries::default())
+        .manage(ProfileWatch::default())
+        .manage(SingboxVersion::default())
+        .manage(ClashApi::default())
         .manage(Arc::new(Mutex::new(ProxyState::default())))
         .setup(move |app| {
             let app_handle = app.handle();
-            let saved_state = load_app_state(&app_handle);
+            let saved_state = load_app_state(app_handle);
             let saved_mode = saved_state.last_mode;
-            let saved_rules = saved_state.app_rules;
+            let saved_rules = saved_state.active_rules();
             let saved_force_ipv4_ru = saved_state.force_ipv4_ru;
+            let start_minimized = saved_state.settings.start_minimized;
+            let autostart_delay = if autostart_launch {
+                saved_state.settings.autostart_delay()
+            } else {
+                Duration::ZERO
+            };
 
-            let tray_menu = Menu::new(app)?;
-            let open_item = MenuItemBuilder::with_id(TRAY_OPEN_ID, "Открыть").build(app)?;
-            let exit_item = MenuItemBuilder::with_id(TRAY_EXIT_ID, "Закрыть").build(app)?;
-            tray_menu.append_items(&[&open_item, &exit_item])?;
+            kill_orphaned_singbox(app_handle);
+            let _ = start_profile_watcher(app_handle);
+            let version_handle = app_handle.clone();
+            std::thread::spawn(move || {
+                version_handle.state::<SingboxVersion>().get(&version_handle);
+            });
+
+            let tray_menu = build_tray_menu(app_handle)?;
 
             let mut tray_builder = TrayIconBuilder::new()
                 .menu(&tray_menu)
@@ -2138,7 +6361,11 @@ pub fn run() {
                         exit_flag.allow_exit();
                         app.exit(0);
                     }
-                    _ => {}
+                    id => {
+                        let app = app.clone();
+                        let id = id.to_string();
+                        std::thread::spawn(move || handle_tray_menu_event(&app, &id));
+                    }
                 })
                 .on_tray_icon_event(|tray, event| {
                     if let TrayIconEvent::Click {
@@ -2156,33 +6383,104 @@ pub fn run() {
             }
 
             let tray = tray_builder.build(app)?;
-            app.manage(TrayState { _tray: tray });
+            app.manage(TrayState { tray });
 
-            if autostart_launch && saved_mode != ProxyMode::Off {
-                hide_main_window(&app_handle);
+            restore_window_geometry(app_handle);
+            if start_minimized || (autostart_launch && saved_mode != ProxyMode::Off) {
+                hide_main_window(app_handle);
             }
 
-            let state = app.state::<SharedState>();
-            let _ = apply_mode(
-                &app_handle,
-                state.inner(),
-                saved_mode,
-                saved_rules,
-                saved_force_ipv4_ru,
-            );
+            if saved_mode == ProxyMode::Off {
+                let state = app.state::<SharedState>();
+                let _ = apply_mode(
+                    app_handle,
+                    state.inner(),
+                    saved_mode,
+                    saved_rules,
+                    saved_force_ipv4_ru,
+                );
+            } else {
+                let app_handle = app_handle.clone();
+                std::thread::spawn(move || {
+                    if !autostart_delay.is_zero() {
+                        std::thread::sleep(autostart_delay);
+                    }
+                    let state = app_handle.state::<SharedState>();
+                    let already_running = {
+                        let mut guard = state.lock().expect("state lock");
+                        refresh_state(&mut guard);
+                        guard.child.is_some()
+                    };
+                    if already_running {
+                        return;
+                    }
+                    let _ = apply_mode(
+                        &app_handle,
+                        state.inner(),
+                        saved_mode,
+                        saved_rules,
+                        saved_force_ipv4_ru,
+                    );
+                });
+            }
 
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             get_status,
+            clear_last_error,
             get_saved_state,
+            take_state_recoveries,
+            set_settings,
+            refresh_rule_sets,
+            download_rule_sets,
+            encrypt_profiles,
+            create_backup,
+            list_backups,
+            restore_backup,
+            reset_to_defaults,
             list_processes,
             read_log_tail,
+            clear_log,
+            pause_log_stream,
+            resume_log_stream,
+            get_autostart,
+            set_autostart,
+            get_effective_config,
+            test_outbound_latency,
+            test_all_outbounds,
+            get_tunnel_info,
+            tcp_ping,
+            open_config_folder,
+            open_config_file,
+            open_log_file,
             set_mode,
+            stop_proxy,
+            start_proxy,
             get_profiles,
+            group_outbounds,
+            validate_profile,
+            probe_active_node,
+            toggle_main_window,
+            resolve_node_country,
+            get_template,
+            set_template,
             set_active_profile,
             remove_outbound,
+            export_profile,
+            import_profile,
+            list_profile_groups,
+            create_profile_group,
+            switch_profile_group,
+            delete_profile_group,
+            list_rule_presets,
+            create_rule_preset,
+            select_rule_preset,
+            delete_rule_preset,
             import_share_links,
+            import_clash_yaml,
+            preview_share_link,
+            preview_links,
             import_outbound_json
         ])
         .build(tauri::generate_context!())
@@ -2190,25 +6488,30 @@ pub fn run() {
 
     app.run(|app_handle, event| {
         match event {
-            tauri::RunEvent::WindowEvent { label, event, .. } => {
-                if let tauri::WindowEvent::CloseRequested { api, .. } = event {
-                    let exit_flag = app_handle.state::<ExitFlag>();
-                    if exit_flag.is_allowed() {
-                        return;
-                    }
-                    api.prevent_close();
-                    if label == "main" {
-                        hide_main_window(app_handle);
-                    }
+            tauri::RunEvent::WindowEvent {
+                label,
+                event: tauri::WindowEvent::CloseRequested { api, .. },
+                ..
+            } => {
+                let exit_flag = app_handle.state::<ExitFlag>();
+                if exit_flag.is_allowed() {
+                    return;
+                }
+                api.prevent_close();
+                if label == "main" {
+                    hide_main_window(app_handle);
                 }
             }
             tauri::RunEvent::ExitRequested { .. } | tauri::RunEvent::Exit => {
+                if let Some(window) = app_handle.get_webview_window("main") {
+                    save_window_geometry(app_handle, &window);
+                }
                 let state: State<SharedState> = app_handle.state();
                 let guard_result = state.lock();
                 if let Ok(mut guard) = guard_result {
                     if let Some(mut child) = guard.child.take() {
-                        let _ = child.kill();
-                        let _ = child.wait();
+                        stop_child(&mut child, &guard.elevation);
+                        remove_pid_lock(app_handle);
                     }
                 }
             }
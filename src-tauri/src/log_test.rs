@@ -0,0 +1,240 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use crate::test_util::scratch_dir;
+use crate::{
+    exit_detail, move_log_prefix, parse_log_line, rotate_log_file, tail_log_lines, trim_log_file,
+    truncate_log, LogLevel, LogTail, EXIT_LOG_LINES,
+};
+
+fn parsed(line: &str) -> (Option<LogLevel>, Option<String>, String) {
+    let entry = parse_log_line(line);
+    (entry.level, entry.ts, entry.msg)
+}
+
+#[test]
+fn parses_singbox_timestamp_and_level() {
+    assert_eq!(
+        parsed("+0300 2025-01-02 10:11:12 INFO [1234 0ms] inbound/mixed[local-proxy]: started"),
+        (
+            Some(LogLevel::Info),
+            Some("+0300 2025-01-02 10:11:12".to_string()),
+            "[1234 0ms] inbound/mixed[local-proxy]: started".to_string()
+        )
+    );
+}
+
+#[test]
+fn strips_ansi_colors() {
+    assert_eq!(
+        parsed("\u{1b}[31mERROR\u{1b}[0m dns: lookup failed"),
+        (
+            Some(LogLevel::Error),
+            None,
+            "dns: lookup failed".to_string()
+        )
+    );
+}
+
+#[test]
+fn accepts_bracketed_and_aliased_levels() {
+    assert_eq!(parsed("[WARNING] slow handshake").0, Some(LogLevel::Warn));
+    assert_eq!(parsed("12:00:00 panic: boom").0, Some(LogLevel::Fatal));
+}
+
+#[test]
+fn lines_without_a_level_keep_the_whole_text() {
+    assert_eq!(
+        parsed("  goroutine 1 [running]:  "),
+        (None, None, "goroutine 1 [running]:".to_string())
+    );
+}
+
+#[test]
+fn levels_are_ordered_by_severity() {
+    assert!(LogLevel::Trace < LogLevel::Debug);
+    assert!(LogLevel::Warn < LogLevel::Error);
+    assert!(LogLevel::Error < LogLevel::Fatal);
+}
+
+#[test]
+fn small_logs_are_not_rotated() {
+    let dir = scratch_dir("rotate-small");
+    let log = dir.join("singbox.log");
+    fs::write(&log, "short").unwrap();
+    assert!(!rotate_log_file(&log, 1024, 3).unwrap());
+    assert_eq!(fs::read_to_string(&log).unwrap(), "short");
+    assert!(!dir.join("singbox.log.1").exists());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn missing_log_is_not_an_error() {
+    let dir = scratch_dir("rotate-missing");
+    assert!(!rotate_log_file(&dir.join("singbox.log"), 1, 3).unwrap());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn rotation_shifts_old_files_and_truncates_the_log() {
+    let dir = scratch_dir("rotate-shift");
+    let log = dir.join("singbox.log");
+    fs::write(&log, "current").unwrap();
+    fs::write(dir.join("singbox.log.1"), "older").unwrap();
+    fs::write(dir.join("singbox.log.2"), "oldest").unwrap();
+
+    assert!(rotate_log_file(&log, 4, 2).unwrap());
+    assert_eq!(fs::read_to_string(&log).unwrap(), "");
+    assert_eq!(
+        fs::read_to_string(dir.join("singbox.log.1")).unwrap(),
+        "current"
+    );
+    // Only `count` rotated files are kept; the oldest one is overwritten.
+    assert_eq!(
+        fs::read_to_string(dir.join("singbox.log.2")).unwrap(),
+        "older"
+    );
+    assert!(!dir.join("singbox.log.3").exists());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn rotation_keeps_lines_appended_after_the_size_check() {
+    let dir = scratch_dir("rotate-appended");
+    let log_path = dir.join("singbox.log");
+    fs::write(&log_path, "rotated\nappended\n").unwrap();
+    let mut log = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&log_path)
+        .unwrap();
+    let rotated_path = dir.join("singbox.log.1");
+    let mut rotated = fs::File::create(&rotated_path).unwrap();
+
+    move_log_prefix(&mut log, &mut rotated, "rotated\n".len() as u64).unwrap();
+    assert_eq!(fs::read_to_string(&rotated_path).unwrap(), "rotated\n");
+    assert_eq!(fs::read_to_string(&log_path).unwrap(), "appended\n");
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn exit_detail_keeps_the_last_logged_lines() {
+    let dir = scratch_dir("exit-detail");
+    let log = dir.join("singbox.log");
+    let mut content: String = (0..EXIT_LOG_LINES)
+        .map(|index| format!("INFO line {index}\n"))
+        .collect();
+    content.push_str(
+        "FATAL[0000] start service: listen tcp 127.0.0.1:2080: bind: address already in use\n",
+    );
+    fs::write(&log, content).unwrap();
+
+    let detail = exit_detail(Some(1), &log);
+    let lines: Vec<&str> = detail.lines().collect();
+    assert_eq!(lines[0], "sing-box exited with code 1");
+    assert_eq!(lines.len(), EXIT_LOG_LINES + 1);
+    assert!(lines[EXIT_LOG_LINES].contains("address already in use"));
+    assert!(!detail.contains("line 0\n"));
+
+    let missing = dir.join("missing.log");
+    assert_eq!(exit_detail(None, &missing), "sing-box exited");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn paused_tail_reads_nothing_and_resumes_at_the_end() {
+    let dir = scratch_dir("tail-pause");
+    let log_path = dir.join("singbox.log");
+    fs::write(&log_path, "before open\n").unwrap();
+    let mut tail = LogTail::open(&log_path).unwrap();
+    let append = |line: &str| {
+        let mut log = OpenOptions::new().append(true).open(&log_path).unwrap();
+        writeln!(log, "{line}").unwrap();
+    };
+    let mut pending = Vec::new();
+
+    append("live");
+    tail.read_lines(false, &mut pending).unwrap();
+    assert_eq!(pending, ["live"]);
+    pending.clear();
+
+    append("while paused");
+    tail.read_lines(true, &mut pending).unwrap();
+    assert!(pending.is_empty());
+    append("still paused");
+    tail.read_lines(true, &mut pending).unwrap();
+    assert!(pending.is_empty());
+
+    // Resuming seeks past what was logged during the pause.
+    tail.read_lines(false, &mut pending).unwrap();
+    assert!(pending.is_empty());
+    append("after resume");
+    tail.read_lines(false, &mut pending).unwrap();
+    assert_eq!(pending, ["after resume"]);
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn cleared_log_is_read_from_the_start() {
+    let dir = scratch_dir("clear-log");
+    let log_path = dir.join("singbox.log");
+    let old: String = (0..20).map(|index| format!("INFO old {index}\n")).collect();
+    fs::write(&log_path, old).unwrap();
+    let mut tail = LogTail::open(&log_path).unwrap();
+
+    truncate_log(&log_path).unwrap();
+    assert_eq!(fs::metadata(&log_path).unwrap().len(), 0);
+    let mut log = OpenOptions::new().append(true).open(&log_path).unwrap();
+    writeln!(log, "INFO fresh").unwrap();
+
+    let mut pending = vec!["INFO old 19".to_string()];
+    tail.read_lines(false, &mut pending).unwrap();
+    assert_eq!(pending, ["INFO fresh"]);
+    assert!(truncate_log(&dir.join("missing.log")).is_ok());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn tail_at_warn_keeps_unleveled_lines_and_respects_the_limit() {
+    let dir = scratch_dir("tail-warn");
+    let log = dir.join("singbox.log");
+    fs::write(
+        &log,
+        "INFO started\nWARN slow handshake\nDEBUG dial\ngoroutine 1 [running]:\nERROR lookup failed\nTRACE read\nFATAL boom\n",
+    )
+    .unwrap();
+    let warn = Some(LogLevel::Warn);
+    assert_eq!(
+        tail_log_lines(&log, 10, warn).unwrap(),
+        vec![
+            "WARN slow handshake",
+            "goroutine 1 [running]:",
+            "ERROR lookup failed",
+            "FATAL boom",
+        ]
+    );
+    assert_eq!(
+        tail_log_lines(&log, 2, warn).unwrap(),
+        vec!["ERROR lookup failed", "FATAL boom"]
+    );
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn trimming_follows_the_configured_thresholds() {
+    let dir = scratch_dir("trim-thresholds");
+    let log = dir.join("singbox.log");
+    let content: String = (0..100).map(|index| format!("{index:09}\n")).collect();
+    fs::write(&log, &content).unwrap();
+
+    assert!(!trim_log_file(&log, 100, 1000).unwrap());
+    assert_eq!(fs::read_to_string(&log).unwrap(), content);
+
+    assert!(trim_log_file(&log, 300, 999).unwrap());
+    assert_eq!(fs::read_to_string(&log).unwrap(), content[700..]);
+
+    assert!(!trim_log_file(&log, 100, 300).unwrap());
+    assert!(trim_log_file(&log, 40, 299).unwrap());
+    assert_eq!(fs::read_to_string(&log).unwrap(), content[960..]);
+    let _ = fs::remove_dir_all(dir);
+}
@@ -0,0 +1,977 @@
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine;
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use url::Url;
+
+use crate::{err, AppError};
+
+const INSECURE_PARAM_KEYS: [&str; 5] = [
+    "insecure",
+    "allowinsecure",
+    "allow_insecure",
+    "skip-cert-verify",
+    "skipcertverify",
+];
+/// sing-box reality field, accepted share-link keys, and whether an empty value is kept.
+/// Xray's post-quantum `pqv`/`mldsa65Verify` are optional client checks with no sing-box
+/// field, so they are left out and the node imports without them.
+const REALITY_PARAM_FIELDS: [(&str, &[&str], bool); 3] = [
+    ("public_key", &["pbk", "public_key", "publickey"], false),
+    ("short_id", &["sid", "short_id", "shortid"], true),
+    ("spider_x", &["spx", "spider_x", "spiderx"], false),
+];
+const SS_AEAD_METHODS: [&str; 6] = [
+    "none",
+    "aes-128-gcm",
+    "aes-192-gcm",
+    "aes-256-gcm",
+    "chacha20-ietf-poly1305",
+    "xchacha20-ietf-poly1305",
+];
+const SS_2022_METHODS: [(&str, usize); 3] = [
+    ("2022-blake3-aes-128-gcm", 16),
+    ("2022-blake3-aes-256-gcm", 32),
+    ("2022-blake3-chacha20-poly1305", 32),
+];
+
+fn add_padding(value: &str) -> String {
+    let remainder = value.len() % 4;
+    if remainder == 0 {
+        value.to_string()
+    } else {
+        format!("{value}{}", "=".repeat(4 - remainder))
+    }
+}
+
+pub(crate) fn decode_base64_to_string(input: &str) -> Result<String, AppError> {
+    let cleaned = input.trim();
+    let candidates = vec![
+        cleaned.to_string(),
+        cleaned.replace('-', "+").replace('_', "/"),
+    ];
+    for candidate in candidates {
+        let padded = add_padding(&candidate);
+        for engine in [URL_SAFE_NO_PAD, URL_SAFE, STANDARD_NO_PAD, STANDARD] {
+            if let Ok(bytes) = engine.decode(candidate.as_bytes()) {
+                if let Ok(value) = String::from_utf8(bytes) {
+                    return Ok(value);
+                }
+            }
+            if candidate != padded {
+                if let Ok(bytes) = engine.decode(padded.as_bytes()) {
+                    if let Ok(value) = String::from_utf8(bytes) {
+                        return Ok(value);
+                    }
+                }
+            }
+        }
+    }
+    Err(err("IMPORT_INVALID", "base64 decode failed"))
+}
+
+fn query_map(url: &Url) -> HashMap<String, String> {
+    url.query_pairs()
+        .map(|(k, v)| (k.to_lowercase(), v.to_string()))
+        .collect()
+}
+
+fn decode_query_component(value: &str) -> String {
+    let replaced = value.replace('+', " ");
+    percent_decode_str(&replaced)
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+fn decode_fragment(fragment: &str) -> String {
+    decode_query_component(fragment).trim().to_string()
+}
+
+fn parse_ss_query(query: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    for part in query.split('&') {
+        if part.is_empty() {
+            continue;
+        }
+        let (key, value) = part.split_once('=').unwrap_or((part, ""));
+        let key = decode_query_component(key).to_lowercase();
+        let value = decode_query_component(value);
+        params.insert(key, value);
+    }
+    params
+}
+
+pub(crate) fn normalize_v2ray_plugin_opts(opts: &str) -> Result<String, AppError> {
+    let mut parts = Vec::new();
+    let items = opts
+        .split(';')
+        .map(str::trim)
+        .filter(|item| !item.is_empty());
+    for item in items {
+        let (key, value) = item.split_once('=').unwrap_or((item, ""));
+        if key == "mode" && value != "websocket" {
+            return Err(err(
+                "IMPORT_UNSUPPORTED",
+                format!("unsupported v2ray-plugin mode: {value}"),
+            ));
+        }
+        parts.push(item);
+    }
+    Ok(parts.join(";"))
+}
+
+/// `1`/`true` enables the original UoT protocol; a version number selects it explicitly.
+pub(crate) fn udp_over_tcp_value(value: &str) -> Option<Value> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("true") || value == "1" {
+        return Some(json!(true));
+    }
+    match value.parse::<u8>() {
+        Ok(version) if version > 1 => Some(json!({
+            "enabled": true,
+            "version": version
+        })),
+        _ => None,
+    }
+}
+
+/// Reads bandwidth like `100`, `100mbps` or `1 Gbps` as whole mbps; unknown units yield `None`.
+pub(crate) fn parse_mbps(value: &str) -> Option<u32> {
+    let value = value.trim();
+    let digits = value
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(value.len());
+    let number = value[..digits].parse::<u32>().ok()?;
+    let unit = value[digits..].trim().to_ascii_lowercase();
+    match unit.as_str() {
+        "" | "m" | "mb" | "mbps" | "mbit" | "mbit/s" => Some(number),
+        "g" | "gb" | "gbps" | "gbit" | "gbit/s" => number.checked_mul(1000),
+        _ => None,
+    }
+}
+
+fn split_csv(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|part| part.trim().to_string())
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+pub(crate) fn tls_from_params(params: &HashMap<String, String>, fallback_sni: Option<String>) -> Option<Value> {
+    let security = params
+        .get("security")
+        .or_else(|| params.get("tls"))
+        .map(|value| value.to_lowercase())
+        .unwrap_or_default();
+
+    if security.is_empty() || security == "none" {
+        return None;
+    }
+
+    let mut tls = json!({
+        "enabled": true
+    });
+
+    if let Some(sni) = params
+        .get("sni")
+        .cloned()
+        .or(fallback_sni)
+        .or_else(|| params.get("server_name").cloned())
+    {
+        tls["server_name"] = json!(sni);
+    }
+
+    let insecure = INSECURE_PARAM_KEYS
+        .iter()
+        .filter_map(|key| params.get(*key))
+        .any(|value| value == "1" || value.eq_ignore_ascii_case("true"));
+    if insecure {
+        tls["insecure"] = json!(true);
+    }
+
+    if let Some(alpn) = params.get("alpn") {
+        let list = split_csv(alpn);
+        if !list.is_empty() {
+            tls["alpn"] = json!(list);
+        }
+    }
+
+    if let Some(fp) = params
+        .get("fp")
+        .or_else(|| params.get("fingerprint"))
+        .map(|value| value.trim())
+    {
+        if !fp.is_empty() && !fp.eq_ignore_ascii_case("none") {
+            tls["utls"] = json!({
+                "enabled": true,
+                "fingerprint": fp
+            });
+        }
+    }
+
+    if security == "reality" {
+        let mut reality = json!({
+            "enabled": true
+        });
+        let mut has_reality = false;
+
+        for (field, keys, allow_empty) in REALITY_PARAM_FIELDS {
+            let value = keys.iter().find_map(|key| {
+                params
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                    .map(|(_, value)| value)
+            });
+            if let Some(value) = value.filter(|value| allow_empty || !value.is_empty()) {
+                reality[field] = json!(value);
+                has_reality = true;
+            }
+        }
+
+        if has_reality {
+            tls["reality"] = reality;
+        }
+    }
+
+    Some(tls)
+}
+
+pub(crate) fn quic_tls_from_params(
+    params: &HashMap<String, String>,
+    fallback_sni: Option<String>,
+) -> Option<Value> {
+    let mut tls = tls_from_params(params, fallback_sni)?;
+    if let Some(tls_obj) = tls.as_object_mut() {
+        tls_obj.remove("utls");
+    }
+    Some(tls)
+}
+
+pub(crate) fn build_transport(params: &HashMap<String, String>, network: &str) -> Option<Value> {
+    match network {
+        "ws" => {
+            let mut transport = json!({
+                "type": "ws"
+            });
+            if let Some(path) = params.get("path") {
+                transport["path"] = json!(path);
+            }
+            if let Some(host) = params.get("host") {
+                transport["headers"] = json!({ "Host": host });
+            }
+            if let Some(max_early_data) = params
+                .get("ed")
+                .and_then(|value| value.trim().parse::<u32>().ok())
+                .filter(|value| *value > 0)
+            {
+                transport["max_early_data"] = json!(max_early_data);
+                let header = params
+                    .get("eh")
+                    .map(|value| value.trim())
+                    .filter(|value| !value.is_empty())
+                    .unwrap_or("Sec-WebSocket-Protocol");
+                transport["early_data_header_name"] = json!(header);
+            }
+            Some(transport)
+        }
+        "http" | "h2" => {
+            let mut transport = json!({
+                "type": "http"
+            });
+            if let Some(host) = params.get("host") {
+                let hosts = split_csv(host);
+                if !hosts.is_empty() {
+                    transport["host"] = json!(hosts);
+                }
+            }
+            if let Some(path) = params.get("path") {
+                transport["path"] = json!(path);
+            }
+            Some(transport)
+        }
+        "httpupgrade" => {
+            let mut transport = json!({
+                "type": "httpupgrade"
+            });
+            if let Some(host) = params.get("host") {
+                transport["host"] = json!(host);
+            }
+            if let Some(path) = params.get("path") {
+                transport["path"] = json!(path);
+            }
+            Some(transport)
+        }
+        "grpc" => {
+            let mut transport = json!({
+                "type": "grpc"
+            });
+            if let Some(service) = params
+                .get("service_name")
+                .or_else(|| params.get("servicename"))
+                .or_else(|| params.get("path"))
+            {
+                transport["service_name"] = json!(service);
+            }
+            Some(transport)
+        }
+        "quic" => Some(json!({
+            "type": "quic"
+        })),
+        _ => None,
+    }
+}
+
+fn parse_ss_userinfo(value: &str) -> Result<(String, String), AppError> {
+    let decoded = if value.contains(':') {
+        value.to_string()
+    } else {
+        decode_base64_to_string(value)?
+    };
+    let (method, password) = decoded
+        .split_once(':')
+        .ok_or_else(|| err("IMPORT_INVALID", "missing method/password"))?;
+    Ok((method.to_string(), password.to_string()))
+}
+
+fn parse_ss_host_port(value: &str) -> Result<(String, u16), AppError> {
+    let trimmed = value.trim();
+    let host_port = trimmed
+        .split_once('/')
+        .map(|(head, _)| head)
+        .unwrap_or(trimmed);
+
+    if host_port.starts_with('[') {
+        let end = host_port
+            .find(']')
+            .ok_or_else(|| err("IMPORT_INVALID", "invalid ipv6 host"))?;
+        let host = &host_port[1..end];
+        let port_str = host_port[end + 1..]
+            .strip_prefix(':')
+            .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
+        let port = port_str
+            .parse::<u16>()
+            .map_err(|_| err("IMPORT_INVALID", "invalid port number"))?;
+        return Ok((host.to_string(), port));
+    }
+
+    let (host, port_str) = host_port
+        .rsplit_once(':')
+        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
+    if host.is_empty() {
+        return Err(err("IMPORT_INVALID", "missing server"));
+    }
+    let port = port_str
+        .parse::<u16>()
+        .map_err(|_| err("IMPORT_INVALID", "invalid port number"))?;
+    Ok((host.to_string(), port))
+}
+
+fn parse_ss_payload(value: &str) -> Result<(String, String, String, u16), AppError> {
+    if let Some(at_pos) = value.rfind('@') {
+        let (userinfo, hostpart) = value.split_at(at_pos);
+        let hostpart = &hostpart[1..];
+        let (method, password) = parse_ss_userinfo(userinfo)?;
+        let (server, port) = parse_ss_host_port(hostpart)?;
+        return Ok((method, password, server, port));
+    }
+
+    let decoded = decode_base64_to_string(value)?;
+    if let Some(at_pos) = decoded.rfind('@') {
+        let (userinfo, hostpart) = decoded.split_at(at_pos);
+        let hostpart = &hostpart[1..];
+        let (method, password) = parse_ss_userinfo(userinfo)?;
+        let (server, port) = parse_ss_host_port(hostpart)?;
+        return Ok((method, password, server, port));
+    }
+
+    Err(err("IMPORT_INVALID", "missing server"))
+}
+
+pub(crate) fn validate_ss_method(method: &str, password: &str) -> Result<(), AppError> {
+    if SS_AEAD_METHODS.contains(&method) {
+        return Ok(());
+    }
+    let Some((_, key_len)) = SS_2022_METHODS.iter().find(|(name, _)| *name == method) else {
+        return Err(err(
+            "IMPORT_INVALID",
+            format!("unsupported shadowsocks method: {method}"),
+        ));
+    };
+    for key in password.split(':') {
+        let decoded = STANDARD
+            .decode(key.trim())
+            .map_err(|_| err("IMPORT_INVALID", format!("{method} password must be base64")))?;
+        if decoded.len() != *key_len {
+            return Err(err(
+                "IMPORT_INVALID",
+                format!(
+                    "{method} password must be a base64 {key_len}-byte key, got {} bytes",
+                    decoded.len()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn parse_ss(link: &str) -> Result<Value, AppError> {
+    let raw = link.trim().trim_start_matches("ss://");
+    let (payload, fragment) = raw.split_once('#').unwrap_or((raw, ""));
+    let (payload, query) = payload.split_once('?').unwrap_or((payload, ""));
+    let (method, password, server, port) = parse_ss_payload(payload)?;
+    validate_ss_method(&method, &password)?;
+
+    let mut tag = decode_fragment(fragment);
+    let params = if query.is_empty() {
+        HashMap::new()
+    } else {
+        parse_ss_query(query)
+    };
+    if tag.is_empty() {
+        if let Some(name) = params.get("name") {
+            tag = name.to_string();
+        }
+    }
+    let tag = if tag.is_empty() {
+        format!("ss-{server}:{port}")
+    } else {
+        tag
+    };
+
+    let mut outbound = json!({
+        "type": "shadowsocks",
+        "tag": tag,
+        "server": server,
+        "server_port": port,
+        "method": method,
+        "password": password
+    });
+
+    if let Some(plugin) = params.get("plugin") {
+        let (plugin_name, opts) = plugin.split_once(';').unwrap_or((plugin, ""));
+        if !plugin_name.is_empty() {
+            outbound["plugin"] = json!(plugin_name);
+        }
+        let opts = if plugin_name == "v2ray-plugin" {
+            normalize_v2ray_plugin_opts(opts)?
+        } else {
+            let opts: Vec<&str> = opts.split(';').filter(|item| !item.is_empty()).collect();
+            opts.join(";")
+        };
+        if !opts.is_empty() {
+            outbound["plugin_opts"] = json!(opts);
+        }
+    }
+
+    if let Some(uot) = params
+        .get("udp-over-tcp")
+        .or_else(|| params.get("uot"))
+        .and_then(|value| udp_over_tcp_value(value))
+    {
+        outbound["udp_over_tcp"] = uot;
+    }
+
+    Ok(outbound)
+}
+
+pub(crate) fn legacy_vmess_warning(outbound: &Value) -> Option<String> {
+    if outbound.get("type").and_then(Value::as_str) != Some("vmess") {
+        return None;
+    }
+    let alter_id = outbound.get("alter_id").and_then(Value::as_u64)?;
+    (alter_id > 0).then(|| {
+        format!(
+            "{}: legacy VMess (alterId {alter_id}), consider switching the server to AEAD (alterId 0)",
+            outbound.get("tag").and_then(Value::as_str).unwrap_or("vmess")
+        )
+    })
+}
+
+fn parse_vmess(link: &str) -> Result<Value, AppError> {
+    let encoded = link.trim().trim_start_matches("vmess://");
+    let decoded = decode_base64_to_string(encoded)?;
+    let raw: Value =
+        serde_json::from_str(&decoded).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
+    let obj = raw
+        .as_object()
+        .ok_or_else(|| err("IMPORT_INVALID", "invalid vmess json"))?;
+
+    let server = obj
+        .get("add")
+        .and_then(Value::as_str)
+        .ok_or_else(|| err("IMPORT_INVALID", "missing server"))?;
+    let port = obj
+        .get("port")
+        .and_then(|value| {
+            value
+                .as_str()
+                .and_then(|s| s.parse::<u16>().ok())
+                .or_else(|| value.as_u64().map(|v| v as u16))
+        })
+        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
+    let uuid = obj
+        .get("id")
+        .and_then(Value::as_str)
+        .ok_or_else(|| err("IMPORT_INVALID", "missing uuid"))?;
+
+    let mut params: HashMap<String, String> = HashMap::new();
+    for key in ["net", "type", "host", "path", "tls", "sni", "alpn", "fp"] {
+        if let Some(value) = obj.get(key).and_then(Value::as_str) {
+            params.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    let ps = obj.get("ps").and_then(Value::as_str).unwrap_or("");
+    let tag = if ps.trim().is_empty() {
+        format!("vmess-{server}:{port}")
+    } else {
+        ps.to_string()
+    };
+
+    let mut outbound = json!({
+        "type": "vmess",
+        "tag": tag,
+        "server": server,
+        "server_port": port,
+        "uuid": uuid
+    });
+
+    let security = obj
+        .get("scy")
+        .and_then(Value::as_str)
+        .or_else(|| obj.get("security").and_then(Value::as_str))
+        .filter(|security| !security.trim().is_empty())
+        .unwrap_or("auto");
+    outbound["security"] = json!(security);
+
+    if let Some(alter_id) = obj
+        .get("aid")
+        .and_then(|value| {
+            value
+                .as_str()
+                .and_then(|s| s.parse::<u32>().ok())
+                .or_else(|| value.as_u64().map(|v| v as u32))
+        })
+    {
+        outbound["alter_id"] = json!(alter_id);
+    }
+
+    let network = params
+        .get("net")
+        .cloned()
+        .unwrap_or_else(|| "tcp".to_string());
+    if let Some(transport) = build_transport(&params, network.as_str()) {
+        outbound["transport"] = transport;
+    }
+
+    let mut tls_params = params.clone();
+    if !tls_params.contains_key("security") && !tls_params.contains_key("tls") {
+        tls_params.insert("security".to_string(), "tls".to_string());
+    }
+    if let Some(tls) = tls_from_params(&tls_params, Some(server.to_string())) {
+        outbound["tls"] = tls;
+    }
+
+    Ok(outbound)
+}
+
+fn parse_vless(link: &str) -> Result<Value, AppError> {
+    let url = Url::parse(link).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
+    let uuid = url.username();
+    if uuid.is_empty() {
+        return Err(err("IMPORT_INVALID", "missing uuid"));
+    }
+    let server = url
+        .host_str()
+        .ok_or_else(|| err("IMPORT_INVALID", "missing server"))?;
+    let port = url
+        .port()
+        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
+    let tag = decode_fragment(url.fragment().unwrap_or(""));
+    let tag = if tag.is_empty() {
+        format!("vless-{server}:{port}")
+    } else {
+        tag
+    };
+    let params = query_map(&url);
+
+    let mut outbound = json!({
+        "type": "vless",
+        "tag": tag,
+        "server": server,
+        "server_port": port,
+        "uuid": uuid
+    });
+
+    if let Some(flow) = params.get("flow") {
+        outbound["flow"] = json!(flow);
+    }
+
+    let network = params
+        .get("type")
+        .cloned()
+        .unwrap_or_else(|| "tcp".to_string());
+    if let Some(transport) = build_transport(&params, network.as_str()) {
+        outbound["transport"] = transport;
+    }
+
+    let mut tls_params = params.clone();
+    if !tls_params.contains_key("security") && !tls_params.contains_key("tls") {
+        tls_params.insert("security".to_string(), "tls".to_string());
+    }
+    if let Some(tls) = tls_from_params(&tls_params, Some(server.to_string())) {
+        outbound["tls"] = tls;
+    }
+
+    Ok(outbound)
+}
+
+fn parse_trojan(link: &str) -> Result<Value, AppError> {
+    let url = Url::parse(link).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
+    let server = url
+        .host_str()
+        .ok_or_else(|| err("IMPORT_INVALID", "missing server"))?;
+    let port = url
+        .port()
+        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
+    let mut password = url.username().to_string();
+    if password.is_empty() {
+        if let Some(pass) = url.password() {
+            password = pass.to_string();
+        }
+    } else if let Some(pass) = url.password() {
+        password = format!("{password}:{pass}");
+    }
+
+    if password.is_empty() {
+        return Err(err("IMPORT_INVALID", "missing password"));
+    }
+    let tag = decode_fragment(url.fragment().unwrap_or(""));
+    let tag = if tag.is_empty() {
+        format!("trojan-{server}:{port}")
+    } else {
+        tag
+    };
+    let params = query_map(&url);
+
+    let mut outbound = json!({
+        "type": "trojan",
+        "tag": tag,
+        "server": server,
+        "server_port": port,
+        "password": password
+    });
+
+    let network = params
+        .get("type")
+        .cloned()
+        .unwrap_or_else(|| "tcp".to_string());
+    if let Some(transport) = build_transport(&params, network.as_str()) {
+        outbound["transport"] = transport;
+    }
+
+    if let Some(tls) = tls_from_params(&params, Some(server.to_string())) {
+        outbound["tls"] = tls;
+    }
+
+    Ok(outbound)
+}
+
+fn parse_anytls(link: &str) -> Result<Value, AppError> {
+    let url = Url::parse(link).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
+    let server = url
+        .host_str()
+        .ok_or_else(|| err("IMPORT_INVALID", "missing server"))?;
+    let port = url
+        .port()
+        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
+    let mut password = percent_decode_str(url.username())
+        .decode_utf8_lossy()
+        .into_owned();
+    if let Some(pass) = url.password() {
+        password = format!("{password}:{}", percent_decode_str(pass).decode_utf8_lossy());
+    }
+    if password.is_empty() {
+        return Err(err("IMPORT_INVALID", "missing password"));
+    }
+    let tag = decode_fragment(url.fragment().unwrap_or(""));
+    let tag = if tag.is_empty() {
+        format!("anytls-{server}:{port}")
+    } else {
+        tag
+    };
+    let params = query_map(&url);
+
+    let mut outbound = json!({
+        "type": "anytls",
+        "tag": tag,
+        "server": server,
+        "server_port": port,
+        "password": password
+    });
+
+    let mut tls_params = params.clone();
+    if !tls_params.contains_key("security") && !tls_params.contains_key("tls") {
+        tls_params.insert("security".to_string(), "tls".to_string());
+    }
+    if let Some(tls) = tls_from_params(&tls_params, Some(server.to_string())) {
+        outbound["tls"] = tls;
+    }
+
+    Ok(outbound)
+}
+
+fn parse_hysteria(link: &str) -> Result<Value, AppError> {
+    let url = Url::parse(link).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
+    let server = url
+        .host_str()
+        .ok_or_else(|| err("IMPORT_INVALID", "missing server"))?;
+    let port = url
+        .port()
+        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
+    let params = query_map(&url);
+    let tag = decode_fragment(url.fragment().unwrap_or(""));
+    let tag = if tag.is_empty() {
+        format!("hysteria-{server}:{port}")
+    } else {
+        tag
+    };
+
+    let mut outbound = json!({
+        "type": "hysteria",
+        "tag": tag,
+        "server": server,
+        "server_port": port
+    });
+
+    if let Some(auth) = params.get("auth").or_else(|| params.get("auth_str")) {
+        outbound["auth_str"] = json!(auth);
+    }
+    if let Some(obfs) = params.get("obfs") {
+        outbound["obfs"] = json!(obfs);
+    }
+    if let Some(up) = params
+        .get("upmbps")
+        .or_else(|| params.get("up"))
+        .and_then(|value| parse_mbps(value))
+    {
+        outbound["up_mbps"] = json!(up);
+    }
+    if let Some(down) = params
+        .get("downmbps")
+        .or_else(|| params.get("down"))
+        .and_then(|value| parse_mbps(value))
+    {
+        outbound["down_mbps"] = json!(down);
+    }
+
+    let mut tls_params = params.clone();
+    if !tls_params.contains_key("security") {
+        tls_params.insert("security".to_string(), "tls".to_string());
+    }
+    if let Some(peer) = params.get("peer") {
+        tls_params.insert("sni".to_string(), peer.to_string());
+    }
+    if let Some(tls) = quic_tls_from_params(&tls_params, Some(server.to_string())) {
+        outbound["tls"] = tls;
+    }
+
+    Ok(outbound)
+}
+
+fn parse_hysteria2(link: &str) -> Result<Value, AppError> {
+    let url = Url::parse(link).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
+    let server = url
+        .host_str()
+        .ok_or_else(|| err("IMPORT_INVALID", "missing server"))?;
+    let port = url
+        .port()
+        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
+    let password = url.username();
+    if password.is_empty() {
+        return Err(err("IMPORT_INVALID", "missing password"));
+    }
+    let params = query_map(&url);
+    let tag = decode_fragment(url.fragment().unwrap_or(""));
+    let tag = if tag.is_empty() {
+        format!("hysteria2-{server}:{port}")
+    } else {
+        tag
+    };
+
+    let mut outbound = json!({
+        "type": "hysteria2",
+        "tag": tag,
+        "server": server,
+        "server_port": port,
+        "password": password
+    });
+
+    if let Some(obfs) = params.get("obfs") {
+        if obfs == "salamander" {
+            let mut obfs_obj = json!({
+                "type": "salamander"
+            });
+            if let Some(password) = params
+                .get("obfs-password")
+                .or_else(|| params.get("obfs_password"))
+            {
+                obfs_obj["password"] = json!(password);
+            }
+            outbound["obfs"] = obfs_obj;
+        }
+    }
+
+    if let Some(up) = params
+        .get("upmbps")
+        .or_else(|| params.get("up"))
+        .and_then(|value| parse_mbps(value))
+    {
+        outbound["up_mbps"] = json!(up);
+    }
+    if let Some(down) = params
+        .get("downmbps")
+        .or_else(|| params.get("down"))
+        .and_then(|value| parse_mbps(value))
+    {
+        outbound["down_mbps"] = json!(down);
+    }
+
+    let mut tls_params = params.clone();
+    if !tls_params.contains_key("security") {
+        tls_params.insert("security".to_string(), "tls".to_string());
+    }
+    if let Some(tls) = quic_tls_from_params(&tls_params, Some(server.to_string())) {
+        outbound["tls"] = tls;
+    }
+
+    Ok(outbound)
+}
+
+fn parse_tuic(link: &str) -> Result<Value, AppError> {
+    let url = Url::parse(link).map_err(|e| err("IMPORT_INVALID", e.to_string()))?;
+    let server = url
+        .host_str()
+        .ok_or_else(|| err("IMPORT_INVALID", "missing server"))?;
+    let port = url
+        .port()
+        .ok_or_else(|| err("IMPORT_INVALID", "missing port"))?;
+    let mut uuid = url.username().to_string();
+    let mut password = url.password().unwrap_or("").to_string();
+    if password.is_empty() {
+        if let Some((left, right)) = uuid.split_once(':') {
+            let left = left.to_string();
+            let right = right.to_string();
+            uuid = left;
+            password = right;
+        }
+    }
+    let params = query_map(&url);
+    let version = params
+        .get("version")
+        .or_else(|| params.get("v"))
+        .map(|value| value.trim().trim_start_matches(['v', 'V']).to_string());
+    let is_v4 = match version.as_deref() {
+        Some("4") => true,
+        Some("5") => false,
+        _ => password.is_empty(),
+    };
+    if is_v4 {
+        // sing-box only speaks TUIC v5; v4 token links cannot be imported.
+        return Err(err(
+            "IMPORT_UNSUPPORTED",
+            "tuic v4 links are not supported by sing-box",
+        ));
+    }
+    if uuid.is_empty() || password.is_empty() {
+        return Err(err("IMPORT_INVALID", "missing uuid/password"));
+    }
+    let tag = decode_fragment(url.fragment().unwrap_or(""));
+    let tag = if tag.is_empty() {
+        format!("tuic-{server}:{port}")
+    } else {
+        tag
+    };
+
+    let mut outbound = json!({
+        "type": "tuic",
+        "tag": tag,
+        "server": server,
+        "server_port": port,
+        "uuid": uuid,
+        "password": password
+    });
+
+    if let Some(congestion) = params.get("congestion_control") {
+        outbound["congestion_control"] = json!(congestion);
+    }
+    if let Some(udp_mode) = params.get("udp_relay_mode") {
+        outbound["udp_relay_mode"] = json!(udp_mode);
+    }
+
+    let mut tls_params = params.clone();
+    if !tls_params.contains_key("security") {
+        tls_params.insert("security".to_string(), "tls".to_string());
+    }
+    if let Some(tls) = quic_tls_from_params(&tls_params, Some(server.to_string())) {
+        outbound["tls"] = tls;
+    }
+
+    Ok(outbound)
+}
+
+pub(crate) fn parse_share_link(link: &str) -> Result<Value, AppError> {
+    let trimmed = link.trim();
+    if trimmed.starts_with("ss://") {
+        return parse_ss(trimmed);
+    }
+    if trimmed.starts_with("vmess://") {
+        return parse_vmess(trimmed);
+    }
+    if trimmed.starts_with("vless://") {
+        return parse_vless(trimmed);
+    }
+    if trimmed.starts_with("trojan://") {
+        return parse_trojan(trimmed);
+    }
+    if trimmed.starts_with("hysteria2://") || trimmed.starts_with("hy2://") {
+        return parse_hysteria2(trimmed);
+    }
+    if trimmed.starts_with("hysteria://") {
+        return parse_hysteria(trimmed);
+    }
+    if trimmed.starts_with("tuic://") {
+        return parse_tuic(trimmed);
+    }
+    if trimmed.starts_with("anytls://") {
+        return parse_anytls(trimmed);
+    }
+    Err(err("IMPORT_UNSUPPORTED", "unsupported share link"))
+}
+
+fn decode_subscription(input: &str) -> Option<Vec<String>> {
+    let trimmed = input.trim();
+    if trimmed.is_empty() || trimmed.contains("://") {
+        return None;
+    }
+    let decoded = decode_base64_to_string(trimmed).ok()?;
+    let links: Vec<String> = decoded
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    if links.is_empty() || !links.iter().all(|line| line.contains("://")) {
+        return None;
+    }
+    Some(links)
+}
+
+pub(crate) fn expand_subscriptions(inputs: Vec<String>) -> Vec<String> {
+    inputs
+        .into_iter()
+        .flat_map(|input| decode_subscription(&input).unwrap_or_else(|| vec![input]))
+        .collect()
+}
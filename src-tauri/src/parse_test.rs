@@ -0,0 +1,376 @@
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use serde_json::{json, Value};
+
+use crate::parse::{expand_subscriptions, legacy_vmess_warning, parse_mbps, parse_share_link};
+
+fn parse(link: &str) -> Value {
+    parse_share_link(link).unwrap_or_else(|error| panic!("{link}: {error}"))
+}
+
+fn error_code(link: &str) -> String {
+    parse_share_link(link).expect_err(link).code
+}
+
+#[test]
+fn ss_sip002_with_base64_userinfo() {
+    let userinfo = URL_SAFE_NO_PAD.encode("aes-256-gcm:secret");
+    let outbound = parse(&format!("ss://{userinfo}@203.0.113.1:8388#Tokyo"));
+    assert_eq!(
+        outbound,
+        json!({
+            "type": "shadowsocks",
+            "tag": "Tokyo",
+            "server": "203.0.113.1",
+            "server_port": 8388,
+            "method": "aes-256-gcm",
+            "password": "secret"
+        })
+    );
+}
+
+#[test]
+fn ss_sip002_with_plugin_and_ipv6_host() {
+    let outbound = parse(
+        "ss://chacha20-ietf-poly1305:pass@[2001:db8::1]:443/?plugin=v2ray-plugin%3Bmode%3Dwebsocket%3Btls",
+    );
+    assert_eq!(outbound["server"], "2001:db8::1");
+    assert_eq!(outbound["server_port"], 443);
+    assert_eq!(outbound["tag"], "ss-2001:db8::1:443");
+    assert_eq!(outbound["plugin"], "v2ray-plugin");
+    assert_eq!(outbound["plugin_opts"], "mode=websocket;tls");
+}
+
+#[test]
+fn ss_v2ray_plugin_quic_mode_is_unsupported() {
+    assert_eq!(
+        error_code("ss://aes-256-gcm:pass@203.0.113.1:443/?plugin=v2ray-plugin;mode=quic"),
+        "IMPORT_UNSUPPORTED"
+    );
+}
+
+#[test]
+fn ss_legacy_fully_encoded_payload() {
+    let payload = STANDARD.encode("aes-128-gcm:pa@ss@198.51.100.7:8000");
+    let outbound = parse(&format!("ss://{payload}#Legacy"));
+    assert_eq!(outbound["method"], "aes-128-gcm");
+    assert_eq!(outbound["password"], "pa@ss");
+    assert_eq!(outbound["server"], "198.51.100.7");
+    assert_eq!(outbound["server_port"], 8000);
+    assert_eq!(outbound["tag"], "Legacy");
+}
+
+#[test]
+fn ss_uot_param_enables_udp_over_tcp() {
+    let link = |query: &str| {
+        parse(&format!(
+            "ss://aes-256-gcm:secret@203.0.113.1:8388/?{query}#UoT"
+        ))
+    };
+    assert_eq!(link("uot=1")["udp_over_tcp"], true);
+    assert_eq!(link("udp-over-tcp=true")["udp_over_tcp"], true);
+    assert_eq!(
+        link("uot=2")["udp_over_tcp"],
+        json!({ "enabled": true, "version": 2 })
+    );
+    assert!(link("uot=0").get("udp_over_tcp").is_none());
+}
+
+#[test]
+fn ss_rejects_unknown_methods_and_bad_2022_keys() {
+    assert_eq!(
+        error_code("ss://rc4-md5:pass@example.com:8388"),
+        "IMPORT_INVALID"
+    );
+    assert_eq!(
+        error_code("ss://2022-blake3-aes-128-gcm:short@example.com:8388"),
+        "IMPORT_INVALID"
+    );
+}
+
+#[test]
+fn vmess_base64_json() {
+    let body = json!({
+        "v": "2",
+        "ps": "Frankfurt",
+        "add": "vm.example.com",
+        "port": "443",
+        "id": "b831381d-6324-4d53-ad4f-8cda48b30811",
+        "aid": "0",
+        "net": "ws",
+        "host": "cdn.example.com",
+        "path": "/ws",
+        "tls": "tls",
+        "sni": "vm.example.com"
+    });
+    let outbound = parse(&format!("vmess://{}", STANDARD.encode(body.to_string())));
+    assert_eq!(outbound["type"], "vmess");
+    assert_eq!(outbound["tag"], "Frankfurt");
+    assert_eq!(outbound["server"], "vm.example.com");
+    assert_eq!(outbound["server_port"], 443);
+    assert_eq!(outbound["uuid"], "b831381d-6324-4d53-ad4f-8cda48b30811");
+    assert_eq!(outbound["security"], "auto");
+    assert_eq!(outbound["alter_id"], 0);
+    assert_eq!(
+        outbound["transport"],
+        json!({ "type": "ws", "path": "/ws", "headers": { "Host": "cdn.example.com" } })
+    );
+    assert_eq!(outbound["tls"]["enabled"], true);
+    assert_eq!(outbound["tls"]["server_name"], "vm.example.com");
+}
+
+#[test]
+fn legacy_vmess_alter_id_warns_but_imports() {
+    let body = json!({
+        "ps": "Legacy",
+        "add": "vm.example.com",
+        "port": 443,
+        "id": "b831381d-6324-4d53-ad4f-8cda48b30811",
+        "aid": 64
+    });
+    let outbound = parse(&format!("vmess://{}", STANDARD.encode(body.to_string())));
+    assert_eq!(outbound["alter_id"], 64);
+    assert_eq!(
+        legacy_vmess_warning(&outbound).as_deref(),
+        Some(
+            "Legacy: legacy VMess (alterId 64), consider switching the server to AEAD (alterId 0)"
+        )
+    );
+
+    let aead = parse(&format!(
+        "vmess://{}",
+        STANDARD.encode(
+            json!({ "add": "vm.example.com", "port": 443, "id": "x", "aid": 0 }).to_string()
+        )
+    ));
+    assert_eq!(legacy_vmess_warning(&aead), None);
+}
+
+#[test]
+fn vless_with_reality() {
+    let outbound = parse(
+        "vless://b831381d-6324-4d53-ad4f-8cda48b30811@203.0.113.5:443\
+         ?security=reality&sni=www.microsoft.com&fp=chrome&pbk=PUBLICKEY&sid=0123abcd\
+         &flow=xtls-rprx-vision&type=tcp#Reality",
+    );
+    assert_eq!(outbound["type"], "vless");
+    assert_eq!(outbound["tag"], "Reality");
+    assert_eq!(outbound["flow"], "xtls-rprx-vision");
+    assert!(outbound.get("transport").is_none());
+    assert_eq!(
+        outbound["tls"],
+        json!({
+            "enabled": true,
+            "server_name": "www.microsoft.com",
+            "utls": { "enabled": true, "fingerprint": "chrome" },
+            "reality": {
+                "enabled": true,
+                "public_key": "PUBLICKEY",
+                "short_id": "0123abcd"
+            }
+        })
+    );
+}
+
+#[test]
+fn vless_reality_keeps_spider_x_and_sni_equal_to_server() {
+    let outbound = parse(
+        "vless://b831381d-6324-4d53-ad4f-8cda48b30811@www.example.com:443\
+         ?security=reality&sni=www.example.com&pbk=PUBLICKEY&sid=ab&spx=%2Fsearch%3Fq%3D1#Spider",
+    );
+    assert_eq!(outbound["tls"]["server_name"], "www.example.com");
+    assert_eq!(
+        outbound["tls"]["reality"],
+        json!({
+            "enabled": true,
+            "public_key": "PUBLICKEY",
+            "short_id": "ab",
+            "spider_x": "/search?q=1"
+        })
+    );
+
+    let outbound = parse(
+        "vless://b831381d-6324-4d53-ad4f-8cda48b30811@www.example.com:443\
+         ?security=reality&pbk=PUBLICKEY&sid=ab&spx=#Empty",
+    );
+    assert!(outbound["tls"]["reality"].get("spider_x").is_none());
+}
+
+#[test]
+fn vless_reality_with_post_quantum_verify_key_imports() {
+    for key in ["pqv", "mldsa65Verify"] {
+        let outbound = parse(&format!(
+            "vless://b831381d-6324-4d53-ad4f-8cda48b30811@203.0.113.5:443\
+             ?security=reality&sni=www.microsoft.com&pbk=PUBLICKEY&sid=ab\
+             &{key}=TUxEU0E2NVZFUklGWUtFWQ#PQ"
+        ));
+        assert_eq!(outbound["tag"], "PQ");
+        // No sing-box field carries the key, so it is dropped rather than passed through.
+        assert_eq!(
+            outbound["tls"]["reality"],
+            json!({
+                "enabled": true,
+                "public_key": "PUBLICKEY",
+                "short_id": "ab"
+            })
+        );
+    }
+}
+
+#[test]
+fn vless_ws_early_data_params_become_transport_fields() {
+    let outbound = parse(
+        "vless://b831381d-6324-4d53-ad4f-8cda48b30811@203.0.113.5:443\
+         ?security=tls&type=ws&path=%2Fws&host=cdn.example.com&ed=2048&eh=X-Early-Data#WS",
+    );
+    assert_eq!(
+        outbound["transport"],
+        json!({
+            "type": "ws",
+            "path": "/ws",
+            "headers": { "Host": "cdn.example.com" },
+            "max_early_data": 2048,
+            "early_data_header_name": "X-Early-Data"
+        })
+    );
+
+    let outbound = parse(
+        "vless://b831381d-6324-4d53-ad4f-8cda48b30811@203.0.113.5:443\
+         ?security=tls&type=ws&ed=0&eh=X-Early#WS",
+    );
+    assert_eq!(outbound["transport"], json!({ "type": "ws" }));
+}
+
+#[test]
+fn trojan_with_grpc_transport() {
+    let outbound = parse(
+        "trojan://secret@tr.example.com:443?security=tls&type=grpc&serviceName=tunnel&allowInsecure=1#Trojan",
+    );
+    assert_eq!(outbound["type"], "trojan");
+    assert_eq!(outbound["password"], "secret");
+    assert_eq!(outbound["server"], "tr.example.com");
+    assert_eq!(
+        outbound["transport"],
+        json!({ "type": "grpc", "service_name": "tunnel" })
+    );
+    assert_eq!(outbound["tls"]["insecure"], true);
+    assert_eq!(outbound["tls"]["server_name"], "tr.example.com");
+}
+
+#[test]
+fn hy2_with_salamander_obfs() {
+    let outbound = parse(
+        "hy2://secret@hy.example.com:8443?obfs=salamander&obfs-password=mask&sni=real.example.com&up=50&down=1gbps&fp=chrome#HY2",
+    );
+    assert_eq!(outbound["type"], "hysteria2");
+    assert_eq!(outbound["tag"], "HY2");
+    assert_eq!(outbound["password"], "secret");
+    assert_eq!(
+        outbound["obfs"],
+        json!({ "type": "salamander", "password": "mask" })
+    );
+    assert_eq!(outbound["up_mbps"], 50);
+    assert_eq!(outbound["down_mbps"], 1000);
+    assert_eq!(outbound["tls"]["server_name"], "real.example.com");
+    assert!(outbound["tls"].get("utls").is_none());
+}
+
+#[test]
+fn tuic_v5_uuid_password() {
+    let outbound = parse(
+        "tuic://b831381d-6324-4d53-ad4f-8cda48b30811:pw@tuic.example.com:443\
+         ?congestion_control=bbr&udp_relay_mode=native&alpn=h3#TUIC",
+    );
+    assert_eq!(outbound["type"], "tuic");
+    assert_eq!(outbound["uuid"], "b831381d-6324-4d53-ad4f-8cda48b30811");
+    assert_eq!(outbound["password"], "pw");
+    assert_eq!(outbound["congestion_control"], "bbr");
+    assert_eq!(outbound["udp_relay_mode"], "native");
+    assert_eq!(outbound["tls"]["alpn"], json!(["h3"]));
+}
+
+#[test]
+fn tuic_v4_token_is_unsupported() {
+    assert_eq!(
+        error_code("tuic://token@tuic.example.com:443"),
+        "IMPORT_UNSUPPORTED"
+    );
+    assert_eq!(
+        error_code("tuic://uuid:pw@tuic.example.com:443?version=4"),
+        "IMPORT_UNSUPPORTED"
+    );
+}
+
+#[test]
+fn anytls_defaults_to_tls() {
+    let outbound = parse("anytls://pa%3Ass@any.example.com:443?sni=front.example.com#AnyTLS");
+    assert_eq!(outbound["type"], "anytls");
+    assert_eq!(outbound["password"], "pa:ss");
+    assert_eq!(outbound["tls"]["enabled"], true);
+    assert_eq!(outbound["tls"]["server_name"], "front.example.com");
+}
+
+#[test]
+fn percent_encoded_fragments_become_tags() {
+    let outbound = parse("trojan://pw@tr.example.com:443#%F0%9F%87%A9%F0%9F%87%AA%20Berlin+01");
+    assert_eq!(outbound["tag"], "🇩🇪 Berlin 01");
+    let userinfo = URL_SAFE_NO_PAD.encode("aes-256-gcm:secret");
+    let outbound = parse(&format!(
+        "ss://{userinfo}@203.0.113.1:8388#%D0%9C%D0%BE%D1%81%D0%BA%D0%B2%D0%B0"
+    ));
+    assert_eq!(outbound["tag"], "Москва");
+}
+
+#[test]
+fn unknown_schemes_are_unsupported() {
+    assert_eq!(
+        error_code("wireguard://key@example.com:51820"),
+        "IMPORT_UNSUPPORTED"
+    );
+}
+
+#[test]
+fn subscription_blob_expands_into_links() {
+    let links = "vless://id@a.example.com:443#A\ntrojan://pw@b.example.com:443#B\n";
+    let expanded = expand_subscriptions(vec![
+        STANDARD.encode(links),
+        "hy2://pw@c.example.com:443".to_string(),
+    ]);
+    assert_eq!(
+        expanded,
+        vec![
+            "vless://id@a.example.com:443#A",
+            "trojan://pw@b.example.com:443#B",
+            "hy2://pw@c.example.com:443",
+        ]
+    );
+}
+
+#[test]
+fn non_subscription_input_is_kept() {
+    // A lone vmess link is base64 after the scheme but must not be
+    // mistaken for a whole subscription body.
+    let body = json!({ "ps": "Frankfurt", "add": "vm.example.com", "port": "443" });
+    let link = format!("vmess://{}", STANDARD.encode(body.to_string()));
+    assert_eq!(expand_subscriptions(vec![link.clone()]), vec![link]);
+
+    let expanded = expand_subscriptions(vec!["not a subscription".to_string()]);
+    assert_eq!(expanded, vec!["not a subscription"]);
+}
+
+#[test]
+fn mbps_accepts_plain_and_unit_suffixed_values() {
+    assert_eq!(parse_mbps("100"), Some(100));
+    assert_eq!(parse_mbps(" 50 Mbps "), Some(50));
+    assert_eq!(parse_mbps("20mbit/s"), Some(20));
+    assert_eq!(parse_mbps("1gbps"), Some(1000));
+    assert_eq!(parse_mbps("2 G"), Some(2000));
+}
+
+#[test]
+fn mbps_rejects_unknown_units_and_overflow() {
+    assert_eq!(parse_mbps(""), None);
+    assert_eq!(parse_mbps("fast"), None);
+    assert_eq!(parse_mbps("100kbps"), None);
+    assert_eq!(parse_mbps("5000000gbps"), None);
+}
@@ -0,0 +1,217 @@
+use std::fs;
+use std::net::TcpListener;
+use std::time::Duration;
+
+use crate::test_util::scratch_dir;
+use crate::{
+    controller_listen_addr, ensure_port_free, is_bundled_singbox, is_system_path,
+    netstat_listener_pid, probe_port, proc_net_listener_inodes, read_pid_lock, store_pid_lock,
+    PidLock, ProcessEntry, ProcessSort, BIN_DIR, PID_FILE, SINGBOX_EXE,
+};
+
+#[test]
+fn windows_system_dirs_are_hidden() {
+    assert!(is_system_path(r"C:\Windows\System32\svchost.exe"));
+    assert!(is_system_path(r"d:\WINDOWS\explorer.exe"));
+    assert!(is_system_path(
+        r"C:\ProgramData\Microsoft\Windows Defender\MsMpEng.exe"
+    ));
+}
+
+#[test]
+fn unix_and_macos_system_dirs_are_hidden() {
+    assert!(is_system_path("/usr/lib/systemd/systemd-resolved"));
+    assert!(is_system_path("/usr/sbin/sshd"));
+    assert!(is_system_path("/System/Library/CoreServices/Finder.app"));
+    assert!(is_system_path("/snap/core22/current/usr/bin/snapd"));
+}
+
+#[test]
+fn user_apps_are_kept() {
+    assert!(!is_system_path(
+        r"C:\Program Files\Telegram Desktop\Telegram.exe"
+    ));
+    assert!(!is_system_path(
+        r"C:\Users\me\AppData\Local\Discord\Discord.exe"
+    ));
+    assert!(!is_system_path(r"C:\Games\Windows Tools\tool.exe"));
+    assert!(!is_system_path("/usr/bin/firefox"));
+    assert!(!is_system_path("/Applications/Telegram.app"));
+    assert!(!is_system_path(""));
+}
+
+fn entry(name: &str, path: &str, cpu: f32, memory: u64) -> ProcessEntry {
+    ProcessEntry {
+        name: name.to_string(),
+        path: path.to_string(),
+        count: 1,
+        pids: vec![1],
+        cpu,
+        memory,
+    }
+}
+
+fn sorted(sort: ProcessSort, mut entries: Vec<ProcessEntry>) -> Vec<String> {
+    entries.sort_by(|a, b| sort.compare(a, b));
+    entries.into_iter().map(|entry| entry.path).collect()
+}
+
+#[test]
+fn name_sort_ignores_case_and_breaks_ties_by_path() {
+    let entries = vec![
+        entry("zoom.exe", "C:/z/zoom.exe", 0.0, 0),
+        entry("Chrome.exe", "D:/chrome.exe", 0.0, 0),
+        entry("chrome.exe", "C:/chrome.exe", 0.0, 0),
+    ];
+    assert_eq!(
+        sorted(ProcessSort::Name, entries),
+        ["C:/chrome.exe", "D:/chrome.exe", "C:/z/zoom.exe"]
+    );
+}
+
+#[test]
+fn cpu_and_memory_sort_descending_then_by_name() {
+    let entries = vec![
+        entry("b.exe", "b", 5.0, 100),
+        entry("a.exe", "a", 5.0, 300),
+        entry("c.exe", "c", 50.0, 200),
+    ];
+    assert_eq!(sorted(ProcessSort::Cpu, entries.clone()), ["c", "a", "b"]);
+    assert_eq!(sorted(ProcessSort::Memory, entries), ["a", "c", "b"]);
+}
+
+#[test]
+fn only_the_copied_binary_counts_as_bundled() {
+    let dir = scratch_dir("bundled");
+    let bin = dir.join(BIN_DIR);
+    let other = dir.join("usr-local");
+    fs::create_dir_all(&bin).unwrap();
+    fs::create_dir_all(&other).unwrap();
+    let bundled = bin.join(SINGBOX_EXE);
+    fs::write(&bundled, b"").unwrap();
+    fs::write(other.join(SINGBOX_EXE), b"").unwrap();
+
+    assert!(is_bundled_singbox(&bundled, &bundled));
+    assert!(is_bundled_singbox(
+        &bin.join("..").join(BIN_DIR).join(SINGBOX_EXE),
+        &bundled
+    ));
+    assert!(!is_bundled_singbox(&other.join(SINGBOX_EXE), &bundled));
+    assert!(!is_bundled_singbox(&dir.join(SINGBOX_EXE), &bundled));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(target_os = "windows")]
+#[test]
+fn bundled_binary_match_ignores_case_on_windows() {
+    let bundled = PathBuf::from(r"C:\Users\me\AppData\Roaming\yotsuba\bin\sing-box.exe");
+    let running = PathBuf::from(r"c:\users\ME\appdata\roaming\Yotsuba\BIN\SING-BOX.EXE");
+    assert!(is_bundled_singbox(&running, &bundled));
+}
+
+#[test]
+fn pid_lock_round_trips_through_the_file() {
+    let dir = scratch_dir("pid-lock");
+    let path = dir.join(PID_FILE);
+    store_pid_lock(
+        &path,
+        &PidLock {
+            pid: 4242,
+            started_at: 1_700_000_000,
+        },
+    );
+    let lock = read_pid_lock(&path).expect("pid lock");
+    assert_eq!((lock.pid, lock.started_at), (4242, 1_700_000_000));
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        r#"{"pid":4242,"startedAt":1700000000}"#
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn missing_or_malformed_pid_lock_is_ignored() {
+    let dir = scratch_dir("pid-lock-bad");
+    let path = dir.join(PID_FILE);
+    assert!(read_pid_lock(&path).is_none());
+    fs::write(&path, "4242").unwrap();
+    assert!(read_pid_lock(&path).is_none());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn bound_port_is_reported_as_in_use() {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let error = ensure_port_free("127.0.0.1", port).unwrap_err();
+    assert_eq!(error.code, "PORT_IN_USE");
+    assert!(error.detail.contains(&format!("port {port}")));
+    if cfg!(target_os = "linux") {
+        assert!(
+            error
+                .detail
+                .contains(&format!("pid {}", std::process::id())),
+            "{}",
+            error.detail
+        );
+    }
+    drop(listener);
+    assert!(ensure_port_free("127.0.0.1", port).is_ok());
+}
+
+#[test]
+fn profile_clash_controller_port_is_checked() {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (host, parsed) = controller_listen_addr(&format!("127.0.0.1:{port}")).unwrap();
+    assert_eq!(parsed, port);
+    let error = ensure_port_free(&host, parsed).unwrap_err();
+    assert_eq!(error.code, "PORT_IN_USE");
+    assert!(error.detail.contains(&format!("port {port}")));
+
+    assert_eq!(
+        controller_listen_addr(":9090"),
+        Some(("0.0.0.0".to_string(), 9090))
+    );
+    assert_eq!(
+        controller_listen_addr("[::1]:9090"),
+        Some(("::1".to_string(), 9090))
+    );
+    assert_eq!(controller_listen_addr("127.0.0.1"), None);
+}
+
+#[test]
+fn netstat_rows_name_the_listening_pid() {
+    let output = "\r
+Active Connections\r
+\r
+  Proto  Local Address          Foreign Address        State           PID\r
+  TCP    127.0.0.1:2080         127.0.0.1:51000        ESTABLISHED     900\r
+  TCP    127.0.0.1:2080         0.0.0.0:0              ПРОСЛУШИВАНИЕ   4242\r
+  TCP    [::]:9090              [::]:0                 LISTENING       77\r
+";
+    assert_eq!(netstat_listener_pid(output, 2080), Some(4242));
+    assert_eq!(netstat_listener_pid(output, 9090), Some(77));
+    assert_eq!(netstat_listener_pid(output, 51000), None);
+}
+
+#[test]
+fn proc_net_rows_give_listening_inodes() {
+    let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:0820 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 31337 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:0820 0100007F:C738 01 00000000:00000000 00:00000000 00000000  1000        0 31338 1 0000000000000000 20 4 30 10 -1
+   2: 00000000:2382 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 4242 1 0000000000000000 100 0 0 10 0
+";
+    assert_eq!(proc_net_listener_inodes(table, 2080), [31337]);
+    assert_eq!(proc_net_listener_inodes(table, 9090), [4242]);
+    assert!(proc_net_listener_inodes(table, 443).is_empty());
+}
+
+#[test]
+fn probe_succeeds_only_while_something_listens() {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    assert!(probe_port(addr, Duration::from_millis(200)));
+    drop(listener);
+    assert!(!probe_port(addr, Duration::from_millis(200)));
+}
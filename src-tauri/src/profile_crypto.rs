@@ -0,0 +1,72 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::{err, AppError, PROFILE_ENCRYPTED_PREFIX};
+
+const KEYRING_SERVICE: &str = "YotsubaCore";
+const KEYRING_USER: &str = "profile-key";
+const NONCE_LEN: usize = 12;
+
+fn load_or_create_key() -> Result<Key<Aes256Gcm>, AppError> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)
+        .map_err(|e| err("KEYRING_ERROR", e.to_string()))?;
+    match entry.get_password() {
+        Ok(encoded) => {
+            let bytes = STANDARD
+                .decode(encoded.trim())
+                .map_err(|e| err("KEYRING_ERROR", e.to_string()))?;
+            if bytes.len() != 32 {
+                return Err(err("KEYRING_ERROR", "stored profile key has wrong length"));
+            }
+            Ok(*Key::<Aes256Gcm>::from_slice(&bytes))
+        }
+        Err(keyring::Error::NoEntry) => {
+            let key = Aes256Gcm::generate_key(OsRng);
+            entry
+                .set_password(&STANDARD.encode(key))
+                .map_err(|e| err("KEYRING_ERROR", e.to_string()))?;
+            Ok(key)
+        }
+        Err(e) => Err(err("KEYRING_ERROR", e.to_string())),
+    }
+}
+
+pub(crate) fn encrypt_with_key(key: &Key<Aes256Gcm>, plaintext: &str) -> Result<String, AppError> {
+    let cipher = Aes256Gcm::new(key);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|e| err("PROFILE_ENCRYPT_FAILED", e.to_string()))?;
+    let mut payload = nonce.to_vec();
+    payload.extend(ciphertext);
+    Ok(format!("{PROFILE_ENCRYPTED_PREFIX}{}", STANDARD.encode(payload)))
+}
+
+pub(crate) fn decrypt_with_key(key: &Key<Aes256Gcm>, content: &str) -> Result<String, AppError> {
+    let encoded = content
+        .trim()
+        .strip_prefix(PROFILE_ENCRYPTED_PREFIX)
+        .ok_or_else(|| err("PROFILE_DECRYPT_FAILED", "missing encryption header"))?;
+    let payload = STANDARD
+        .decode(encoded)
+        .map_err(|e| err("PROFILE_DECRYPT_FAILED", e.to_string()))?;
+    if payload.len() <= NONCE_LEN {
+        return Err(err("PROFILE_DECRYPT_FAILED", "encrypted payload is truncated"));
+    }
+    let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(key);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|e| err("PROFILE_DECRYPT_FAILED", e.to_string()))?;
+    String::from_utf8(plaintext).map_err(|e| err("PROFILE_DECRYPT_FAILED", e.to_string()))
+}
+
+pub(crate) fn encrypt(plaintext: &str) -> Result<String, AppError> {
+    encrypt_with_key(&load_or_create_key()?, plaintext)
+}
+
+pub(crate) fn decrypt(content: &str) -> Result<String, AppError> {
+    decrypt_with_key(&load_or_create_key()?, content)
+}
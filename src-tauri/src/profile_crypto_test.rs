@@ -0,0 +1,49 @@
+use aes_gcm::aead::{KeyInit, OsRng};
+use aes_gcm::Aes256Gcm;
+
+use crate::profile_crypto::{decrypt_with_key, encrypt_with_key};
+use crate::PROFILE_ENCRYPTED_PREFIX;
+
+#[test]
+fn encrypted_profile_round_trips() {
+    let key = Aes256Gcm::generate_key(OsRng);
+    let profile = r#"{"outbounds":[{"type":"vless","tag":"Москва"}]}"#;
+    let encrypted = encrypt_with_key(&key, profile).unwrap();
+    assert!(encrypted.starts_with(PROFILE_ENCRYPTED_PREFIX));
+    assert!(!encrypted.contains("vless"));
+    assert_eq!(decrypt_with_key(&key, &encrypted).unwrap(), profile);
+    assert_eq!(
+        decrypt_with_key(&key, &format!("{encrypted}\n")).unwrap(),
+        profile
+    );
+}
+
+#[test]
+fn each_encryption_uses_a_fresh_nonce() {
+    let key = Aes256Gcm::generate_key(OsRng);
+    assert_ne!(
+        encrypt_with_key(&key, "{}").unwrap(),
+        encrypt_with_key(&key, "{}").unwrap()
+    );
+}
+
+#[test]
+fn decrypt_rejects_wrong_keys_and_damaged_payloads() {
+    let key = Aes256Gcm::generate_key(OsRng);
+    let encrypted = encrypt_with_key(&key, "{}").unwrap();
+    let other = Aes256Gcm::generate_key(OsRng);
+    for content in [
+        "{}".to_string(),
+        format!("{PROFILE_ENCRYPTED_PREFIX}not base64!"),
+        format!("{PROFILE_ENCRYPTED_PREFIX}AAAA"),
+    ] {
+        assert_eq!(
+            decrypt_with_key(&key, &content).unwrap_err().code,
+            "PROFILE_DECRYPT_FAILED"
+        );
+    }
+    assert_eq!(
+        decrypt_with_key(&other, &encrypted).unwrap_err().code,
+        "PROFILE_DECRYPT_FAILED"
+    );
+}
@@ -0,0 +1,585 @@
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::test_util::scratch_dir;
+use crate::{
+    clear_errors, copy_profile, default_profile, dry_run_in, is_backup_name, migrate_state,
+    prune_backups, read_backup_group, read_profile_file, read_state_file, reset_data_dir,
+    resume_mode, status_of, stop_runtime, validate_profile_shape, write_file_atomic, AppRule,
+    AppRuleMode, AppState, ComposedConfig, ProfileExport, ProfileGroups, ProfileState, ProxyMode,
+    ProxyState, ProxyStatus, RulePreset, SelectorChange, SharedState, StateFile, APP_STATE_FILE,
+    BACKUP_META_FILE, CONFIG_FILE, CRASH_LOOP_COOLDOWN, CRASH_LOOP_LIMIT, DEFAULT_PROFILE_GROUP,
+    DEFAULT_RULE_PRESET, DRY_RUN_CONFIG_PREFIX, FAST_EXIT_WINDOW, PROFILE_ENCRYPTED_PREFIX,
+    PROFILE_EXPORT_VERSION, PROFILE_FILE, PROFILE_GROUPS_DIR, PROFILE_GROUPS_FILE,
+    PROFILE_STATE_FILE, RULE_SET_DIR, TEMPLATE_FILE,
+};
+
+fn fast_exit(state: &mut ProxyState, now: Instant) -> Instant {
+    let exited = now + FAST_EXIT_WINDOW / 2;
+    state.started_at = Some(now);
+    state.record_exit(exited);
+    exited
+}
+
+#[test]
+fn fast_exits_trigger_a_cooldown_at_the_limit() {
+    let now = Instant::now();
+    let mut state = ProxyState::default();
+    for _ in 1..CRASH_LOOP_LIMIT {
+        fast_exit(&mut state, now);
+        assert_eq!(state.crash_loop_remaining(now), None);
+    }
+    let exited = fast_exit(&mut state, now);
+    assert_eq!(state.fast_exits, CRASH_LOOP_LIMIT);
+    assert_eq!(
+        state.crash_loop_remaining(exited),
+        Some(CRASH_LOOP_COOLDOWN)
+    );
+    let later = exited + Duration::from_secs(10);
+    assert_eq!(
+        state.crash_loop_remaining(later),
+        Some(CRASH_LOOP_COOLDOWN - Duration::from_secs(10))
+    );
+}
+
+#[test]
+fn long_run_resets_the_counter() {
+    let now = Instant::now();
+    let mut state = ProxyState::default();
+    fast_exit(&mut state, now);
+    fast_exit(&mut state, now);
+    state.started_at = Some(now);
+    state.record_exit(now + FAST_EXIT_WINDOW * 2);
+    assert_eq!(state.fast_exits, 0);
+    assert_eq!(state.cooldown_until, None);
+}
+
+#[test]
+fn exit_without_start_is_ignored() {
+    let now = Instant::now();
+    let mut state = ProxyState::default();
+    state.record_exit(now);
+    assert_eq!(state.fast_exits, 0);
+}
+
+#[test]
+fn cooldown_expires_and_clears_the_counter() {
+    let now = Instant::now();
+    let mut state = ProxyState::default();
+    for _ in 0..CRASH_LOOP_LIMIT {
+        fast_exit(&mut state, now);
+    }
+    let later = now + FAST_EXIT_WINDOW + CRASH_LOOP_COOLDOWN;
+    assert_eq!(state.crash_loop_remaining(later), None);
+    assert_eq!(state.fast_exits, 0);
+    assert_eq!(state.cooldown_until, None);
+}
+
+#[test]
+fn prune_backups_keeps_the_newest() {
+    let names: Vec<String> = ["0003", "0002", "0001"]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+    assert_eq!(prune_backups(&names, 2), ["0001".to_string()]);
+    assert!(prune_backups(&names, 3).is_empty());
+    assert!(prune_backups(&names, 10).is_empty());
+    assert_eq!(prune_backups(&names, 0), names.as_slice());
+}
+
+#[test]
+fn backup_names_are_timestamps() {
+    assert!(is_backup_name("0001712345678901"));
+    assert!(!is_backup_name(""));
+    assert!(!is_backup_name("profiles"));
+    assert!(!is_backup_name("../0001"));
+}
+
+#[test]
+fn backup_group_is_read_from_metadata() {
+    let dir = std::env::temp_dir().join(format!("yotsuba-backup-meta-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    assert_eq!(read_backup_group(&dir), None);
+    fs::write(dir.join(BACKUP_META_FILE), r#"{ "group": "work" }"#).unwrap();
+    assert_eq!(read_backup_group(&dir).as_deref(), Some("work"));
+    fs::write(dir.join(BACKUP_META_FILE), r#"{ "group": "../escape" }"#).unwrap();
+    assert_eq!(read_backup_group(&dir), None);
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn backed_up_profiles_stay_encrypted() {
+    let dir = scratch_dir("backup-profile-copy");
+    let plain = dir.join("plain.json");
+    fs::write(&plain, r#"{"outbounds":[]}"#).unwrap();
+    let copied = dir.join("copied.json");
+    copy_profile(&plain, &copied, false).unwrap();
+    assert_eq!(fs::read_to_string(&copied).unwrap(), r#"{"outbounds":[]}"#);
+
+    // Already encrypted copies are kept as they are instead of being encrypted twice.
+    let sealed = dir.join("sealed.json");
+    let content = format!("{PROFILE_ENCRYPTED_PREFIX}AAAA");
+    fs::write(&sealed, &content).unwrap();
+    copy_profile(&sealed, &copied, true).unwrap();
+    assert_eq!(fs::read_to_string(&copied).unwrap(), content);
+    copy_profile(&copied, &copied, true).unwrap();
+    assert_eq!(fs::read_to_string(&copied).unwrap(), content);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn migrate_state_moves_app_rules_into_a_preset() {
+    let rules = json!([{ "path": "telegram.exe", "mode": "proxy" }]);
+    let migrated = migrate_state(json!({ "appRules": rules }), StateFile::App);
+    assert_eq!(migrated["schemaVersion"], 2);
+    assert_eq!(
+        migrated["rulePresets"],
+        json!([{ "name": DEFAULT_RULE_PRESET, "rules": rules }])
+    );
+    assert_eq!(migrated["activePreset"], DEFAULT_RULE_PRESET);
+}
+
+#[test]
+fn migrate_state_keeps_existing_presets() {
+    let state = json!({
+        "schemaVersion": 1,
+        "appRules": [],
+        "rulePresets": [{ "name": "work", "rules": [] }],
+        "activePreset": "work"
+    });
+    let migrated = migrate_state(state, StateFile::App);
+    assert_eq!(migrated["schemaVersion"], 2);
+    assert_eq!(
+        migrated["rulePresets"],
+        json!([{ "name": "work", "rules": [] }])
+    );
+    assert_eq!(migrated["activePreset"], "work");
+}
+
+#[test]
+fn migrate_state_leaves_current_and_foreign_values_alone() {
+    let current = json!({ "schema_version": 2, "appRules": [] });
+    assert_eq!(migrate_state(current.clone(), StateFile::Profile), current);
+    assert_eq!(migrate_state(json!([1, 2]), StateFile::App), json!([1, 2]));
+    assert_eq!(
+        migrate_state(json!({}), StateFile::App),
+        json!({ "schemaVersion": 2 })
+    );
+}
+
+#[test]
+fn app_rule_migration_only_touches_the_app_state() {
+    let legacy = json!({ "appRules": [] });
+    let profile = migrate_state(legacy.clone(), StateFile::Profile);
+    assert_eq!(profile, json!({ "appRules": [], "schema_version": 2 }));
+    let profile_state = migrate_state(legacy, StateFile::ProfileState);
+    assert_eq!(profile_state, json!({ "appRules": [], "schemaVersion": 2 }));
+}
+
+#[test]
+fn per_mode_tag_wins_and_falls_back_to_the_active_tag() {
+    let state = ProfileState {
+        active_tag: Some("nl".to_string()),
+        active_tag_by_mode: HashMap::from([(ProxyMode::Full, "de".to_string())]),
+        ..ProfileState::default()
+    };
+    assert_eq!(state.active_tag_for(ProxyMode::Full).as_deref(), Some("de"));
+    assert_eq!(
+        state.active_tag_for(ProxyMode::Selected).as_deref(),
+        Some("nl")
+    );
+    assert_eq!(
+        ProfileState::default().active_tag_for(ProxyMode::Full),
+        None
+    );
+
+    // State files from before per-mode tags only have the shared one.
+    let legacy: ProfileState = serde_json::from_value(json!({ "activeTag": "nl" })).unwrap();
+    assert!(legacy.active_tag_by_mode.is_empty());
+    assert_eq!(
+        legacy.active_tag_for(ProxyMode::Selected).as_deref(),
+        Some("nl")
+    );
+}
+
+#[test]
+fn profile_export_round_trips_per_mode_tags() {
+    let export = ProfileExport {
+        version: PROFILE_EXPORT_VERSION,
+        profile: json!({ "outbounds": [{ "type": "direct", "tag": "nl" }] }),
+        active_tag: Some("nl".to_string()),
+        active_tag_by_mode: HashMap::from([
+            (ProxyMode::Full, "nl".to_string()),
+            (ProxyMode::Selected, "de".to_string()),
+        ]),
+        app_rules: Vec::new(),
+    };
+    let payload = serde_json::to_string(&export).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&payload).unwrap();
+    assert_eq!(
+        value["activeTagByMode"],
+        json!({ "full": "nl", "selected": "de" })
+    );
+
+    let imported: ProfileExport = serde_json::from_str(&payload).unwrap();
+    assert_eq!(imported.version, PROFILE_EXPORT_VERSION);
+    assert_eq!(imported.profile, export.profile);
+    assert_eq!(imported.active_tag.as_deref(), Some("nl"));
+    assert_eq!(imported.active_tag_by_mode, export.active_tag_by_mode);
+}
+
+#[test]
+fn older_profile_exports_have_no_per_mode_tags() {
+    let imported: ProfileExport = serde_json::from_value(json!({
+        "version": 1,
+        "profile": { "outbounds": [] },
+        "activeTag": null,
+        "appRules": [{ "path": "telegram.exe", "mode": "proxy", "name": null }]
+    }))
+    .unwrap();
+    assert!(imported.active_tag_by_mode.is_empty());
+    assert_eq!(imported.app_rules.len(), 1);
+}
+
+#[test]
+fn stored_rules_follow_the_active_preset() {
+    let mut state = AppState {
+        active_preset: "work".to_string(),
+        rule_presets: vec![
+            RulePreset {
+                name: DEFAULT_RULE_PRESET.to_string(),
+                rules: Vec::new(),
+            },
+            RulePreset {
+                name: "work".to_string(),
+                rules: Vec::new(),
+            },
+        ],
+        ..AppState::default()
+    };
+    let rules = vec![AppRule {
+        path: "slack.exe".to_string(),
+        mode: AppRuleMode::Proxy,
+        name: None,
+    }];
+    state.store_rules(rules);
+    assert_eq!(state.active_rules().len(), 1);
+    assert!(state.rule_presets[0].rules.is_empty());
+    assert_eq!(state.rule_presets[1].rules[0].path, "slack.exe");
+}
+
+#[test]
+fn reset_leaves_the_default_profile_and_no_state() {
+    let data = scratch_dir("reset-data");
+    let backup = scratch_dir("reset-backup");
+    fs::write(data.join(PROFILE_FILE), r#"{ "outbounds": [] }"#).unwrap();
+    fs::write(data.join(APP_STATE_FILE), r#"{ "lastMode": "full" }"#).unwrap();
+    fs::write(data.join(PROFILE_STATE_FILE), r#"{ "activeTag": "nl" }"#).unwrap();
+    fs::write(data.join(PROFILE_GROUPS_FILE), r#"{ "active": "work" }"#).unwrap();
+    fs::write(data.join(TEMPLATE_FILE), "{}").unwrap();
+    fs::create_dir_all(data.join(PROFILE_GROUPS_DIR)).unwrap();
+    fs::create_dir_all(data.join(RULE_SET_DIR)).unwrap();
+    fs::write(data.join(RULE_SET_DIR).join("geoip-ru.srs"), b"srs").unwrap();
+
+    reset_data_dir(&data, &backup).unwrap();
+
+    let mut profile = read_profile_file(&data.join(PROFILE_FILE)).unwrap();
+    profile.as_object_mut().unwrap().remove("schema_version");
+    assert_eq!(profile, default_profile());
+    assert!(!data.join(APP_STATE_FILE).exists());
+    assert!(!data.join(PROFILE_STATE_FILE).exists());
+    assert!(!data.join(RULE_SET_DIR).exists());
+    for name in [PROFILE_GROUPS_FILE, PROFILE_GROUPS_DIR, TEMPLATE_FILE] {
+        assert!(!data.join(name).exists(), "{name}");
+        assert!(backup.join(name).exists(), "{name}");
+    }
+    let _ = fs::remove_dir_all(&data);
+    let _ = fs::remove_dir_all(&backup);
+}
+
+#[test]
+fn created_groups_can_be_switched_to_and_back() {
+    let mut groups = ProfileGroups::default();
+    groups.add("Work".to_string()).unwrap();
+    assert_eq!(
+        groups.add("work".to_string()).unwrap_err().code,
+        "GROUP_EXISTS"
+    );
+    assert_eq!(groups.groups, [DEFAULT_PROFILE_GROUP, "Work"]);
+
+    let previous = groups.switch_to("Work".to_string()).unwrap();
+    assert_eq!(previous.as_deref(), Some(DEFAULT_PROFILE_GROUP));
+    assert_eq!(groups.active, "Work");
+    assert_eq!(groups.switch_to("Work".to_string()).unwrap(), None);
+    assert_eq!(
+        groups.switch_to("Home".to_string()).unwrap_err().code,
+        "GROUP_MISSING"
+    );
+
+    // A failed restart rolls back by switching to the returned group.
+    groups.switch_to(previous.unwrap()).unwrap();
+    assert_eq!(groups.active, DEFAULT_PROFILE_GROUP);
+}
+
+#[test]
+fn the_active_and_default_groups_cannot_be_deleted() {
+    let mut groups = ProfileGroups::default();
+    groups.add("Work".to_string()).unwrap();
+    groups.switch_to("Work".to_string()).unwrap();
+    assert_eq!(groups.remove("Work").unwrap_err().code, "GROUP_ACTIVE");
+    assert_eq!(
+        groups.remove(DEFAULT_PROFILE_GROUP).unwrap_err().code,
+        "GROUP_INVALID"
+    );
+
+    groups.switch_to(DEFAULT_PROFILE_GROUP.to_string()).unwrap();
+    groups.remove("Work").unwrap();
+    assert_eq!(groups.groups, [DEFAULT_PROFILE_GROUP]);
+    assert_eq!(groups.remove("Work").unwrap_err().code, "GROUP_MISSING");
+}
+
+#[cfg(unix)]
+#[test]
+fn dry_run_leaves_the_running_child_and_saved_files_alone() {
+    let dir = scratch_dir("dry-run");
+    fs::write(dir.join(CONFIG_FILE), "running").unwrap();
+    fs::write(dir.join(APP_STATE_FILE), "saved").unwrap();
+    let state: SharedState = Arc::new(Mutex::new(ProxyState {
+        child: Some(Command::new("sleep").arg("30").spawn().unwrap()),
+        mode: ProxyMode::Full,
+        ..ProxyState::default()
+    }));
+    let (pid, token) = {
+        let guard = state.lock().unwrap();
+        (
+            guard.child.as_ref().map(|child| child.id()),
+            guard.watch_token,
+        )
+    };
+    let rules = vec![
+        AppRule {
+            path: "steam.exe".to_string(),
+            mode: AppRuleMode::Proxy,
+            name: None,
+        },
+        AppRule {
+            path: "steam.exe".to_string(),
+            mode: AppRuleMode::Direct,
+            name: None,
+        },
+    ];
+    let candidate = |exe: &'static str| {
+        move |_rules: Vec<AppRule>| {
+            Ok((
+                ComposedConfig {
+                    config: json!({ "outbounds": [] }),
+                    selector_change: SelectorChange::ProxyRenamed,
+                    stale_tag: None,
+                },
+                PathBuf::from(exe),
+            ))
+        }
+    };
+    let live = |guard: &mut ProxyState| ProxyStatus {
+        running: guard.child.is_some(),
+        mode: guard.mode,
+        pid: guard.child.as_ref().map(|child| child.id()),
+        ..ProxyStatus::default()
+    };
+
+    let status = dry_run_in(
+        &state,
+        &dir,
+        ProxyMode::Selected,
+        rules.clone(),
+        candidate("true"),
+        live,
+    )
+    .unwrap();
+    assert!(status.running);
+    assert_eq!(status.pid, pid);
+    assert_eq!(status.mode, ProxyMode::Selected);
+    assert_eq!(status.selector_change, Some(SelectorChange::ProxyRenamed));
+    assert_eq!(status.rule_conflicts, ["steam.exe"]);
+
+    let Err(error) = dry_run_in(
+        &state,
+        &dir,
+        ProxyMode::Selected,
+        rules,
+        candidate("false"),
+        live,
+    ) else {
+        panic!("a failing check must fail the dry run");
+    };
+    assert_eq!(error.code, "CONFIG_INVALID");
+
+    let mut guard = state.lock().unwrap();
+    assert_eq!(guard.mode, ProxyMode::Full);
+    assert_eq!(guard.watch_token, token);
+    let child = guard.child.as_mut().unwrap();
+    assert_eq!(child.try_wait().unwrap(), None);
+    assert_eq!(Some(child.id()), pid);
+    assert_eq!(
+        fs::read_to_string(dir.join(CONFIG_FILE)).unwrap(),
+        "running"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join(APP_STATE_FILE)).unwrap(),
+        "saved"
+    );
+    assert!(!fs::read_dir(&dir).unwrap().any(|entry| entry
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .starts_with(DRY_RUN_CONFIG_PREFIX)));
+
+    let _ = child.kill();
+    let _ = child.wait();
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn corrupt_state_file_is_moved_aside_and_defaults_load() {
+    let dir = scratch_dir("corrupt-state");
+    let path = dir.join(APP_STATE_FILE);
+    fs::write(&path, "{ not json").unwrap();
+
+    let (state, recovered): (AppState, _) = read_state_file(&path, StateFile::App);
+    assert_eq!(state.last_mode, AppState::default().last_mode);
+    assert!(state.active_rules().is_empty());
+    let recovered = recovered.expect("corrupt file is reported");
+    assert_eq!(recovered.file, path.display().to_string());
+    let prefix = format!("{APP_STATE_FILE}.corrupt.");
+    let backup = PathBuf::from(&recovered.backup);
+    let suffix = backup
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix(&prefix))
+        .expect("backup keeps the file name");
+    assert!(!suffix.is_empty() && suffix.chars().all(|c| c.is_ascii_digit()));
+    assert_eq!(fs::read_to_string(&backup).unwrap(), "{ not json");
+    assert!(!path.exists());
+
+    // Nothing to recover once the file is gone.
+    let (_, recovered): (AppState, _) = read_state_file(&path, StateFile::App);
+    assert!(recovered.is_none());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn empty_state_file_is_not_quarantined() {
+    let dir = scratch_dir("empty-state");
+    let path = dir.join(APP_STATE_FILE);
+    fs::write(&path, "").unwrap();
+
+    let (state, recovered): (AppState, _) = read_state_file(&path, StateFile::App);
+    assert!(recovered.is_none());
+    assert!(state.active_rules().is_empty());
+    assert!(path.exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn atomic_write_replaces_the_file_without_leftovers() {
+    let dir = scratch_dir("atomic-write");
+    let path = dir.join(APP_STATE_FILE);
+    fs::write(&path, "old").unwrap();
+
+    write_file_atomic(&path, "new").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    let names: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, vec![APP_STATE_FILE]);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn stopping_keeps_the_saved_mode_to_resume() {
+    let mut saved = AppState {
+        last_mode: ProxyMode::Full,
+        ..AppState::default()
+    };
+    let mut runtime = ProxyState {
+        mode: ProxyMode::Full,
+        last_error: Some("exited".to_string()),
+        ..ProxyState::default()
+    };
+    let mut killed = false;
+    // `stop_proxy` only takes the runtime down; the saved state is not part of it.
+    stop_runtime(&mut runtime, |_| killed = true);
+    assert!(killed);
+    assert_eq!(runtime.mode, ProxyMode::Off);
+    assert_eq!(runtime.last_error, None);
+    assert_eq!(resume_mode(&saved), Ok(ProxyMode::Full));
+
+    // `set_mode(Off)` records the request, which leaves nothing to resume.
+    saved.remember_request(ProxyMode::Off, Vec::new(), true);
+    assert_eq!(resume_mode(&saved).unwrap_err().code, "MODE_OFF");
+    saved.remember_request(ProxyMode::Selected, Vec::new(), true);
+    assert_eq!(resume_mode(&saved), Ok(ProxyMode::Selected));
+}
+
+#[test]
+fn malformed_profile_shapes_are_rejected() {
+    let rejected = |profile: serde_json::Value| {
+        let error = validate_profile_shape(&profile).unwrap_err();
+        (error.code, error.detail)
+    };
+    assert_eq!(
+        rejected(json!(["outbounds"])),
+        (
+            "PROFILE_INVALID".to_string(),
+            "root must be an object, found array".to_string()
+        )
+    );
+    assert_eq!(
+        rejected(json!({ "outbounds": { "tag": "proxy" } })),
+        (
+            "PROFILE_OUTBOUNDS_INVALID".to_string(),
+            "outbounds must be an array, found object".to_string()
+        )
+    );
+    assert_eq!(
+        rejected(json!({ "outbounds": [{ "type": "direct", "tag": "direct" }, "proxy"] })),
+        (
+            "PROFILE_OUTBOUNDS_INVALID".to_string(),
+            "outbounds[1] must be an object, found string".to_string()
+        )
+    );
+    assert_eq!(validate_profile_shape(&json!({})), Ok(()));
+    assert_eq!(validate_profile_shape(&default_profile()), Ok(()));
+}
+
+#[test]
+fn cleared_errors_stay_cleared_in_the_status() {
+    let mut child = Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap();
+    child.wait().unwrap();
+    let mut state = ProxyState {
+        child: Some(child),
+        mode: ProxyMode::Full,
+        last_error: Some("sing-box exited".to_string()),
+        ..ProxyState::default()
+    };
+    clear_errors(&mut state);
+    let status = status_of(&mut state);
+    assert!(!status.running);
+    assert_eq!(status.mode, ProxyMode::Off);
+    assert_eq!(status.last_exit, None);
+    assert_eq!(status.last_error, None);
+
+    state.last_exit = Some(1);
+    state.last_error = Some("again".to_string());
+    clear_errors(&mut state);
+    let status = status_of(&mut state);
+    assert_eq!(status.last_exit, None);
+    assert_eq!(status.last_error, None);
+}
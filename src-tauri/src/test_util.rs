@@ -0,0 +1,10 @@
+use std::fs;
+use std::path::PathBuf;
+
+/// Fresh per-process temp directory for a test; any leftovers from an earlier run are removed.
+pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("yotsuba-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("scratch dir");
+    dir
+}
@@ -0,0 +1,92 @@
+use notify_debouncer_mini::notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebouncedEvent, DebouncedEventKind};
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::test_util::scratch_dir;
+use crate::{changed_profile_content, ProfileWatch, PROFILE_FILE};
+
+fn event(path: &Path) -> DebouncedEvent {
+    DebouncedEvent {
+        path: path.to_path_buf(),
+        kind: DebouncedEventKind::Any,
+    }
+}
+
+#[test]
+fn own_writes_are_ignored_and_external_edits_reported() {
+    let dir = scratch_dir("watch-own");
+    let profile = dir.join(PROFILE_FILE);
+    let watch = ProfileWatch::default();
+
+    fs::write(&profile, "{\"outbounds\":[]}").unwrap();
+    watch.remember_write("{\"outbounds\":[]}".to_string());
+    assert_eq!(
+        changed_profile_content(&watch, &[event(&profile)], &profile),
+        None
+    );
+
+    fs::write(&profile, "{\"outbounds\":[1]}").unwrap();
+    assert_eq!(
+        changed_profile_content(&watch, &[event(&profile)], &profile).as_deref(),
+        Some("{\"outbounds\":[1]}")
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn events_for_other_files_are_ignored() {
+    let dir = scratch_dir("watch-other");
+    let profile = dir.join(PROFILE_FILE);
+    fs::write(&profile, "{}").unwrap();
+    let watch = ProfileWatch::default();
+
+    let log = dir.join("sing-box.log");
+    let nested = dir.join("groups").join(PROFILE_FILE);
+    assert_eq!(
+        changed_profile_content(&watch, &[event(&log), event(&nested)], &profile),
+        None
+    );
+    assert!(changed_profile_content(&watch, &[event(&log), event(&profile)], &profile).is_some());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn rapid_replacing_saves_arrive_as_one_batch() {
+    let dir = scratch_dir("watch-debounce");
+    let profile = dir.join(PROFILE_FILE);
+    fs::write(&profile, "{}").unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let mut debouncer = new_debouncer(Duration::from_millis(200), move |result| {
+        let _ = tx.send(result);
+    })
+    .unwrap();
+    debouncer
+        .watcher()
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .unwrap();
+
+    // Save the way editors do: write a sibling and rename it over the profile.
+    for index in 0..3 {
+        let staged = dir.join(format!("{PROFILE_FILE}.tmp"));
+        fs::write(&staged, format!("{{\"outbounds\":[{index}]}}")).unwrap();
+        fs::rename(&staged, &profile).unwrap();
+    }
+
+    let watch = ProfileWatch::default();
+    let mut reloads = Vec::new();
+    while let Ok(result) = rx.recv_timeout(Duration::from_secs(2)) {
+        let events = result.expect("watch events");
+        // The app remembers each reloaded profile, so a batch split by the
+        // debouncer does not reload the same content twice.
+        if let Some(content) = changed_profile_content(&watch, &events, &profile) {
+            watch.remember_write(content.clone());
+            reloads.push(content);
+        }
+    }
+    assert_eq!(reloads, ["{\"outbounds\":[2]}"]);
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,63 @@
+use crate::{clamp_window_geometry, window_should_hide, MonitorArea, WindowGeometry};
+
+fn geometry(x: i32, y: i32, width: u32, height: u32) -> WindowGeometry {
+    WindowGeometry {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+fn monitor(x: i32, y: i32, width: u32, height: u32) -> MonitorArea {
+    MonitorArea {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+#[test]
+fn visible_window_is_kept() {
+    let monitors = [monitor(0, 0, 1920, 1080), monitor(1920, 0, 2560, 1440)];
+    let saved = geometry(2000, 100, 1000, 700);
+    assert_eq!(clamp_window_geometry(saved, &monitors), saved);
+    let mostly_offscreen = geometry(1800, 1000, 1000, 700);
+    assert_eq!(
+        clamp_window_geometry(mostly_offscreen, &monitors),
+        mostly_offscreen
+    );
+}
+
+#[test]
+fn window_on_unplugged_monitor_is_centered_on_the_first() {
+    let monitors = [monitor(0, 0, 1920, 1080)];
+    assert_eq!(
+        clamp_window_geometry(geometry(2500, 200, 1000, 700), &monitors),
+        geometry(460, 190, 1000, 700)
+    );
+}
+
+#[test]
+fn barely_visible_window_is_moved_and_shrunk() {
+    let monitors = [monitor(-1280, 0, 1280, 720)];
+    assert_eq!(
+        clamp_window_geometry(geometry(-40, 0, 1600, 900), &monitors),
+        geometry(-1280, 0, 1280, 720)
+    );
+}
+
+#[test]
+fn no_monitors_keeps_the_geometry() {
+    let saved = geometry(10, 10, 800, 600);
+    assert_eq!(clamp_window_geometry(saved, &[]), saved);
+}
+
+#[test]
+fn only_a_focused_visible_window_is_hidden_by_the_hotkey() {
+    assert!(window_should_hide(true, false, true));
+    assert!(!window_should_hide(true, false, false));
+    assert!(!window_should_hide(true, true, true));
+    assert!(!window_should_hide(false, false, false));
+}
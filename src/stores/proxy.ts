@@ -1,14 +1,11 @@
 import { defineStore } from "pinia";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
-import {
-  disable as disableAutostart,
-  enable as enableAutostart,
-  isEnabled as isAutostartEnabled,
-} from "@tauri-apps/plugin-autostart";
 
 export type ProxyMode = "off" | "selected" | "full";
-export type AppRuleMode = "proxy" | "direct";
+export type AppRuleMode = "proxy" | "direct" | "exclude";
+export type FinalOutbound = "proxy" | "direct";
+export type SelectorChange = "noChange" | "proxyRenamed" | "selectorSynthesized";
 
 export interface AppRule {
   path: string;
@@ -21,8 +18,12 @@ export interface RunningProcess {
   path: string;
   count: number;
   pids: number[];
+  cpu: number;
+  memory: number;
 }
 
+export type ProcessSort = "name" | "cpu" | "memory";
+
 export interface AppListItem {
   name: string;
   path: string;
@@ -40,23 +41,169 @@ export interface ProxyStatus {
   configPath: string | null;
   profilePath: string;
   logPath: string | null;
+  listenAddress: string | null;
+  ipv6: boolean | null;
+  selectedFinal: FinalOutbound | null;
+  selectorChange: SelectorChange | null;
+  tunAddresses: string[];
+  ruleConflicts: string[];
+  singboxVersion: string | null;
+  testUrl: string | null;
+  testTimeoutMs: number | null;
+  logMaxBytes: number | null;
+  logKeepBytes: number | null;
+  hijackDns: boolean | null;
+  hijackDnsProxyOnly: boolean | null;
+  warnings: string[];
 }
 
 export interface ProfileData {
   outbounds: Record<string, unknown>[];
   activeTag: string | null;
+  activeTagByMode: Partial<Record<ProxyMode, string>>;
 }
 
 export interface ImportResult {
   profile: ProfileData;
   added: number;
+  addedTags: string[];
   errors: string[];
+  duplicates: string[];
+}
+
+export interface ConfigGenerated {
+  path: string;
+  config: Record<string, unknown> | null;
+}
+
+export interface StateRecovered {
+  file: string;
+  backup: string;
+}
+
+export interface ResetResult {
+  backup: string;
+  profile: ProfileData;
+}
+
+export interface LatencyResult {
+  tag: string;
+  delay: number | null;
+}
+
+export interface PingResult {
+  tag: string;
+  address: string;
+  sent: number;
+  received: number;
+  minMs: number | null;
+  avgMs: number | null;
+  maxMs: number | null;
+  lossPercent: number;
+}
+
+export interface ProbeResult {
+  tag: string;
+  ok: boolean;
+  latencyMs: number | null;
+  error: string | null;
+}
+
+export interface ModeValidation {
+  mode: ProxyMode;
+  ok: boolean;
+  error: AppError | null;
+}
+
+export interface OutboundGroup {
+  name: string;
+  tags: string[];
+}
+
+export interface TunnelInfo {
+  interfaceName: string;
+  addresses: string[];
+  autoRoute: boolean;
+  interfaceUp: boolean;
+  receivedBytes: number;
+  transmittedBytes: number;
+}
+
+export interface ImportProgress {
+  parsed: number;
+  total: number;
+  failed: number;
+}
+
+export interface DnsServer {
+  tag: string;
+  type: string;
+  server: string | null;
+  detour: string | null;
+}
+
+export interface ProxySettings {
+  allowLan: boolean;
+  mixedUsername: string | null;
+  mixedPassword: string | null;
+  mixedAuthRequired: boolean;
+  dnsServers: DnsServer[];
+  dnsStrategy: string | null;
+  dnsFinal: string | null;
+  directDns: string | null;
+  groupPattern: string | null;
+  fakeIp: boolean;
+  overrideRoute: boolean;
+  ipv6: boolean;
+  logLevel: "trace" | "debug" | "info" | "warn" | "error";
+  selectedFinal: FinalOutbound;
+  defaultFingerprint: string;
+  ssUdpOverTcp: boolean;
+  autostartDelaySecs: number;
+  startMinimized: boolean;
+  geoipRuUrl: string;
+  geoipUpdateInterval: string;
+  encryptProfile: boolean;
+  allowDirectSelection: boolean;
+  tunInet4Address: string;
+  tunInet6Address: string;
+  testUrl: string;
+  testTimeoutMs: number;
+  autoSelect: boolean;
+  urlTestInterval: string;
+  logMaxBytes: number;
+  logKeepBytes: number;
+  logRotateCount: number;
+  structuredLogs: boolean;
+  configEventJson: boolean;
+  tunEnabled: boolean;
+  hijackDns: boolean;
+  hijackDnsProxyOnly: boolean;
 }
 
 interface SavedState {
   lastMode: ProxyMode;
   appRules: AppRule[];
   forceIpv4Ru: boolean;
+  settings: ProxySettings;
+  rulePresets: RulePreset[];
+  activePreset: string;
+}
+
+export interface RulePreset {
+  name: string;
+  rules: AppRule[];
+}
+
+export interface RulePresets {
+  active: string;
+  presets: RulePreset[];
+}
+
+export interface PreviewResult {
+  input: string;
+  outbound: Record<string, unknown> | null;
+  error: AppError | null;
 }
 
 export interface ProfileItem {
@@ -67,16 +214,40 @@ export interface ProfileItem {
   raw: Record<string, unknown>;
 }
 
-interface ProxyExitPayload {
-  code: number | null;
+export interface AppError {
+  code: string;
+  detail: string;
 }
 
 interface LogPayload {
   lines: string[];
 }
 
+export type LogLevel = "trace" | "debug" | "info" | "warn" | "error" | "fatal";
+
+export interface LogEntry {
+  level: LogLevel | null;
+  ts: string | null;
+  msg: string;
+}
+
+interface LogEntryPayload {
+  entries: LogEntry[];
+}
+
 const LOG_LIMIT = 500;
 
+const isAppError = (value: unknown): value is AppError =>
+  typeof value === "object" &&
+  value !== null &&
+  typeof (value as AppError).code === "string" &&
+  typeof (value as AppError).detail === "string";
+
+const formatError = (err: unknown, fallback: string) => {
+  if (isAppError(err)) return `${err.code}|${err.detail}`;
+  return String(err ?? fallback);
+};
+
 const normalizePath = (value: string) => value.trim().replace(/^"|"$/g, "");
 
 const isProcessName = (value: string) => {
@@ -112,8 +283,14 @@ const toProfileItem = (raw: Record<string, unknown>): ProfileItem => {
 };
 
 let processTimer: number | null = null;
-let unlistenExit: (() => void) | null = null;
+let unlistenState: (() => void) | null = null;
 let unlistenLog: (() => void) | null = null;
+let unlistenLogEntries: (() => void) | null = null;
+let unlistenProfile: (() => void) | null = null;
+let unlistenImport: (() => void) | null = null;
+let unlistenLatency: (() => void) | null = null;
+let unlistenRecovered: (() => void) | null = null;
+let unlistenConfig: (() => void) | null = null;
 let applyTimer: number | null = null;
 
 const rulesSignature = (rules: AppRule[]) =>
@@ -129,6 +306,9 @@ export const useProxyStore = defineStore("proxy", {
     appRules: [] as AppRule[],
     processes: [] as RunningProcess[],
     logs: [] as string[],
+    logEntries: [] as LogEntry[],
+    stateRecoveries: [] as StateRecovered[],
+    generatedConfig: null as ConfigGenerated | null,
     profiles: [] as ProfileItem[],
     activeTag: null as string | null,
     status: {
@@ -146,6 +326,13 @@ export const useProxyStore = defineStore("proxy", {
     profileBusy: false,
     profileError: null as string | null,
     profileWarnings: [] as string[],
+    importProgress: null as ImportProgress | null,
+    latencies: {} as Record<string, number | null>,
+    processSort: "name" as ProcessSort,
+    rulePresets: [] as RulePreset[],
+    activePreset: "default",
+    processQuery: "",
+    showSystemProcesses: false,
     autostartEnabled: false,
     autostartBusy: false,
     autostartError: null as string | null,
@@ -234,9 +421,9 @@ export const useProxyStore = defineStore("proxy", {
       ]);
       this.snapshotAppliedState();
       this.startProcessPolling();
-      if (!unlistenExit) {
-        unlistenExit = await listen<ProxyExitPayload>("proxy-exited", () => {
-          this.refreshStatus();
+      if (!unlistenState) {
+        unlistenState = await listen<ProxyStatus>("proxy-state", (event) => {
+          this.applyStatus(event.payload);
         });
       }
       if (!unlistenLog) {
@@ -244,9 +431,58 @@ export const useProxyStore = defineStore("proxy", {
           this.appendLogs(event.payload.lines);
         });
       }
+      if (!unlistenLogEntries) {
+        unlistenLogEntries = await listen<LogEntryPayload>("proxy-log-structured", (event) => {
+          this.appendLogEntries(event.payload.entries);
+        });
+      }
+      if (!unlistenProfile) {
+        unlistenProfile = await listen<ProfileData>("profile-changed", (event) => {
+          this.activeTag = event.payload.activeTag;
+          this.profiles = (event.payload.outbounds ?? [])
+            .filter((item) => item && typeof item === "object")
+            .map((item) => toProfileItem(item));
+        });
+      }
+      if (!unlistenImport) {
+        unlistenImport = await listen<ImportProgress>("import-progress", (event) => {
+          this.importProgress = event.payload;
+        });
+      }
+      if (!unlistenLatency) {
+        unlistenLatency = await listen<LatencyResult>("latency-result", (event) => {
+          this.latencies = { ...this.latencies, [event.payload.tag]: event.payload.delay };
+        });
+      }
+      if (!unlistenRecovered) {
+        unlistenRecovered = await listen<StateRecovered>("state-recovered", (event) => {
+          this.stateRecoveries.push(event.payload);
+        });
+      }
+      if (!unlistenConfig) {
+        unlistenConfig = await listen<ConfigGenerated>("config-generated", (event) => {
+          this.generatedConfig = event.payload;
+        });
+      }
+      const recovered = await invoke<StateRecovered[]>("take_state_recoveries");
+      for (const item of recovered) {
+        if (!this.stateRecoveries.some((known) => known.backup === item.backup)) {
+          this.stateRecoveries.push(item);
+        }
+      }
+    },
+    dismissStateRecoveries() {
+      this.stateRecoveries = [];
+    },
+    async clearLastError() {
+      const status = await invoke<ProxyStatus>("clear_last_error");
+      this.applyStatus(status);
     },
     async refreshStatus() {
       const status = await invoke<ProxyStatus>("get_status");
+      this.applyStatus(status);
+    },
+    applyStatus(status: ProxyStatus) {
       this.status = status;
       if (!this.busy) {
         this.mode = status.mode;
@@ -256,20 +492,22 @@ export const useProxyStore = defineStore("proxy", {
       try {
         const saved = await invoke<SavedState>("get_saved_state");
         this.appRules = saved.appRules ?? [];
+        this.rulePresets = saved.rulePresets ?? [];
+        this.activePreset = saved.activePreset ?? "default";
         this.forceIpv4Ru = saved.forceIpv4Ru ?? true;
         if (!this.busy) {
           this.mode = saved.lastMode ?? "off";
         }
       } catch (err) {
-        this.error = String(err ?? "Не удалось загрузить сохранённые настройки.");
+        this.error = formatError(err, "Не удалось загрузить сохранённые настройки.");
       }
     },
     async refreshAutostart() {
       try {
-        this.autostartEnabled = await isAutostartEnabled();
+        this.autostartEnabled = await invoke<boolean>("get_autostart");
         this.autostartError = null;
       } catch (err) {
-        this.autostartError = String(err ?? "Не удалось проверить автозапуск.");
+        this.autostartError = formatError(err, "Не удалось проверить автозапуск.");
       }
     },
     async setAutostart(enabled: boolean) {
@@ -278,13 +516,9 @@ export const useProxyStore = defineStore("proxy", {
       this.autostartBusy = true;
       this.autostartError = null;
       try {
-        if (enabled) {
-          await enableAutostart();
-        } else {
-          await disableAutostart();
-        }
+        this.autostartEnabled = await invoke<boolean>("set_autostart", { enabled });
       } catch (err) {
-        this.autostartError = String(err ?? "Не удалось обновить автозапуск.");
+        this.autostartError = formatError(err, "Не удалось обновить автозапуск.");
       } finally {
         this.autostartBusy = false;
         await this.refreshAutostart();
@@ -292,9 +526,13 @@ export const useProxyStore = defineStore("proxy", {
     },
     async refreshProcesses() {
       try {
-        this.processes = await invoke<RunningProcess[]>("list_processes");
+        this.processes = await invoke<RunningProcess[]>("list_processes", {
+          sort: this.processSort,
+          query: this.processQuery || null,
+          includeSystem: this.showSystemProcesses,
+        });
       } catch (err) {
-        this.error = String(err ?? "Не удалось получить процессы.");
+        this.error = formatError(err, "Не удалось получить процессы.");
       }
     },
     startProcessPolling(intervalMs = 4000) {
@@ -323,8 +561,23 @@ export const useProxyStore = defineStore("proxy", {
         this.logs.splice(0, this.logs.length - LOG_LIMIT);
       }
     },
+    async setLogStreamPaused(paused: boolean) {
+      try {
+        await invoke(paused ? "pause_log_stream" : "resume_log_stream");
+      } catch (err) {
+        this.error = formatError(err, "Не удалось переключить поток логов.");
+      }
+    },
+    appendLogEntries(entries: LogEntry[]) {
+      if (!entries?.length) return;
+      this.logEntries.push(...entries);
+      if (this.logEntries.length > LOG_LIMIT) {
+        this.logEntries.splice(0, this.logEntries.length - LOG_LIMIT);
+      }
+    },
     clearLogs() {
       this.logs = [];
+      this.logEntries = [];
     },
     snapshotAppliedState() {
       this.lastAppliedMode = this.mode;
@@ -360,9 +613,81 @@ export const useProxyStore = defineStore("proxy", {
         const lines = await invoke<string[]>("read_log_tail", { limit });
         this.logs = lines.slice(-LOG_LIMIT);
       } catch (err) {
-        this.error = String(err ?? "Не удалось прочитать лог.");
+        this.error = formatError(err, "Не удалось прочитать лог.");
       }
     },
+    applyRulePresets(result: RulePresets) {
+      this.rulePresets = result.presets;
+      this.activePreset = result.active;
+      const active = result.presets.find((preset) => preset.name === result.active);
+      if (active) {
+        this.appRules = active.rules;
+      }
+    },
+    async createRulePreset(name: string, rules?: AppRule[]) {
+      this.applyRulePresets(
+        await invoke<RulePresets>("create_rule_preset", { name, rules: rules ?? null }),
+      );
+    },
+    async selectRulePreset(name: string) {
+      this.applyRulePresets(await invoke<RulePresets>("select_rule_preset", { name }));
+      this.snapshotAppliedState();
+      await this.refreshStatus();
+    },
+    async deleteRulePreset(name: string) {
+      this.applyRulePresets(await invoke<RulePresets>("delete_rule_preset", { name }));
+    },
+    async testLatency(tag: string) {
+      const delay = await invoke<number | null>("test_outbound_latency", { tag });
+      this.latencies = { ...this.latencies, [tag]: delay };
+      return delay;
+    },
+    async testAllLatency() {
+      this.latencies = {};
+      this.latencies = await invoke<Record<string, number | null>>("test_all_outbounds");
+    },
+    async getTemplate() {
+      return invoke<Record<string, unknown> | null>("get_template");
+    },
+    async setTemplate(template: Record<string, unknown> | null) {
+      await invoke("set_template", { template });
+    },
+    async resolveNodeCountry(tag: string) {
+      return invoke<string>("resolve_node_country", { tag });
+    },
+    async toggleMainWindow() {
+      await invoke("toggle_main_window");
+    },
+    async probeActiveNode() {
+      return invoke<ProbeResult>("probe_active_node");
+    },
+    async validateProfile() {
+      return invoke<ModeValidation[]>("validate_profile");
+    },
+    async groupOutbounds() {
+      return invoke<OutboundGroup[]>("group_outbounds");
+    },
+    async tcpPing(tag: string, attempts?: number) {
+      return invoke<PingResult>("tcp_ping", { tag, attempts });
+    },
+    async dryRunMode(mode: ProxyMode = this.mode) {
+      return invoke<ProxyStatus>("set_mode", {
+        mode,
+        appRules: this.appRules,
+        forceIpv4Ru: this.forceIpv4Ru,
+        dryRun: true,
+      });
+    },
+    async getTunnelInfo() {
+      return invoke<TunnelInfo>("get_tunnel_info");
+    },
+    async getEffectiveConfig(mode: ProxyMode = this.mode) {
+      return invoke<Record<string, unknown>>("get_effective_config", {
+        mode,
+        appRules: this.appRules,
+        forceIpv4Ru: this.forceIpv4Ru,
+      });
+    },
     setProxy(path: string, name?: string) {
       const normalized = normalizePath(path);
       if (!normalized) return;
@@ -376,6 +701,19 @@ export const useProxyStore = defineStore("proxy", {
       this.appRules.push({ path: normalized, mode: "proxy", name });
       this.scheduleApply();
     },
+    setExclude(path: string, name?: string) {
+      const normalized = normalizePath(path);
+      if (!normalized) return;
+      const existing = this.appRules.find((item) => item.path === normalized);
+      if (existing) {
+        existing.mode = "exclude";
+        if (name) existing.name = name;
+        this.scheduleApply();
+        return;
+      }
+      this.appRules.push({ path: normalized, mode: "exclude", name });
+      this.scheduleApply();
+    },
     setDirect(path: string) {
       const normalized = normalizePath(path);
       if (!normalized) return;
@@ -412,22 +750,23 @@ export const useProxyStore = defineStore("proxy", {
         this.lastAppliedRulesSignature = signature;
         this.lastAppliedForceIpv4Ru = this.forceIpv4Ru;
       } catch (err) {
-        const message = String(err ?? "");
-        if (message.startsWith("PROFILE_MISSING|")) {
-          const path = message.split("|")[1] ?? "";
-          this.error = `Создан шаблон профиля: ${path}. Заполни его и повтори.`;
-        } else if (message.startsWith("PROFILE_INVALID|")) {
-          this.error = `Профиль некорректен: ${message.split("|")[1] ?? ""}`;
-        } else if (message.startsWith("PROFILE_PROXY_TAG_MISSING|")) {
+        const code = isAppError(err) ? err.code : "";
+        const detail = isAppError(err) ? err.detail : "";
+        if (code === "PROFILE_MISSING") {
+          this.error = `Создан шаблон профиля: ${detail}. Заполни его и повтори.`;
+        } else if (code === "PROFILE_INVALID") {
+          this.error = `Профиль некорректен: ${detail}`;
+        } else if (code === "PROFILE_PROXY_TAG_MISSING") {
           this.error =
             "В профиле нет outbound с tag=proxy и нет активного профиля.";
-        } else if (message.startsWith("PROFILE_OUTBOUNDS_MISSING|")) {
+        } else if (code === "PROFILE_OUTBOUNDS_MISSING") {
           this.error = "В профиле нет outbounds. Добавь профиль и повтори.";
-        } else if (message.startsWith("SINGBOX_MISSING|")) {
-          const path = message.split("|")[1] ?? "";
-          this.error = `Не найден sing-box.exe: ${path}`;
+        } else if (code === "SINGBOX_MISSING") {
+          this.error = `Не найден sing-box.exe: ${detail}`;
+        } else if (code === "NEEDS_ELEVATION") {
+          this.error = "Для TUN нужны права администратора. Разреши доступ и повтори.";
         } else {
-          this.error = message || "Не удалось применить режим.";
+          this.error = formatError(err, "") || "Не удалось применить режим.";
         }
       } finally {
         this.busy = false;
@@ -452,7 +791,24 @@ export const useProxyStore = defineStore("proxy", {
           .map((item) => toProfileItem(item));
         this.scheduleApply();
       } catch (err) {
-        this.profileError = String(err ?? "Не удалось выбрать профиль.");
+        this.profileError = formatError(err, "Не удалось выбрать профиль.");
+      }
+    },
+    async resetToDefaults() {
+      this.profileError = null;
+      try {
+        const result = await invoke<ResetResult>("reset_to_defaults");
+        this.activeTag = result.profile.activeTag;
+        this.profiles = (result.profile.outbounds ?? [])
+          .filter((item) => item && typeof item === "object")
+          .map((item) => toProfileItem(item));
+        await this.loadSavedState();
+        await this.refreshStatus();
+        this.snapshotAppliedState();
+        return result.backup;
+      } catch (err) {
+        this.profileError = formatError(err, "Не удалось сбросить настройки.");
+        return null;
       }
     },
     async removeProfile(tag: string) {
@@ -465,7 +821,7 @@ export const useProxyStore = defineStore("proxy", {
           .map((item) => toProfileItem(item));
         this.scheduleApply();
       } catch (err) {
-        this.profileError = String(err ?? "Не удалось удалить профиль.");
+        this.profileError = formatError(err, "Не удалось удалить профиль.");
       }
     },
     async importShareLinks(links: string[]) {
@@ -483,9 +839,10 @@ export const useProxyStore = defineStore("proxy", {
         this.profileWarnings = result.errors ?? [];
         this.scheduleApply();
       } catch (err) {
-        this.profileError = String(err ?? "Не удалось импортировать ссылки.");
+        this.profileError = formatError(err, "Не удалось импортировать ссылки.");
       } finally {
         this.profileBusy = false;
+        this.importProgress = null;
       }
     },
     async importJson(payload: string) {
@@ -503,7 +860,27 @@ export const useProxyStore = defineStore("proxy", {
         this.profileWarnings = result.errors ?? [];
         this.scheduleApply();
       } catch (err) {
-        this.profileError = String(err ?? "Не удалось импортировать JSON.");
+        this.profileError = formatError(err, "Не удалось импортировать JSON.");
+      } finally {
+        this.profileBusy = false;
+      }
+    },
+    async importClashYaml(payload: string) {
+      this.profileBusy = true;
+      this.profileError = null;
+      this.profileWarnings = [];
+      try {
+        const result = await invoke<ImportResult>("import_clash_yaml", {
+          payload,
+        });
+        this.activeTag = result.profile.activeTag;
+        this.profiles = (result.profile.outbounds ?? [])
+          .filter((item) => item && typeof item === "object")
+          .map((item) => toProfileItem(item));
+        this.profileWarnings = result.errors ?? [];
+        this.scheduleApply();
+      } catch (err) {
+        this.profileError = formatError(err, "Не удалось импортировать YAML.");
       } finally {
         this.profileBusy = false;
       }